@@ -0,0 +1,493 @@
+use std::collections::BTreeMap;
+use std::ops::Not;
+
+/// `Σ coeffs[x] * x + constant`, over integer variables numbered by the caller
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linear {
+    pub coeffs: BTreeMap<usize, i128>,
+    pub constant: i128,
+}
+
+impl Linear {
+    pub fn constant(c: i128) -> Linear {
+        Linear {
+            coeffs: BTreeMap::new(),
+            constant: c,
+        }
+    }
+
+    pub fn var(x: usize) -> Linear {
+        Linear {
+            coeffs: BTreeMap::from([(x, 1)]),
+            constant: 0,
+        }
+    }
+
+    pub fn add(&self, other: &Linear) -> Linear {
+        let mut sum = self.clone();
+        for (x, c) in &other.coeffs {
+            *sum.coeffs.entry(*x).or_default() += c;
+        }
+        sum.coeffs.retain(|_, c| *c != 0);
+        sum.constant += other.constant;
+        sum
+    }
+
+    pub fn scale(&self, k: i128) -> Linear {
+        Linear {
+            coeffs: self
+                .coeffs
+                .iter()
+                .filter(|_| k != 0)
+                .map(|(x, c)| (*x, c * k))
+                .collect(),
+            constant: self.constant * k,
+        }
+    }
+
+    pub fn sub(&self, other: &Linear) -> Linear {
+        self.add(&other.scale(-1))
+    }
+}
+
+/// A quantifier-free formula of linear integer arithmetic, with boolean variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Formula {
+    Const(bool),
+    /// `l <= 0`
+    Leq(Linear),
+    /// `l == 0`
+    Eq(Linear),
+    /// A boolean variable, numbered separately from the integer ones
+    Bool(usize),
+    Not(Box<Formula>),
+    And(Vec<Formula>),
+    Or(Vec<Formula>),
+}
+
+impl Not for Formula {
+    type Output = Formula;
+
+    fn not(self) -> Formula {
+        match self {
+            Formula::Const(b) => Formula::Const(!b),
+            Formula::Not(f) => *f,
+            f => Formula::Not(Box::new(f)),
+        }
+    }
+}
+
+impl Formula {
+    pub fn and(self, other: Formula) -> Formula {
+        match (self, other) {
+            (Formula::Const(false), _) | (_, Formula::Const(false)) => Formula::Const(false),
+            (Formula::Const(true), f) | (f, Formula::Const(true)) => f,
+            (a, b) => Formula::And(vec![a, b]),
+        }
+    }
+
+    pub fn or(self, other: Formula) -> Formula {
+        match (self, other) {
+            (Formula::Const(true), _) | (_, Formula::Const(true)) => Formula::Const(true),
+            (Formula::Const(false), f) | (f, Formula::Const(false)) => f,
+            (a, b) => Formula::Or(vec![a, b]),
+        }
+    }
+
+    /// `a <= b`
+    pub fn leq(a: &Linear, b: &Linear) -> Formula {
+        Formula::Leq(a.sub(b))
+    }
+}
+
+/// The answer to whether a formula can be satisfied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sat {
+    Sat,
+    Unsat,
+    /// The numbers got too large for `i128`, or there were too many cases to look at
+    Unknown,
+}
+
+// How many conjunctions and eliminations are tried before giving up
+const MAX_STEPS: usize = 100_000;
+
+/// Whether some integers and booleans satisfy `formula`. The formula is split into conjunctions
+/// of constraints, and each one is decided with the Omega test, which is exact for integers.
+pub fn satisfiable(formula: &Formula) -> Sat {
+    let mut search = Search {
+        steps: 0,
+        overflowed: false,
+    };
+    if search.branch(vec![(formula, true)], Conjunction::default()) {
+        Sat::Sat
+    } else if search.overflowed || search.steps > MAX_STEPS {
+        Sat::Unknown
+    } else {
+        Sat::Unsat
+    }
+}
+
+// Constraints of the form `l >= 0` and `l == 0`, and boolean literals
+#[derive(Debug, Clone, Default)]
+struct Conjunction {
+    geqs: Vec<Linear>,
+    eqs: Vec<Linear>,
+    bools: BTreeMap<usize, bool>,
+}
+
+struct Search {
+    steps: usize,
+    overflowed: bool,
+}
+
+impl Search {
+    // Takes the formulas left to satisfy one at a time, each with whether it must be true or false
+    fn branch(&mut self, mut todo: Vec<(&Formula, bool)>, mut conj: Conjunction) -> bool {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return false;
+        }
+        let Some((formula, value)) = todo.pop() else {
+            return match self.omega(conj.eqs, conj.geqs) {
+                Some(sat) => sat,
+                None => {
+                    self.overflowed = true;
+                    false
+                }
+            };
+        };
+        match (formula, value) {
+            (Formula::Const(b), value) => *b == value && self.branch(todo, conj),
+            (Formula::Bool(b), value) => match conj.bools.insert(*b, value) {
+                Some(old) if old != value => false,
+                _ => self.branch(todo, conj),
+            },
+            (Formula::Not(f), value) => {
+                todo.push((f, !value));
+                self.branch(todo, conj)
+            }
+            (Formula::And(fs), true) | (Formula::Or(fs), false) => {
+                todo.extend(fs.iter().map(|f| (f, value)));
+                self.branch(todo, conj)
+            }
+            (Formula::And(fs), false) | (Formula::Or(fs), true) => fs.iter().any(|f| {
+                let mut todo = todo.clone();
+                todo.push((f, value));
+                self.branch(todo, conj.clone())
+            }),
+            // `l <= 0` is `-l >= 0`, and `l > 0` is `l - 1 >= 0`
+            (Formula::Leq(l), true) => {
+                conj.geqs.push(l.scale(-1));
+                self.branch(todo, conj)
+            }
+            (Formula::Leq(l), false) => {
+                conj.geqs.push(l.add(&Linear::constant(-1)));
+                self.branch(todo, conj)
+            }
+            (Formula::Eq(l), true) => {
+                conj.eqs.push(l.clone());
+                self.branch(todo, conj)
+            }
+            (Formula::Eq(l), false) => [
+                l.add(&Linear::constant(-1)),
+                l.scale(-1).add(&Linear::constant(-1)),
+            ]
+            .into_iter()
+            .any(|geq| {
+                let mut conj = conj.clone();
+                conj.geqs.push(geq);
+                self.branch(todo.clone(), conj)
+            }),
+        }
+    }
+
+    // Whether the equalities and inequalities have an integer solution, or `None` on overflow
+    fn omega(&mut self, mut eqs: Vec<Linear>, geqs: Vec<Linear>) -> Option<bool> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Some(false);
+        }
+        let mut geqs = match normalize_geqs(geqs)? {
+            Some(geqs) => geqs,
+            None => return Some(false),
+        };
+
+        // Equalities are solved one at a time, for a variable with a coefficient of 1 if there is
+        // one. Otherwise Pugh's "mod hat" trick makes a new equality that has one.
+        while let Some(eq) = eqs.pop() {
+            let Some(eq) = normalize_eq(eq)? else {
+                return Some(false);
+            };
+            if eq.coeffs.is_empty() {
+                continue;
+            }
+            let (&x, &a) = eq.coeffs.iter().min_by_key(|(_, a)| a.abs()).unwrap();
+            if a.abs() == 1 {
+                // `x = -(eq - a x) / a`, substituted into everything else
+                for l in eqs.iter_mut().chain(geqs.iter_mut()) {
+                    *l = eliminate(l, &eq, x, a)?;
+                }
+                continue;
+            }
+            let m = a.abs() + 1;
+            let sigma = fresh(&eqs, &geqs, &eq);
+            let mut hat = Linear::constant(mod_hat(eq.constant, m)?);
+            for (y, b) in &eq.coeffs {
+                hat.coeffs.insert(*y, mod_hat(*b, m)?);
+            }
+            hat.coeffs.insert(sigma, -m);
+            let x_coeff = hat.coeffs[&x];
+            debug_assert_eq!(x_coeff.abs(), 1);
+            let eq = eliminate(&eq, &hat, x, x_coeff)?;
+            for l in eqs.iter_mut().chain(geqs.iter_mut()) {
+                *l = eliminate(l, &hat, x, x_coeff)?;
+            }
+            eqs.push(eq);
+        }
+        let geqs = match normalize_geqs(geqs)? {
+            Some(geqs) => geqs,
+            None => return Some(false),
+        };
+        self.eliminate_geqs(geqs)
+    }
+
+    // Fourier-Motzkin elimination, with the dark and grey shadows of the Omega test when it
+    // isn't exact
+    fn eliminate_geqs(&mut self, geqs: Vec<Linear>) -> Option<bool> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Some(false);
+        }
+        let geqs = match normalize_geqs(geqs)? {
+            Some(geqs) => geqs,
+            None => return Some(false),
+        };
+        let vars: Vec<usize> = {
+            let mut vars: Vec<_> = geqs.iter().flat_map(|l| l.coeffs.keys().copied()).collect();
+            vars.sort();
+            vars.dedup();
+            vars
+        };
+        if vars.is_empty() {
+            return Some(true);
+        }
+        let bounds = |x: usize| {
+            let lower: Vec<_> = geqs
+                .iter()
+                .filter(|l| l.coeffs.get(&x).is_some_and(|a| *a > 0))
+                .collect();
+            let upper: Vec<_> = geqs
+                .iter()
+                .filter(|l| l.coeffs.get(&x).is_some_and(|a| *a < 0))
+                .collect();
+            (lower, upper)
+        };
+        // A variable bounded on one side only can always be picked far enough to satisfy them
+        for &x in &vars {
+            let (lower, upper) = bounds(x);
+            if lower.is_empty() || upper.is_empty() {
+                let rest = geqs
+                    .iter()
+                    .filter(|l| !l.coeffs.contains_key(&x))
+                    .cloned()
+                    .collect();
+                return self.eliminate_geqs(rest);
+            }
+        }
+        let exact = |x: usize| {
+            let (lower, upper) = bounds(x);
+            lower.iter().all(|l| l.coeffs[&x] == 1) || upper.iter().all(|u| u.coeffs[&x] == -1)
+        };
+        let cost = |x: usize| {
+            let (lower, upper) = bounds(x);
+            (!exact(x), lower.len() * upper.len())
+        };
+        let x = vars.iter().copied().min_by_key(|x| cost(*x)).unwrap();
+        let (lower, upper) = bounds(x);
+        let rest: Vec<Linear> = geqs
+            .iter()
+            .filter(|l| !l.coeffs.contains_key(&x))
+            .cloned()
+            .collect();
+
+        // `a x + L >= 0` and `-b x + U >= 0` give `b L + a U >= 0`, which is all that's needed when
+        // `a` or `b` is 1, and `b L + a U >= (a - 1)(b - 1)` guarantees an integer between them
+        let shadow = |dark: bool| -> Option<Vec<Linear>> {
+            let mut shadow = rest.clone();
+            for l in &lower {
+                for u in &upper {
+                    let (a, b) = (l.coeffs[&x], -u.coeffs[&x]);
+                    let mut combined = checked_add(&checked_scale(l, b)?, &checked_scale(u, a)?)?;
+                    combined.coeffs.remove(&x);
+                    if dark {
+                        let gap = (a - 1).checked_mul(b - 1)?;
+                        combined.constant = combined.constant.checked_sub(gap)?;
+                    }
+                    shadow.push(combined);
+                }
+            }
+            Some(shadow)
+        };
+        if exact(x) {
+            return self.eliminate_geqs(shadow(false)?);
+        }
+        if !self.eliminate_geqs(shadow(false)?)? {
+            return Some(false);
+        }
+        if self.eliminate_geqs(shadow(true)?)? {
+            return Some(true);
+        }
+        // Otherwise any solution is close to one of the lower bounds
+        let a_max = upper.iter().map(|u| -u.coeffs[&x]).max().unwrap();
+        for l in &lower {
+            let a = l.coeffs[&x];
+            let max_i = (a_max.checked_mul(a)? - a_max - a) / a_max;
+            for i in 0..=max_i {
+                let eq = l.add(&Linear::constant(-i));
+                if self.omega(vec![eq], geqs.clone())? {
+                    return Some(true);
+                }
+            }
+        }
+        Some(false)
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    match b {
+        0 => a.abs(),
+        _ => gcd(b, a % b),
+    }
+}
+
+// Divides by the gcd of the coefficients, `None` if the equality has no integer solution
+fn normalize_eq(mut l: Linear) -> Option<Option<Linear>> {
+    let g = l.coeffs.values().fold(0, |g, a| gcd(g, *a));
+    if g == 0 {
+        return Some((l.constant == 0).then_some(l));
+    }
+    if l.constant % g != 0 {
+        return Some(None);
+    }
+    l.coeffs.values_mut().for_each(|a| *a /= g);
+    l.constant /= g;
+    Some(Some(l))
+}
+
+// Divides by the gcd of the coefficients, rounding the constant down, and drops the inequalities
+// without variables. `Some(None)` if one of those doesn't hold.
+fn normalize_geqs(geqs: Vec<Linear>) -> Option<Option<Vec<Linear>>> {
+    let mut normalized = Vec::new();
+    for mut l in geqs {
+        let g = l.coeffs.values().fold(0, |g, a| gcd(g, *a));
+        if g == 0 {
+            if l.constant < 0 {
+                return Some(None);
+            }
+            continue;
+        }
+        l.coeffs.values_mut().for_each(|a| *a /= g);
+        l.constant = l.constant.div_euclid(g);
+        if !normalized.contains(&l) {
+            normalized.push(l);
+        }
+    }
+    Some(Some(normalized))
+}
+
+// Uses `eq`, where `x` has the coefficient `a` of 1 or -1, to remove `x` from `l`
+fn eliminate(l: &Linear, eq: &Linear, x: usize, a: i128) -> Option<Linear> {
+    let k = l.coeffs.get(&x).copied().unwrap_or(0);
+    if k == 0 {
+        return Some(l.clone());
+    }
+    let mut result = checked_add(l, &checked_scale(eq, -k.checked_mul(a)?)?)?;
+    result.coeffs.remove(&x);
+    Some(result)
+}
+
+// `a - m * floor(a / m + 1 / 2)`, the remainder of `a` closest to 0
+fn mod_hat(a: i128, m: i128) -> Option<i128> {
+    let q = (a.checked_mul(2)?.checked_add(m)?).div_euclid(m.checked_mul(2)?);
+    a.checked_sub(m.checked_mul(q)?)
+}
+
+// A variable that isn't used in any of the constraints
+fn fresh(eqs: &[Linear], geqs: &[Linear], eq: &Linear) -> usize {
+    eqs.iter()
+        .chain(geqs)
+        .chain([eq])
+        .flat_map(|l| l.coeffs.keys().copied())
+        .max()
+        .map_or(0, |x| x + 1)
+}
+
+fn checked_scale(l: &Linear, k: i128) -> Option<Linear> {
+    let mut coeffs = BTreeMap::new();
+    for (x, a) in &l.coeffs {
+        coeffs.insert(*x, a.checked_mul(k)?);
+    }
+    Some(Linear {
+        coeffs,
+        constant: l.constant.checked_mul(k)?,
+    })
+}
+
+fn checked_add(a: &Linear, b: &Linear) -> Option<Linear> {
+    let mut sum = a.clone();
+    for (x, c) in &b.coeffs {
+        let entry = sum.coeffs.entry(*x).or_default();
+        *entry = entry.checked_add(*c)?;
+    }
+    sum.coeffs.retain(|_, c| *c != 0);
+    sum.constant = sum.constant.checked_add(b.constant)?;
+    Some(sum)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Not;
+
+    use super::{satisfiable, Formula, Linear, Sat};
+
+    fn var(x: usize, c: i128) -> Linear {
+        Linear::var(x).scale(c)
+    }
+
+    #[test]
+    fn test_omega() {
+        let (x, y) = (Linear::var(0), Linear::var(1));
+        let c = Linear::constant;
+        // 2x == 1 has no integer solution, even though it has a rational one
+        assert_eq!(satisfiable(&Formula::Eq(var(0, 2).sub(&c(1)))), Sat::Unsat);
+        // 3 <= 2x <= 3 has no integer solution either
+        let between = Formula::leq(&c(3), &var(0, 2)).and(Formula::leq(&var(0, 2), &c(3)));
+        assert_eq!(satisfiable(&between), Sat::Unsat);
+        // 27 <= 11x + 13y <= 45 and -10 <= 7x - 9y <= 4, from Pugh's paper
+        let sum = var(0, 11).add(&var(1, 13));
+        let diff = var(0, 7).sub(&var(1, 9));
+        let pugh = Formula::And(vec![
+            Formula::leq(&c(27), &sum),
+            Formula::leq(&sum, &c(45)),
+            Formula::leq(&c(-10), &diff),
+            Formula::leq(&diff, &c(4)),
+        ]);
+        assert_eq!(satisfiable(&pugh), Sat::Unsat);
+        // x == y + 1 and x <= y or x == 5 and y == 5
+        let cases = Formula::Eq(x.sub(&y).sub(&c(1)))
+            .and(Formula::leq(&x, &y).or(Formula::Eq(x.sub(&c(5))).and(Formula::Eq(y.sub(&c(5))))));
+        assert_eq!(satisfiable(&cases), Sat::Unsat);
+        let cases = Formula::Eq(x.sub(&y).sub(&c(1))).and(Formula::Eq(x.sub(&c(5))).not());
+        assert_eq!(satisfiable(&cases), Sat::Sat);
+        // 3x + 5y == 1 has solutions, like x = 2 and y = -1
+        assert_eq!(
+            satisfiable(&Formula::Eq(var(0, 3).add(&var(1, 5)).sub(&c(1)))),
+            Sat::Sat
+        );
+        assert_eq!(
+            satisfiable(&Formula::Bool(0).and(Formula::Bool(0).not())),
+            Sat::Unsat
+        );
+    }
+}
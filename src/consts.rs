@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::ImpParseError;
+use crate::evaluator::{self, Value};
+use crate::syntax::{Constant, Expr, Ident, Statement};
+
+/// Computes the value of every `const` in `program`, and replaces every read of a constant with its
+/// value. `defines` overrides the values of the program's constants.
+pub fn inline(
+    program: Statement,
+    defines: &HashMap<Ident, Constant>,
+) -> Result<Statement, ImpParseError> {
+    let mut folder = Folder {
+        defines,
+        values: HashMap::new(),
+    };
+    let program = folder.fold_stmnt(program, true)?;
+
+    let declared: HashSet<&Ident> = folder.values.keys().collect();
+    if let Some(name) = defines.keys().find(|name| !declared.contains(name)) {
+        return Err(ImpParseError::Other(format!(
+            "No constant named '{}' to override",
+            name
+        )));
+    }
+    Ok(program)
+}
+
+struct Folder<'a> {
+    defines: &'a HashMap<Ident, Constant>,
+    // The expression every constant declared so far is replaced with
+    values: HashMap<Ident, Expr>,
+}
+
+impl Folder<'_> {
+    fn fold_stmnt(
+        &mut self,
+        stmnt: Statement,
+        top_level: bool,
+    ) -> Result<Statement, ImpParseError> {
+        let boxed = |folder: &mut Self, s: Box<Statement>| -> Result<_, ImpParseError> {
+            Ok(Box::new(folder.fold_stmnt(*s, false)?))
+        };
+        Ok(match stmnt {
+            Statement::Const(id, expr) => {
+                if !top_level {
+                    return Err(ImpParseError::Other(format!(
+                        "Constant '{}' must be declared at the top level",
+                        id
+                    )));
+                }
+                if self.values.contains_key(&id) {
+                    return Err(ImpParseError::Other(format!(
+                        "Constant '{}' is declared twice",
+                        id
+                    )));
+                }
+                let value = match self.defines.get(&id) {
+                    Some(c) => Expr::Constant(c.clone()),
+                    None => self.evaluate(&id, expr)?,
+                };
+                self.values.insert(id.clone(), value.clone());
+                Statement::Const(id, value)
+            }
+            // An enum is declared for the rest of the program, whichever branches ran before
+            Statement::Enum(name, variants) => {
+                if !top_level {
+                    return Err(ImpParseError::Other(format!(
+                        "Enum '{}' must be declared at the top level",
+                        name
+                    )));
+                }
+                Statement::Enum(name, variants)
+            }
+            Statement::StoreAssign(id, expr) => Statement::StoreAssign(id, self.fold_expr(expr)),
+            Statement::Destructure(ids, expr) => Statement::Destructure(ids, self.fold_expr(expr)),
+            Statement::HeapNew(id, expr) => Statement::HeapNew(id, self.fold_expr(expr)),
+            Statement::HeapUpdate(id, expr) => Statement::HeapUpdate(id, self.fold_expr(expr)),
+            Statement::Sequence(s1, s2) => Statement::Sequence(
+                Box::new(self.fold_stmnt(*s1, top_level)?),
+                Box::new(self.fold_stmnt(*s2, top_level)?),
+            ),
+            Statement::Conditional(cond, then_s, else_s) => Statement::Conditional(
+                self.fold_expr(cond),
+                boxed(self, then_s)?,
+                boxed(self, else_s)?,
+            ),
+            Statement::While(cond, body) => {
+                Statement::While(self.fold_expr(cond), boxed(self, body)?)
+            }
+            Statement::Scope(body) => Statement::Scope(boxed(self, body)?),
+            Statement::Labeled(level, stmnt) => Statement::Labeled(level, boxed(self, stmnt)?),
+            Statement::Choose(s1, s2) => Statement::Choose(boxed(self, s1)?, boxed(self, s2)?),
+            Statement::Parallel(s1, s2) => Statement::Parallel(boxed(self, s1)?, boxed(self, s2)?),
+            Statement::Print(expr) => Statement::Print(self.fold_expr(expr)),
+            Statement::Assert(cond, message) => {
+                Statement::Assert(self.fold_expr(cond), message.map(|m| self.fold_expr(m)))
+            }
+            Statement::Assume(cond) => Statement::Assume(self.fold_expr(cond)),
+            Statement::Contract(clause, cond) => Statement::Contract(clause, self.fold_expr(cond)),
+            Statement::Match(expr, cases) => Statement::Match(
+                self.fold_expr(expr),
+                cases
+                    .into_iter()
+                    .map(|(pattern, body)| Ok((pattern, self.fold_stmnt(body, false)?)))
+                    .collect::<Result<_, ImpParseError>>()?,
+            ),
+            stmnt => stmnt,
+        })
+    }
+
+    fn fold_expr(&self, expr: Expr) -> Expr {
+        let fold = |e: Box<Expr>| Box::new(self.fold_expr(*e));
+        match expr {
+            Expr::StoreRead(x) => match self.values.get(&x) {
+                Some(value) => value.clone(),
+                None => Expr::StoreRead(x),
+            },
+            Expr::NatAdd(a, b) => Expr::NatAdd(fold(a), fold(b)),
+            Expr::NatSub(a, b) => Expr::NatSub(fold(a), fold(b)),
+            Expr::NatLeq(a, b) => Expr::NatLeq(fold(a), fold(b)),
+            Expr::IntNeg(a) => Expr::IntNeg(fold(a)),
+            Expr::ToInt(a) => Expr::ToInt(fold(a)),
+            Expr::ToNat(a) => Expr::ToNat(fold(a)),
+            Expr::BoolAnd(a, b) => Expr::BoolAnd(fold(a), fold(b)),
+            Expr::BoolNot(a) => Expr::BoolNot(fold(a)),
+            Expr::StrConcat(a, b) => Expr::StrConcat(fold(a), fold(b)),
+            Expr::StrLen(a) => Expr::StrLen(fold(a)),
+            Expr::Equal(a, b) => Expr::Equal(fold(a), fold(b)),
+            Expr::Tuple(components) => {
+                Expr::Tuple(components.into_iter().map(|e| self.fold_expr(e)).collect())
+            }
+            Expr::Construct(name, variant, args) => Expr::Construct(
+                name,
+                variant,
+                args.into_iter().map(|e| self.fold_expr(e)).collect(),
+            ),
+            Expr::HeapRead(_) | Expr::Constant(_) => expr,
+        }
+    }
+
+    // Constants can only be computed from literals and other constants. If the expression doesn't
+    // evaluate, it's left as it is for the typechecker to report.
+    fn evaluate(&self, id: &Ident, expr: Expr) -> Result<Expr, ImpParseError> {
+        let expr = self.fold_expr(expr);
+        if reads_variable(&expr) {
+            return Err(ImpParseError::NotConstant(id.clone()));
+        }
+        Ok(
+            match evaluator::eval_expr(&expr, &HashMap::new(), &Vec::new()) {
+                Ok(Value::Number(n)) => Expr::Constant(Constant::Nat(n)),
+                Ok(Value::Int(i)) => Expr::Constant(Constant::Int(i)),
+                Ok(Value::Bool(b)) => Expr::Constant(Constant::Bool(b)),
+                Ok(Value::Str(s)) => Expr::Constant(Constant::Str(s)),
+                _ => expr,
+            },
+        )
+    }
+}
+
+fn reads_variable(expr: &Expr) -> bool {
+    match expr {
+        Expr::StoreRead(_) | Expr::HeapRead(_) => true,
+        Expr::Constant(_) => false,
+        Expr::NatAdd(a, b)
+        | Expr::NatSub(a, b)
+        | Expr::NatLeq(a, b)
+        | Expr::BoolAnd(a, b)
+        | Expr::StrConcat(a, b)
+        | Expr::Equal(a, b) => reads_variable(a) || reads_variable(b),
+        Expr::BoolNot(a) | Expr::IntNeg(a) | Expr::ToInt(a) | Expr::ToNat(a) | Expr::StrLen(a) => {
+            reads_variable(a)
+        }
+        Expr::Tuple(components) | Expr::Construct(_, _, components) => {
+            components.iter().any(reads_variable)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::error::ImpParseError;
+    use crate::parser::parse;
+    use crate::syntax::{Constant::*, Expr::*, Statement::*};
+
+    #[test]
+    fn test_inline_consts() {
+        let program = parse("const A = 2 + 3\nconst B = A + 1\nlet x = B").unwrap();
+        let inlined = super::inline(program, &HashMap::new()).unwrap();
+        let expected = Sequence(
+            Box::new(Sequence(
+                Box::new(Sequence(
+                    Box::new(Skip),
+                    Box::new(Const("A".into(), Constant(Nat(5)))),
+                )),
+                Box::new(Const("B".into(), Constant(Nat(6)))),
+            )),
+            Box::new(StoreAssign("x".into(), Constant(Nat(6)))),
+        );
+        assert_eq!(inlined, expected);
+
+        // Overriding a constant also changes the constants computed from it
+        let program = parse("const A = 2 + 3\nconst B = A + 1\nlet x = B").unwrap();
+        let defines = HashMap::from([("A".to_string(), Nat(10))]);
+        let inlined = super::inline(program, &defines).unwrap();
+        assert!(
+            matches!(inlined, Sequence(_, s) if *s == StoreAssign("x".into(), Constant(Nat(11))))
+        );
+    }
+
+    #[test]
+    fn test_not_constant() {
+        let program = parse("let x = 1\nconst A = x + 1").unwrap();
+        let result = super::inline(program, &HashMap::new());
+        assert!(matches!(result, Err(ImpParseError::NotConstant(id)) if id == "A"));
+
+        let program = parse("if true then\nconst A = 1\nelse\nskip\nfi").unwrap();
+        assert!(super::inline(program, &HashMap::new()).is_err());
+
+        let program = parse("const A = 1").unwrap();
+        let defines = HashMap::from([("B".to_string(), Nat(10))]);
+        assert!(super::inline(program, &defines).is_err());
+    }
+
+    #[test]
+    fn test_nested_enum() {
+        let program = parse("enum E = A\nwhile true do\nskip\nend").unwrap();
+        assert!(super::inline(program, &HashMap::new()).is_ok());
+
+        let program = parse("if true then\nenum E = A\nelse\nenum E = A\nfi").unwrap();
+        assert!(super::inline(program, &HashMap::new()).is_err());
+
+        let program = parse("do\nenum E = A\nend").unwrap();
+        assert!(super::inline(program, &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use std::fmt;
+
+use crate::syntax::{Constant, Expr, Ident, Statement};
+use crate::typechecker::Type;
+pub use crate::typed::Context;
+use crate::typed::{ExprKind, StmntKind, TypedExpr, TypedStatement};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Judgment {
+    /// `Σ ⊢ e : τ`
+    Expr(Context, Expr, Type),
+    /// `Σ, s ⇓ Σ'`, where `Σ'` is the context after `s`
+    Stmnt(Context, Statement, Context),
+    /// `Σ(x) = τ`, a side condition that holds by looking `x` up in the context
+    Lookup(Ident, Type),
+}
+
+/// A typing derivation, showing how the typechecker concluded a judgment from its premises
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Derivation {
+    /// The typing rule, like `T-Conditional`, or `None` for a lookup
+    pub rule: Option<&'static str>,
+    pub judgment: Judgment,
+    pub premises: Vec<Derivation>,
+}
+
+/// The derivation of the typing judgment of a typed statement, read off the types and contexts
+/// the typechecker recorded. The rules are named after the ones in `typing_rules.pdf`.
+pub(crate) fn derive(stmnt: &TypedStatement) -> Derivation {
+    let before = &stmnt.before;
+    let expr = |e| derive_expr(before, e);
+    let location = |id: &Ident| lookup(id, before[id].clone());
+    let premises = match &stmnt.kind {
+        StmntKind::StoreAssign(_, e)
+        | StmntKind::HeapNew(_, e)
+        | StmntKind::Destructure(_, e)
+        | StmntKind::Print(e)
+        | StmntKind::Assume(e)
+        | StmntKind::Contract(_, e)
+        | StmntKind::Const(_, e) => vec![expr(e)],
+        StmntKind::HeapUpdate(id, e) => vec![location(id), expr(e)],
+        StmntKind::HeapAlias(_, id) | StmntKind::Borrow(_, id, _) | StmntKind::Havoc(id) => {
+            vec![location(id)]
+        }
+        StmntKind::AddressOf(_, x) => vec![location(&x.name)],
+        StmntKind::Assert(cond, message) => {
+            std::iter::once(cond).chain(message).map(expr).collect()
+        }
+        StmntKind::Sequence(s1, s2) => vec![derive(s1), derive(s2)],
+        StmntKind::Conditional(cond, then_s, else_s) => {
+            vec![expr(cond), derive(then_s), derive(else_s)]
+        }
+        // The condition is checked in the context at the head of the loop, like the body
+        StmntKind::While(cond, body) => vec![derive_expr(&body.before, cond), derive(body)],
+        StmntKind::Scope(body) | StmntKind::Labeled(_, body) | StmntKind::Module(_, _, body) => {
+            vec![derive(body)]
+        }
+        StmntKind::Choose(s1, s2) | StmntKind::Parallel(s1, s2) => vec![derive(s1), derive(s2)],
+        // Each case starts from the context with the fields of its pattern bound
+        StmntKind::Match(e, cases) => std::iter::once(expr(e))
+            .chain(cases.iter().map(|(_, body)| derive(body)))
+            .collect(),
+        StmntKind::StoreInput(_)
+        | StmntKind::HeapInput(_)
+        | StmntKind::Enum(_, _)
+        | StmntKind::Skip => Vec::new(),
+    };
+    Derivation {
+        rule: Some(stmnt_rule(&stmnt.kind)),
+        judgment: Judgment::Stmnt(before.clone(), Statement::from(stmnt), stmnt.after.clone()),
+        premises,
+    }
+}
+
+fn lookup(id: &Ident, ty: Type) -> Derivation {
+    Derivation {
+        rule: None,
+        judgment: Judgment::Lookup(id.clone(), ty),
+        premises: Vec::new(),
+    }
+}
+
+fn stmnt_rule(stmnt: &StmntKind) -> &'static str {
+    match stmnt {
+        StmntKind::StoreAssign(_, _) => "T-StoreAss",
+        StmntKind::HeapNew(_, _) => "T-HeapNew",
+        StmntKind::HeapUpdate(_, _) => "T-HeapAssign",
+        StmntKind::HeapAlias(_, _) => "T-HeapAlias",
+        StmntKind::Borrow(_, _, _) => "T-Borrow",
+        StmntKind::AddressOf(_, _) => "T-AddressOf",
+        StmntKind::Destructure(_, _) => "T-Destructure",
+        StmntKind::Sequence(_, _) => "T-Sequence",
+        StmntKind::Conditional(_, _, _) => "T-Conditional",
+        StmntKind::While(_, _) => "T-While",
+        StmntKind::Scope(_) => "T-Scope",
+        StmntKind::Choose(_, _) => "T-Choose",
+        StmntKind::Havoc(_) => "T-Havoc",
+        StmntKind::Parallel(_, _) => "T-Par",
+        StmntKind::Print(_) => "T-Print",
+        StmntKind::StoreInput(_) => "T-StoreInput",
+        StmntKind::HeapInput(_) => "T-HeapInput",
+        StmntKind::Assert(_, _) => "T-Assert",
+        StmntKind::Assume(_) => "T-Assume",
+        StmntKind::Contract(_, _) => "T-Contract",
+        StmntKind::Labeled(_, _) => "T-Labeled",
+        StmntKind::Const(_, _) => "T-Const",
+        StmntKind::Enum(_, _) => "T-Enum",
+        StmntKind::Match(_, _) => "T-Match",
+        StmntKind::Module(_, _, _) => "T-Module",
+        StmntKind::Skip => "T-Skip",
+    }
+}
+
+fn derive_expr(context: &Context, expr: &TypedExpr) -> Derivation {
+    let sub = |e| derive_expr(context, e);
+    let ty = expr.ty.clone();
+    let (rule, premises) = match &expr.kind {
+        ExprKind::StoreRead(x) => ("T-StoreR", vec![lookup(x, ty.clone())]),
+        ExprKind::HeapRead(x) => ("T-HeapR", vec![lookup(x, Type::location(ty.clone()))]),
+        ExprKind::Constant(Constant::Nat(_)) => ("T-Num", Vec::new()),
+        ExprKind::Constant(Constant::Int(_)) => ("T-Int", Vec::new()),
+        ExprKind::Constant(Constant::Bool(_)) => ("T-Bool", Vec::new()),
+        ExprKind::Constant(Constant::Str(_)) => ("T-Str", Vec::new()),
+        ExprKind::NatAdd(a, b) => ("T-Add", vec![sub(a), sub(b)]),
+        ExprKind::NatSub(a, b) => ("T-Sub", vec![sub(a), sub(b)]),
+        ExprKind::NatLeq(a, b) => ("T-Leq", vec![sub(a), sub(b)]),
+        ExprKind::BoolAnd(a, b) => ("T-And", vec![sub(a), sub(b)]),
+        ExprKind::StrConcat(a, b) => ("T-Concat", vec![sub(a), sub(b)]),
+        ExprKind::Equal(a, b) => ("T-Eq", vec![sub(a), sub(b)]),
+        ExprKind::IntNeg(a) => ("T-IntNeg", vec![sub(a)]),
+        ExprKind::ToInt(a) => ("T-ToInt", vec![sub(a)]),
+        ExprKind::ToNat(a) => ("T-ToNat", vec![sub(a)]),
+        ExprKind::BoolNot(a) => ("T-Neg", vec![sub(a)]),
+        ExprKind::StrLen(a) => ("T-Len", vec![sub(a)]),
+        ExprKind::Tuple(components) => ("T-Tuple", components.iter().map(sub).collect()),
+        // Fields holding a location are given a variable, which is looked up rather than read
+        ExprKind::Construct(_, _, args) => {
+            let premises = args
+                .iter()
+                .map(|arg| match (&arg.ty, &arg.kind) {
+                    (Type::Location(_), ExprKind::StoreRead(x)) => lookup(x, arg.ty.clone()),
+                    _ => sub(arg),
+                })
+                .collect();
+            ("T-Construct", premises)
+        }
+    };
+    Derivation {
+        rule: Some(rule),
+        judgment: Judgment::Expr(context.clone(), Expr::from(expr), ty),
+        premises,
+    }
+}
+
+fn write_context(f: &mut fmt::Formatter<'_>, context: &Context) -> fmt::Result {
+    if context.is_empty() {
+        return write!(f, "∅");
+    }
+    let bindings: Vec<_> = context
+        .iter()
+        .map(|(k, ty)| format!("{}: {}", k, ty))
+        .collect();
+    write!(f, "{{{}}}", bindings.join(", "))
+}
+
+impl fmt::Display for Judgment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Judgment::Expr(context, expr, ty) => {
+                write_context(f, context)?;
+                write!(f, " ⊢ {} : {}", expr, ty)
+            }
+            Judgment::Stmnt(before, stmnt, after) => {
+                write_context(f, before)?;
+                write!(f, ", {} ⇓ ", stmnt)?;
+                write_context(f, after)
+            }
+            Judgment::Lookup(id, ty) => write!(f, "Σ({}) = {}", id, ty),
+        }
+    }
+}
+
+impl Derivation {
+    fn write_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self.rule {
+            Some(rule) => writeln!(f, "{}{}: {}", indent, rule, self.judgment)?,
+            None => writeln!(f, "{}{}", indent, self.judgment)?,
+        }
+        self.premises
+            .iter()
+            .try_for_each(|premise| premise.write_indented(f, depth + 1))
+    }
+
+    /// The derivation as a `bussproofs` proof tree. Rules with more than five premises, the most
+    /// `bussproofs` can draw, only show the first four, followed by `⋯`.
+    pub fn to_latex(&self) -> String {
+        let mut latex = String::from("\\begin{prooftree}\n");
+        self.write_latex(&mut latex);
+        latex.push_str("\\end{prooftree}\n");
+        latex
+    }
+
+    fn write_latex(&self, latex: &mut String) {
+        let judgment = latex_judgment(&self.judgment);
+        let rule = match self.rule {
+            Some(rule) => rule,
+            None => {
+                latex.push_str(&format!("\\AxiomC{{${}$}}\n", judgment));
+                return;
+            }
+        };
+        let premises = match self.premises.len() {
+            0 => {
+                latex.push_str("\\AxiomC{}\n");
+                1
+            }
+            n @ 1..=5 => {
+                self.premises.iter().for_each(|p| p.write_latex(latex));
+                n
+            }
+            _ => {
+                self.premises[..4].iter().for_each(|p| p.write_latex(latex));
+                latex.push_str("\\AxiomC{$\\cdots$}\n");
+                5
+            }
+        };
+        let inference = ["Unary", "Binary", "Trinary", "Quaternary", "Quinary"][premises - 1];
+        latex.push_str(&format!("\\RightLabel{{\\scriptsize {}}}\n", rule));
+        latex.push_str(&format!("\\{}InfC{{${}$}}\n", inference, judgment));
+    }
+}
+
+// Shows the derivation as an indented tree, with the premises of each judgment below it
+impl fmt::Display for Derivation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+// Source code, names and types are written in a typewriter font
+fn texttt(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    format!("\\texttt{{{}}}", escaped)
+}
+
+fn latex_context(context: &Context) -> String {
+    if context.is_empty() {
+        return "\\emptyset".to_owned();
+    }
+    let bindings: Vec<_> = context
+        .iter()
+        .map(|(k, ty)| format!("{} : {}", texttt(k), texttt(&ty.to_string())))
+        .collect();
+    format!("\\{{{}\\}}", bindings.join(", "))
+}
+
+fn latex_judgment(judgment: &Judgment) -> String {
+    match judgment {
+        Judgment::Expr(context, expr, ty) => format!(
+            "{} \\vdash {} : {}",
+            latex_context(context),
+            texttt(&expr.to_string()),
+            texttt(&ty.to_string())
+        ),
+        Judgment::Stmnt(before, stmnt, after) => format!(
+            "{}, {} \\Downarrow {}",
+            latex_context(before),
+            texttt(&stmnt.to_string()),
+            latex_context(after)
+        ),
+        Judgment::Lookup(id, ty) => {
+            format!("\\Sigma({}) = {}", texttt(id), texttt(&ty.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::Judgment;
+    use crate::error::TypeError;
+    use crate::parser::parse;
+    use crate::typechecker::{typecheck, typecheck_derived, Type};
+
+    #[test]
+    fn test_derivation() {
+        let program = parse("let x <- 1\nif *x < 2 then\nx <- *x + 1\nelse\nskip\nfi").unwrap();
+        let mut sigma = HashMap::new();
+        let derivation = typecheck_derived(&mut sigma, &program).unwrap();
+        assert_eq!(
+            sigma,
+            HashMap::from([("x".to_owned(), Type::location(Type::Number))])
+        );
+
+        let expected = "\
+T-Sequence: ∅, skip; let x <- 1; if *x < 2 then skip; x <- *x + 1 else skip; skip fi ⇓ {x: ref nat}
+  T-Sequence: ∅, skip; let x <- 1 ⇓ {x: ref nat}
+    T-Skip: ∅, skip ⇓ ∅
+    T-HeapNew: ∅, let x <- 1 ⇓ {x: ref nat}
+      T-Num: ∅ ⊢ 1 : nat
+  T-Conditional: {x: ref nat}, if *x < 2 then skip; x <- *x + 1 else skip; skip fi ⇓ {x: ref nat}
+    T-Leq: {x: ref nat} ⊢ *x < 2 : bool
+      T-HeapR: {x: ref nat} ⊢ *x : nat
+        Σ(x) = ref nat
+      T-Num: {x: ref nat} ⊢ 2 : nat
+    T-Sequence: {x: ref nat}, skip; x <- *x + 1 ⇓ {x: ref nat}
+      T-Skip: {x: ref nat}, skip ⇓ {x: ref nat}
+      T-HeapAssign: {x: ref nat}, x <- *x + 1 ⇓ {x: ref nat}
+        Σ(x) = ref nat
+        T-Add: {x: ref nat} ⊢ *x + 1 : nat
+          T-HeapR: {x: ref nat} ⊢ *x : nat
+            Σ(x) = ref nat
+          T-Num: {x: ref nat} ⊢ 1 : nat
+    T-Sequence: {x: ref nat}, skip; skip ⇓ {x: ref nat}
+      T-Skip: {x: ref nat}, skip ⇓ {x: ref nat}
+      T-Skip: {x: ref nat}, skip ⇓ {x: ref nat}
+";
+        assert_eq!(derivation.to_string(), expected);
+
+        // Only the variables bound by both branches are in the context afterwards
+        let program = parse("if true then\nlet y = 1\nelse\nlet z = 2\nfi").unwrap();
+        let derivation = typecheck_derived(&mut HashMap::new(), &program).unwrap();
+        let conditional = &derivation.premises[1];
+        assert_eq!(conditional.rule, Some("T-Conditional"));
+        assert!(
+            matches!(&conditional.premises[1].judgment, Judgment::Stmnt(_, _, after) if after.contains_key("y"))
+        );
+        assert!(matches!(&conditional.judgment, Judgment::Stmnt(_, _, after) if after.is_empty()));
+
+        // Programs that don't typecheck fail with the typechecker's error
+        let program = parse("let x = 1\nwhile true do\nlet x = \"a\"\nend").unwrap();
+        let error = typecheck_derived(&mut HashMap::new(), &program).unwrap_err();
+        assert!(matches!(error, TypeError::Mismatch { .. }));
+        assert_eq!(
+            format!("{:?}", error),
+            format!("{:?}", typecheck(&program).unwrap_err())
+        );
+    }
+
+    #[test]
+    fn test_latex() {
+        let program = parse("let s = \"a_b\" ++ \"c\"").unwrap();
+        let latex = typecheck_derived(&mut HashMap::new(), &program)
+            .unwrap()
+            .to_latex();
+        let expected = r#"\begin{prooftree}
+\AxiomC{}
+\RightLabel{\scriptsize T-Skip}
+\UnaryInfC{$\emptyset, \texttt{skip} \Downarrow \emptyset$}
+\AxiomC{}
+\RightLabel{\scriptsize T-Str}
+\UnaryInfC{$\emptyset \vdash \texttt{"a\_b"} : \texttt{str}$}
+\AxiomC{}
+\RightLabel{\scriptsize T-Str}
+\UnaryInfC{$\emptyset \vdash \texttt{"c"} : \texttt{str}$}
+\RightLabel{\scriptsize T-Concat}
+\BinaryInfC{$\emptyset \vdash \texttt{"a\_b" ++ "c"} : \texttt{str}$}
+\RightLabel{\scriptsize T-StoreAss}
+\UnaryInfC{$\emptyset, \texttt{let s = "a\_b" ++ "c"} \Downarrow \{\texttt{s} : \texttt{str}\}$}
+\RightLabel{\scriptsize T-Sequence}
+\BinaryInfC{$\emptyset, \texttt{skip; let s = "a\_b" ++ "c"} \Downarrow \{\texttt{s} : \texttt{str}\}$}
+\end{prooftree}
+"#;
+        assert_eq!(latex, expected);
+    }
+}
@@ -1,3 +1,7 @@
+use std::fmt;
+
+use crate::evaluator::Sigma;
+use crate::syntax::{Expr, Ident};
 use crate::typechecker::Type;
 
 pub type EvalResult<T> = std::result::Result<T, EvalError>;
@@ -5,19 +9,152 @@ pub type EvalResult<T> = std::result::Result<T, EvalError>;
 #[derive(Debug)]
 pub enum EvalError {
     UnboundVariable,
-    TypeMismatch { expected: Type, got: Type },
+    TypeMismatch {
+        expected: Type,
+        got: Type,
+    },
     BoundTypeMismatch,
+    /// A value that can't be bound in the store or saved on the heap, like a `bool`
+    NotStorable(Type),
+    /// A location that isn't on the heap
     InvalidDereference,
+    InputExhausted,
+    InvalidInput(String),
+    /// A negative int converted to a nat
+    Underflow(i64),
+    /// A nat too large to be converted to an int
+    Overflow(u64),
+    /// A `match` on a value that isn't a variant of an enum
+    NotAnEnum(Type),
+    /// A `match` without a case for the variant it was given
+    NoMatchingCase(String),
+    /// A pattern with the wrong number of fields for the variant it matched, written
+    /// `Enum::Variant`
+    Arity {
+        variant: String,
+        expected: usize,
+        got: usize,
+    },
+    /// A destructuring `let` with this many names, given a value that isn't a tuple with as many
+    /// components
+    TupleArity {
+        expected: usize,
+        got: Type,
+    },
+    /// An `assert` whose condition was false, along with the store at that point
+    AssertionFailed {
+        expr: Box<Expr>,
+        message: Option<String>,
+        store: Sigma,
+    },
+    /// An `assume` whose condition was false. This isn't a fault in the program, it just means the
+    /// execution isn't one we care about.
+    Pruned,
+    UnresolvedImport(String),
+    /// Both branches of a `par` accessed the same heap location, and at least one of them wrote to
+    /// it. `variable` is the one the second access went through.
+    DataRace {
+        location: usize,
+        variable: Ident,
+    },
 }
 
 #[derive(Debug)]
 pub enum TypeError {
-    Mismatch { expected: Type, got: Type },
+    Mismatch {
+        expected: Type,
+        got: Type,
+    },
+    /// A value that can't be bound in the store or saved on the heap, like a `bool`
+    NotStorable(Type),
     UnboundVariable,
+    /// An `import` that was never loaded, see `module::load`
+    UnresolvedImport(String),
+    /// A module imported with the same name as one that was already imported
+    DuplicateModule(String),
+    /// A variable bound with the same name as a constant
+    ConstReassignment(String),
+    UnknownEnum(String),
+    /// A `match` on a value that isn't of an enum type
+    NotAnEnum(Type),
+    /// An enum declared twice, or with two variants of the same name
+    DuplicateEnum(String),
+    /// A variant that isn't part of its enum, written `Enum::Variant`
+    UnknownVariant(String),
+    /// A constructor or pattern with the wrong number of fields for its variant
+    Arity {
+        variant: String,
+        expected: usize,
+        got: usize,
+    },
+    /// A destructuring `let` with this many names, given a value that isn't a tuple with as many
+    /// components
+    TupleArity {
+        expected: usize,
+        got: Type,
+    },
+    /// A `match` without a case for each of these variants
+    NonExhaustiveMatch(Vec<String>),
+    /// A variable read after the branches of an `if`, `choose` or `match` that didn't all bind it
+    /// with the same type. Has the type each branch bound it with and the statement that did,
+    /// written like in the source, or `None` for a branch that didn't bind it.
+    Dropped {
+        name: String,
+        branches: Vec<Option<(Type, String)>>,
+    },
+    /// A variable that had its address taken inside of a block, when it was bound outside of it
+    AddressTaken(String),
+    /// A `while` whose body kept changing the context at the loop head after this many checks
+    LoopDiverges(usize),
+    /// A heap location used through a variable it was moved out of
+    UseAfterMove(String),
+    /// A variable used in a way that conflicts with a live borrow of it
+    BorrowConflict(String),
+    /// A write through a shared borrow
+    WriteThroughShared(String),
+    /// A borrow still live at the end of the block its owner was bound in
+    BorrowOutlivesOwner(String),
+    /// High data flowing into a low variable or output, named by the variable or the statement
+    InsecureFlow(String),
     Other,
 }
 
+#[derive(Debug)]
+pub enum VerifyError {
+    Type(TypeError),
+    /// A statement or expression the verifier can't reason about, written like in the source
+    Unsupported(String),
+}
+
 #[derive(Debug)]
 pub enum ImpParseError {
     Other(String),
+    /// An imported file that couldn't be read
+    ModuleNotFound(String),
+    /// The chain of files that import each other, starting and ending with the same file
+    ImportCycle(Vec<String>),
+    /// Two imports in the same file with this name
+    DuplicateModule(String),
+    /// A constant whose value depends on a variable
+    NotConstant(String),
+}
+
+impl fmt::Display for ImpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImpParseError::Other(s) => write!(f, "{}", s),
+            ImpParseError::ModuleNotFound(s) => write!(f, "Could not load module {}", s),
+            ImpParseError::ImportCycle(cycle) => write!(f, "Import cycle: {}", cycle.join(" -> ")),
+            ImpParseError::DuplicateModule(name) => {
+                write!(f, "Module '{}' is imported more than once", name)
+            }
+            ImpParseError::NotConstant(id) => {
+                write!(
+                    f,
+                    "The value of constant '{}' isn't known at compile time",
+                    id
+                )
+            }
+        }
+    }
 }
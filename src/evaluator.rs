@@ -1,184 +1,1101 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::BufRead;
+use std::rc::Rc;
 
 use crate::error::{EvalError::*, EvalResult};
+use crate::interval;
+use crate::oracle::{Exhaustive, FirstChoice, Oracle};
 use crate::syntax::{Constant::*, *};
-use crate::typechecker::Type::*;
+use crate::typechecker::{self, qualify, Type};
 
-#[derive(Debug, PartialEq, Eq)]
+/// How many times the body of a `while` runs at most. The loop is then left as if its condition
+/// were false, so that generated programs can't loop forever.
+pub const MAX_ITERATIONS: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
-    Number(i64),
+    Number(u64),
+    Int(i64),
+    Bool(bool),
+    Str(String),
     Location(usize),
+    Tuple(Vec<Value>),
+    /// A value of an enum: the enum, the variant and the values of its fields
+    Variant(Ident, Ident, Vec<Value>),
+}
+
+impl Value {
+    /// The type of the value. Locations need the heap to know what they point to, so a location
+    /// that isn't on the heap is an `InvalidDereference`.
+    pub fn ty(&self, heap: &Heap) -> EvalResult<Type> {
+        Ok(match self {
+            Value::Number(_) => Type::Number,
+            Value::Int(_) => Type::Int,
+            Value::Bool(_) => Type::Boolean,
+            Value::Str(_) => Type::String,
+            Value::Location(l) => Type::location(heap.get(*l).ok_or(InvalidDereference)?.ty(heap)?),
+            Value::Tuple(values) => Type::Tuple(
+                values
+                    .iter()
+                    .map(|v| v.ty(heap))
+                    .collect::<EvalResult<_>>()?,
+            ),
+            Value::Variant(name, _, _) => Type::Enum(name.clone()),
+        })
+    }
+}
+
+impl From<&Constant> for Value {
+    fn from(c: &Constant) -> Self {
+        match c {
+            Nat(n) => Value::Number(*n),
+            Int(i) => Value::Int(*i),
+            Bool(b) => Value::Bool(*b),
+            Str(s) => Value::Str(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Location(l) => write!(f, "<location {}>", l),
+            Value::Tuple(values) => {
+                let values: Vec<_> = values.iter().map(Value::to_string).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Value::Variant(name, variant, fields) if fields.is_empty() => {
+                write!(f, "{}::{}", name, variant)
+            }
+            Value::Variant(name, variant, fields) => {
+                let fields: Vec<_> = fields.iter().map(Value::to_string).collect();
+                write!(f, "{}::{}({})", name, variant, fields.join(", "))
+            }
+        }
+    }
 }
 
-type Sigma = HashMap<Ident, Value>;
-type Heap = Vec<i64>;
+/// Decides which variables are still in scope after a conditional or a loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scoping {
+    /// Keep every variable bound by the branch or the loop body that was executed
+    #[default]
+    Dynamic,
+    /// Only keep the variables that the typechecker keeps, so the store always has the same
+    /// domain as the typing context
+    Static,
+}
 
-pub fn eval_program(program: &Statement) -> EvalResult<(Sigma, Heap)> {
-    let mut store = HashMap::new();
+/// Where the output of `print` statements goes
+pub trait Output {
+    fn print(&mut self, line: &str);
+}
+
+/// Captures every printed line, in order
+impl Output for Vec<String> {
+    fn print(&mut self, line: &str) {
+        self.push(line.to_owned());
+    }
+}
+
+/// Writes printed lines straight to stdout
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn print(&mut self, line: &str) {
+        println!("{}", line);
+    }
+}
+
+/// Where `read` statements get their values from
+pub trait Input {
+    /// The next whitespace separated token, or `None` once the input runs out
+    fn next_token(&mut self) -> Option<String>;
+}
+
+/// Values passed in directly, consumed from the front
+impl Input for VecDeque<String> {
+    fn next_token(&mut self) -> Option<String> {
+        self.pop_front()
+    }
+}
+
+/// Reads tokens from any buffered reader, such as stdin or a file
+pub struct Tokens<R> {
+    reader: R,
+    pending: VecDeque<String>,
+}
+
+impl<R: BufRead> Tokens<R> {
+    pub fn new(reader: R) -> Self {
+        Tokens {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Input for Tokens<R> {
+    fn next_token(&mut self) -> Option<String> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            // A read error is treated like the end of the input
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            self.pending
+                .extend(line.split_whitespace().map(str::to_owned));
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Everything the evaluator needs besides the store and the heap
+pub struct Runtime<'a> {
+    pub scoping: Scoping,
+    pub output: &'a mut dyn Output,
+    pub input: &'a mut dyn Input,
+    /// Resolves `choose` and `havoc`, always picking the first option by default
+    pub oracle: Box<dyn Oracle>,
+    /// How many times a `par` can switch between its branches while both are still running
+    pub max_switches: Option<usize>,
+    // The globals exported by every module that has been run, by module id
+    modules: HashMap<String, Sigma>,
+    // Every enum declaration that has been run, for the typechecker to know about in static mode
+    enums: Vec<Statement>,
+}
+
+impl<'a> Runtime<'a> {
+    pub fn new(output: &'a mut dyn Output, input: &'a mut dyn Input) -> Self {
+        Runtime {
+            scoping: Scoping::default(),
+            output,
+            input,
+            oracle: Box::new(FirstChoice),
+            max_switches: None,
+            modules: HashMap::new(),
+            enums: Vec::new(),
+        }
+    }
+}
+
+pub type Sigma = HashMap<Ident, Value>;
+pub type Heap = Vec<Value>;
+pub type Transcript = Vec<String>;
+
+pub fn eval_program(program: &Statement) -> EvalResult<(Sigma, Heap, Transcript)> {
+    eval_program_scoped(program, Scoping::default())
+}
+
+pub fn eval_program_scoped(
+    program: &Statement,
+    scoping: Scoping,
+) -> EvalResult<(Sigma, Heap, Transcript)> {
+    let mut transcript = Vec::new();
+    let mut input = VecDeque::new();
+    let mut rt = Runtime::new(&mut transcript, &mut input);
+    rt.scoping = scoping;
+    let (store, heap) = eval_program_with(program, HashMap::new(), &mut rt)?;
+    Ok((store, heap, transcript))
+}
+
+/// Evaluates `program` starting from `store`, which can be used to pass arguments to the program
+pub fn eval_program_with(
+    program: &Statement,
+    mut store: Sigma,
+    rt: &mut Runtime,
+) -> EvalResult<(Sigma, Heap)> {
     let mut heap = Vec::new();
-    eval_stmnt(program, &mut store, &mut heap)?;
+    eval_stmnt(program, &mut store, &mut heap, rt)?;
     Ok((store, heap))
 }
 
-fn eval_expr(expr: &Expr, store: &Sigma, heap: &Heap) -> EvalResult<Constant> {
+/// Runs `program` once for every sequence of choices its `choose`, `havoc` and `par` statements can
+/// make, with `havoc` picking from the `havoc_values` nats or ints closest to 0, and each `par`
+/// switching branches at most `max_switches` times. Every run reads from its own copy of `input`.
+pub fn eval_all(
+    program: &Statement,
+    store: &Sigma,
+    input: &VecDeque<String>,
+    scoping: Scoping,
+    havoc_values: usize,
+    max_switches: Option<usize>,
+) -> Vec<(EvalResult<(Sigma, Heap)>, Transcript)> {
+    let oracle = Rc::new(RefCell::new(Exhaustive::new(havoc_values)));
+    let mut runs = Vec::new();
+    loop {
+        let mut transcript = Vec::new();
+        let mut input = input.clone();
+        let mut rt = Runtime::new(&mut transcript, &mut input);
+        rt.scoping = scoping;
+        rt.oracle = Box::new(oracle.clone());
+        rt.max_switches = max_switches;
+        let result = eval_program_with(program, store.clone(), &mut rt);
+        runs.push((result, transcript));
+        if !oracle.borrow_mut().next_run() {
+            return runs;
+        }
+    }
+}
+
+pub fn eval_expr(expr: &Expr, store: &Sigma, heap: &Heap) -> EvalResult<Value> {
     match expr {
-        // Read from the store, and return if it's a constant
+        // Read from the store, and return if it's not a location
         Expr::StoreRead(x) => store.get(x).ok_or(UnboundVariable).and_then(|v| match v {
-            Value::Number(i) => Ok(Nat(*i)),
-            Value::Location(_) => Err(TypeMismatch {
-                expected: Number,
-                got: Location,
-            }),
+            Value::Location(_) => Err(mismatch(Type::Number, v, heap)),
+            v => Ok(v.clone()),
         }),
         // Get the location from the store, and read from the heap
         Expr::HeapRead(x) => {
-            let index = store.get(x).ok_or(UnboundVariable).and_then(get_loc)?;
-            let value = heap.get(index).ok_or(InvalidDereference)?;
-            Ok(Constant::Nat(*value))
+            let index = store
+                .get(x)
+                .ok_or(UnboundVariable)
+                .and_then(|v| get_loc(v, heap))?;
+            heap.get(index).cloned().ok_or(InvalidDereference)
         }
         // Return the constant
-        Expr::Constant(c) => Ok(*c),
+        Expr::Constant(c) => Ok(c.into()),
         // Evaluate expressions if they're the correct values
-        Expr::NatAdd(a, b) => {
-            let a = eval_expr(a, store, heap)?;
-            let b = eval_expr(b, store, heap)?;
-            match (a, b) {
-                // Addition might overflow, so we need to check for that
-                (Nat(a), Nat(b)) => Ok(Nat(a.checked_add(b).unwrap_or(0))),
-                _ => Err(TypeMismatch {
-                    expected: Number,
-                    got: Boolean,
-                }),
-            }
+        // Addition might overflow, so we need to check for that
+        Expr::NatAdd(a, b) => eval_numeric(
+            (a, b),
+            store,
+            heap,
+            |a, b| Value::Number(a.checked_add(b).unwrap_or(0)),
+            |a, b| Value::Int(a.checked_add(b).unwrap_or(0)),
+        ),
+        // Subtraction on nats stops at 0
+        Expr::NatSub(a, b) => eval_numeric(
+            (a, b),
+            store,
+            heap,
+            |a, b| Value::Number(a.saturating_sub(b)),
+            |a, b| Value::Int(a.checked_sub(b).unwrap_or(0)),
+        ),
+        Expr::NatLeq(a, b) => eval_numeric(
+            (a, b),
+            store,
+            heap,
+            |a, b| Value::Bool(a <= b),
+            |a, b| Value::Bool(a <= b),
+        ),
+        Expr::IntNeg(a) => {
+            let a = eval_expr(a, store, heap).and_then(|v| get_int(v, heap))?;
+            Ok(Value::Int(a.checked_neg().unwrap_or(0)))
         }
-        Expr::NatLeq(a, b) => {
-            let a = eval_expr(a, store, heap)?;
-            let b = eval_expr(b, store, heap)?;
-            match (a, b) {
-                (Nat(a), Nat(b)) => Ok(Bool(a <= b)),
-                _ => Err(TypeMismatch {
-                    expected: Number,
-                    got: Boolean,
-                }),
-            }
+        Expr::ToInt(a) => {
+            let a = eval_expr(a, store, heap).and_then(|v| get_nat(v, heap))?;
+            i64::try_from(a).map(Value::Int).map_err(|_| Overflow(a))
+        }
+        Expr::ToNat(a) => {
+            let a = eval_expr(a, store, heap).and_then(|v| get_int(v, heap))?;
+            u64::try_from(a)
+                .map(Value::Number)
+                .map_err(|_| Underflow(a))
         }
         Expr::BoolAnd(a, b) => {
-            let a = eval_expr(a, store, heap)?;
-            let b = eval_expr(b, store, heap)?;
-            match (a, b) {
-                (Bool(a), Bool(b)) => Ok(Bool(a && b)),
-                _ => Err(TypeMismatch {
-                    expected: Boolean,
-                    got: Number,
-                }),
-            }
+            let a = eval_expr(a, store, heap).and_then(|v| get_bool(v, heap))?;
+            let b = eval_expr(b, store, heap).and_then(|v| get_bool(v, heap))?;
+            Ok(Value::Bool(a && b))
         }
         Expr::BoolNot(a) => {
+            let a = eval_expr(a, store, heap).and_then(|v| get_bool(v, heap))?;
+            Ok(Value::Bool(!a))
+        }
+        Expr::StrConcat(a, b) => {
+            let a = eval_expr(a, store, heap).and_then(|v| get_str(v, heap))?;
+            let b = eval_expr(b, store, heap).and_then(|v| get_str(v, heap))?;
+            Ok(Value::Str(a + &b))
+        }
+        Expr::StrLen(a) => {
+            let a = eval_expr(a, store, heap).and_then(|v| get_str(v, heap))?;
+            Ok(Value::Number(a.chars().count() as u64))
+        }
+        Expr::Equal(a, b) => {
             let a = eval_expr(a, store, heap)?;
-            match a {
-                Bool(a) => Ok(Bool(!a)),
-                _ => Err(TypeMismatch {
-                    expected: Boolean,
-                    got: Number,
-                }),
+            let b = eval_expr(b, store, heap)?;
+            let (a_ty, b_ty) = (a.ty(heap)?, b.ty(heap)?);
+            if a_ty != b_ty || matches!(a, Value::Location(_)) {
+                return Err(TypeMismatch {
+                    expected: a_ty,
+                    got: b_ty,
+                });
             }
+            Ok(Value::Bool(a == b))
         }
+        Expr::Tuple(components) => components
+            .iter()
+            .map(|e| eval_expr(e, store, heap))
+            .collect::<EvalResult<_>>()
+            .map(Value::Tuple),
+        // A variable holding a location can be given for a field, like in `Tree::Node(left, right)`
+        Expr::Construct(name, variant, args) => args
+            .iter()
+            .map(|arg| match arg {
+                Expr::StoreRead(x) => match store.get(x) {
+                    Some(l @ Value::Location(_)) => Ok(l.clone()),
+                    _ => eval_expr(arg, store, heap),
+                },
+                arg => eval_expr(arg, store, heap),
+            })
+            .collect::<EvalResult<_>>()
+            .map(|fields| Value::Variant(name.clone(), variant.clone(), fields)),
     }
 }
 
-fn eval_stmnt(stmnt: &Statement, store: &mut Sigma, heap: &mut Heap) -> EvalResult<()> {
+fn eval_stmnt(
+    stmnt: &Statement,
+    store: &mut Sigma,
+    heap: &mut Heap,
+    rt: &mut Runtime,
+) -> EvalResult<()> {
     match stmnt {
         Statement::StoreAssign(id, expr) => {
-            let value = eval_expr(expr, store, heap).and_then(get_nat)?;
-            // If value is present, make sure it's a number
-            match store.get(id) {
-                Some(Value::Number(_)) | None => store.insert(id.clone(), Value::Number(value)),
-                Some(Value::Location(_)) => Err(BoundTypeMismatch)?,
+            let value = eval_expr(expr, store, heap).and_then(|v| get_storable(v, heap))?;
+            bind(id, value, store, heap)
+        }
+        Statement::AddressOf(p, x) => {
+            let index = match store.get(x).ok_or(UnboundVariable)? {
+                Value::Location(index) => *index,
+                value => {
+                    let value = get_storable(value.clone(), heap)?;
+                    heap.push(value);
+                    store.insert(x.clone(), Value::Location(heap.len() - 1));
+                    heap.len() - 1
+                }
             };
-            Ok(())
+            bind(p, Value::Location(index), store, heap)
         }
-        Statement::HeapNew(id, expr) => {
-            let value = eval_expr(expr, store, heap).and_then(get_nat)?;
-            let index = heap.len();
-            heap.push(value);
-            // If value is present, make sure it's a location
-            match store.get(id) {
-                Some(Value::Location(_)) | None => store.insert(id.clone(), Value::Location(index)),
-                Some(Value::Number(_)) => Err(BoundTypeMismatch)?,
+        Statement::Destructure(ids, expr) => {
+            let values = match eval_expr(expr, store, heap).and_then(|v| get_storable(v, heap))? {
+                Value::Tuple(values) if values.len() == ids.len() => values,
+                v => {
+                    return Err(TupleArity {
+                        expected: ids.len(),
+                        got: v.ty(heap)?,
+                    })
+                }
             };
+            for (id, value) in ids.iter().zip(values) {
+                bind(id, value, store, heap)?;
+            }
             Ok(())
         }
+        Statement::HeapNew(id, expr) => {
+            let value = eval_expr(expr, store, heap).and_then(|v| get_storable(v, heap))?;
+            alloc(id, value, store, heap)
+        }
         Statement::HeapUpdate(id, expr) => {
-            let value = eval_expr(expr, store, heap).and_then(get_nat)?;
-            let index = store.get(id).ok_or(UnboundVariable).and_then(get_loc)?;
-            // Check if the index is in the heap, and if it is, update it
-            heap.get_mut(index)
-                .ok_or(InvalidDereference)
-                .map(|c| *c = value)
+            let value = eval_expr(expr, store, heap)?;
+            let index = store
+                .get(id)
+                .ok_or(UnboundVariable)
+                .and_then(|v| get_loc(v, heap))?;
+            // Check if the index is in the heap, and if it holds the same type, update it
+            let cell_ty = heap.get(index).ok_or(InvalidDereference)?.ty(heap)?;
+            if cell_ty != value.ty(heap)? {
+                return Err(mismatch(cell_ty, &value, heap));
+            }
+            heap[index] = value;
+            Ok(())
         }
         // Get the location from the store, and add the alias to the store
-        Statement::HeapAlias(alias, id) => {
-            let index = store.get(id).ok_or(UnboundVariable).and_then(get_loc)?;
+        Statement::HeapAlias(alias, id) | Statement::Borrow(alias, id, _) => {
+            let index = store
+                .get(id)
+                .ok_or(UnboundVariable)
+                .and_then(|v| get_loc(v, heap))?;
             store.insert(alias.clone(), Value::Location(index));
             Ok(())
         }
         Statement::Sequence(s1, s2) => {
-            eval_stmnt(s1, store, heap)?;
-            eval_stmnt(s2, store, heap)
+            eval_stmnt(s1, store, heap, rt)?;
+            eval_stmnt(s2, store, heap, rt)
         }
         Statement::Conditional(expr, then_s, else_s) => {
-            let value = eval_expr(expr, store, heap)?;
-            match value {
-                Bool(true) => eval_stmnt(then_s, store, heap),
-                Bool(false) => eval_stmnt(else_s, store, heap),
-                _ => Err(TypeMismatch {
-                    expected: Boolean,
-                    got: Number,
-                }),
+            // Which variables leak depends on both branches, so work it out before running one
+            let leaked = match rt.scoping {
+                Scoping::Dynamic => None,
+                Scoping::Static => Some(leaked_names(stmnt, store, heap, &rt.enums)),
+            };
+            let value = eval_expr(expr, store, heap).and_then(|v| get_bool(v, heap))?;
+            if value {
+                eval_stmnt(then_s, store, heap, rt)?;
+            } else {
+                eval_stmnt(else_s, store, heap, rt)?;
+            }
+            if let Some(leaked) = leaked {
+                store.retain(|id, _| leaked.contains(id));
             }
+            Ok(())
         }
         Statement::While(expr, loop_s) => {
-            let mut value = eval_expr(expr, store, heap)?;
+            let outer: HashSet<Ident> = store.keys().cloned().collect();
+            let moved = moved_by(loop_s, store);
+            let mut value = eval_expr(expr, store, heap).and_then(|v| get_bool(v, heap))?;
             let mut count = 0;
-            while let Bool(true) = value {
-                if count > 5 {
+            while value {
+                if count == MAX_ITERATIONS {
                     // We don't want to loop forever, automatically break here
-                    return Ok(());
+                    break;
                 }
-                eval_stmnt(loop_s, store, heap)?;
-                value = eval_expr(expr, store, heap)?;
+                eval_stmnt(loop_s, store, heap, rt)?;
+                store.retain(|id, _| !moved.contains(id));
+                value = eval_expr(expr, store, heap).and_then(|v| get_bool(v, heap))?;
                 count += 1;
             }
-            if matches!(value, Bool(_)) {
+            if rt.scoping == Scoping::Static {
+                // The typechecker never lets bindings from the loop body escape
+                store.retain(|id, _| outer.contains(id) && !moved.contains(id));
+            }
+            Ok(())
+        }
+        Statement::Scope(body) => {
+            // Variables bound inside the scope are dropped at `end`, but updates to
+            // variables that already existed outside of it are kept
+            let outer: HashSet<Ident> = store.keys().cloned().collect();
+            eval_stmnt(body, store, heap, rt)?;
+            store.retain(|id, _| outer.contains(id));
+            Ok(())
+        }
+        Statement::Choose(s1, s2) => {
+            let leaked = match rt.scoping {
+                Scoping::Dynamic => None,
+                Scoping::Static => Some(leaked_names(stmnt, store, heap, &rt.enums)),
+            };
+            match rt.oracle.choose(2) {
+                0 => eval_stmnt(s1, store, heap, rt)?,
+                _ => eval_stmnt(s2, store, heap, rt)?,
+            }
+            if let Some(leaked) = leaked {
+                store.retain(|id, _| leaked.contains(id));
+            }
+            Ok(())
+        }
+        Statement::Parallel(s1, s2) => {
+            let leaked = match rt.scoping {
+                Scoping::Dynamic => None,
+                Scoping::Static => Some(leaked_names(stmnt, store, heap, &rt.enums)),
+            };
+            eval_parallel(s1, s2, store, heap, rt)?;
+            if let Some(leaked) = leaked {
+                store.retain(|id, _| leaked.contains(id));
+            }
+            Ok(())
+        }
+        Statement::Labeled(_, stmnt) => eval_stmnt(stmnt, store, heap, rt),
+        Statement::Havoc(id) => {
+            let value = match store.get(id).ok_or(UnboundVariable)? {
+                Value::Number(_) => Value::Number(rt.oracle.arbitrary_nat()),
+                Value::Int(_) => Value::Int(rt.oracle.arbitrary_int()),
+                v => return Err(mismatch(Type::Number, v, heap)),
+            };
+            store.insert(id.clone(), value);
+            Ok(())
+        }
+        Statement::Print(expr) => {
+            let value = eval_expr(expr, store, heap)?;
+            rt.output.print(&value.to_string());
+            Ok(())
+        }
+        Statement::StoreInput(id) => {
+            let value = read_nat(rt)?;
+            bind(id, Value::Number(value), store, heap)
+        }
+        Statement::HeapInput(id) => {
+            let value = read_nat(rt)?;
+            alloc(id, Value::Number(value), store, heap)
+        }
+        Statement::Assert(cond, message) => check(cond, message.as_ref(), store, heap),
+        Statement::Assume(cond) => {
+            if eval_expr(cond, store, heap).and_then(|v| get_bool(v, heap))? {
                 Ok(())
             } else {
-                Err(TypeMismatch {
-                    expected: Boolean,
-                    got: Number,
-                })
+                Err(Pruned)
+            }
+        }
+        Statement::Contract(_, cond) => check(cond, None, store, heap),
+        // Every read of a constant has already been replaced by its value
+        Statement::Const(_, _) => Ok(()),
+        // A declaration in a loop runs more than once, but the enum is only declared once
+        Statement::Enum(_, _) => {
+            if !rt.enums.contains(stmnt) {
+                rt.enums.push(stmnt.clone());
+            }
+            Ok(())
+        }
+        Statement::Match(expr, cases) => {
+            let leaked = match rt.scoping {
+                Scoping::Dynamic => None,
+                Scoping::Static => Some(leaked_names(stmnt, store, heap, &rt.enums)),
+            };
+            let value = eval_expr(expr, store, heap)?;
+            let body = select_case(cases, value, store, heap)?;
+            eval_stmnt(body, store, heap, rt)?;
+            if let Some(leaked) = leaked {
+                store.retain(|id, _| leaked.contains(id));
             }
+            Ok(())
+        }
+        Statement::Import(path, _) => Err(UnresolvedImport(path.clone())),
+        Statement::Module(name, id, body) => {
+            // Each module only runs the first time it is imported, and shares the heap
+            let exports = match rt.modules.get(id) {
+                Some(exports) => exports.clone(),
+                None => {
+                    let mut module_store = HashMap::new();
+                    eval_stmnt(body, &mut module_store, heap, rt)?;
+                    module_store.retain(|k, _| !typechecker::is_qualified(k));
+                    rt.modules.insert(id.clone(), module_store.clone());
+                    module_store
+                }
+            };
+            store.extend(exports.into_iter().map(|(k, v)| (qualify(name, &k), v)));
+            Ok(())
         }
         Statement::Skip => Ok(()),
     }
 }
 
-const fn get_nat(c: Constant) -> EvalResult<i64> {
-    match c {
-        Nat(i) => Ok(i),
-        Bool(_) => Err(TypeMismatch {
-            expected: Number,
-            got: Boolean,
-        }),
+// Fails with the message, if there is one, unless `cond` holds
+fn check(cond: &Expr, message: Option<&Expr>, store: &Sigma, heap: &Heap) -> EvalResult<()> {
+    if eval_expr(cond, store, heap).and_then(|v| get_bool(v, heap))? {
+        return Ok(());
+    }
+    let message = match message {
+        Some(message) => Some(eval_expr(message, store, heap).and_then(|v| get_str(v, heap))?),
+        None => None,
+    };
+    Err(AssertionFailed {
+        expr: Box::new(cond.clone()),
+        message,
+        store: store.clone(),
+    })
+}
+
+// Finds the first case whose pattern matches `value`, and binds the fields it names
+fn select_case<'c>(
+    cases: &'c [(Pattern, Statement)],
+    value: Value,
+    store: &mut Sigma,
+    heap: &Heap,
+) -> EvalResult<&'c Statement> {
+    let (name, variant, fields) = match value {
+        Value::Variant(name, variant, fields) => (name, variant, fields),
+        v => return Err(NotAnEnum(v.ty(heap)?)),
+    };
+    let (pattern, body) = cases
+        .iter()
+        .find(|(pattern, _)| match pattern {
+            Pattern::Wildcard => true,
+            Pattern::Variant(n, v, _) => n == &name && v == &variant,
+        })
+        .ok_or_else(|| NoMatchingCase(format!("{}::{}", name, variant)))?;
+    if let Pattern::Variant(_, _, binders) = pattern {
+        if binders.len() != fields.len() {
+            return Err(Arity {
+                variant: format!("{}::{}", name, variant),
+                expected: fields.len(),
+                got: binders.len(),
+            });
+        }
+        for (binder, value) in binders.iter().zip(fields) {
+            if let Some(id) = binder {
+                bind(id, value, store, heap)?;
+            }
+        }
+    }
+    Ok(body)
+}
+
+fn bind(id: &Ident, value: Value, store: &mut Sigma, heap: &Heap) -> EvalResult<()> {
+    // If a value is present, make sure it has the same type
+    if let Some(old) = store.get(id) {
+        if old.ty(heap)? != value.ty(heap)? {
+            return Err(BoundTypeMismatch);
+        }
+    }
+    store.insert(id.clone(), value);
+    Ok(())
+}
+
+fn alloc(id: &Ident, value: Value, store: &mut Sigma, heap: &mut Heap) -> EvalResult<()> {
+    let index = heap.len();
+    heap.push(value);
+    bind(id, Value::Location(index), store, heap)
+}
+
+fn eval_parallel(
+    s1: &Statement,
+    s2: &Statement,
+    store: &mut Sigma,
+    heap: &mut Heap,
+    rt: &mut Runtime,
+) -> EvalResult<()> {
+    let mut par = Interleaving::new(s1, s2, store, rt.scoping);
+    while !par.is_done() {
+        par.step(&[], store, heap, rt)?;
+    }
+    Ok(())
+}
+
+// The branches of a `par`, which of them took the last step and how many times that changed
+struct Interleaving<'s> {
+    threads: [Thread<'s>; 2],
+    current: usize,
+    switches: usize,
+}
+
+impl<'s> Interleaving<'s> {
+    fn new(s1: &'s Statement, s2: &'s Statement, store: &mut Sigma, scoping: Scoping) -> Self {
+        let mut threads = [Thread::new(s1), Thread::new(s2)];
+        for thread in &mut threads {
+            thread.settle(store, scoping);
+        }
+        Interleaving {
+            threads,
+            current: 0,
+            switches: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.threads.iter().all(Thread::is_done)
+    }
+
+    // The oracle picks which branch takes the next step, for as long as both have steps left.
+    // `others` are the threads running alongside the whole `par`.
+    fn step(
+        &mut self,
+        others: &[&Thread],
+        store: &mut Sigma,
+        heap: &mut Heap,
+        rt: &mut Runtime,
+    ) -> EvalResult<()> {
+        self.current = match (self.threads[0].is_done(), self.threads[1].is_done()) {
+            (true, true) => return Ok(()),
+            (false, true) => 0,
+            (true, false) => 1,
+            (false, false) if rt.max_switches.is_some_and(|max| self.switches >= max) => {
+                self.current
+            }
+            (false, false) => {
+                let next = rt.oracle.choose(2);
+                if next != self.current {
+                    self.switches += 1;
+                }
+                next
+            }
+        };
+        let [first, second] = &mut self.threads;
+        let (thread, sibling) = match self.current {
+            0 => (first, &*second),
+            _ => (second, &*first),
+        };
+        thread.step(&[&[sibling], others].concat(), store, heap, rt)
+    }
+}
+
+// A branch of a `par`. Sequences and blocks are split into separate steps, and so are `if`,
+// `while`, `choose` and `match` once their condition, choice or value picks the statement to run
+// next. A nested `par` takes its steps from both of its branches. Every other statement runs as a
+// single step.
+struct Thread<'s> {
+    frames: Vec<Frame<'s>>,
+    // The variables this thread has bound, in order, so the ones bound in a block can be dropped
+    bound: Vec<Ident>,
+    // The heap locations this thread has read and written, to detect races with the other branch
+    reads: HashSet<usize>,
+    writes: HashSet<usize>,
+}
+
+enum Frame<'s> {
+    Run(&'s Statement),
+    // A `while` loop, how many times its body has run and the variables it moves, see `moved_by`
+    Loop(&'s Expr, &'s Statement, usize, HashSet<Ident>),
+    // A nested `par`, whose branches take turns taking this thread's steps
+    Parallel(Box<Interleaving<'s>>),
+    // The end of a block: drops the variables bound since the mark, except the ones to keep
+    Restore(usize, HashSet<Ident>),
+}
+
+impl<'s> Thread<'s> {
+    fn new(stmnt: &'s Statement) -> Self {
+        Thread {
+            frames: vec![Frame::Run(stmnt)],
+            bound: Vec::new(),
+            reads: HashSet::new(),
+            writes: HashSet::new(),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Unfolds the frames that don't do anything visible, until the next one is a real step
+    fn settle(&mut self, store: &mut Sigma, scoping: Scoping) {
+        while let Some(frame) = self.frames.pop() {
+            match frame {
+                Frame::Run(Statement::Skip) => {}
+                Frame::Run(Statement::Sequence(s1, s2)) => {
+                    self.frames.push(Frame::Run(s2));
+                    self.frames.push(Frame::Run(s1));
+                }
+                Frame::Run(Statement::Scope(body)) => {
+                    self.frames
+                        .push(Frame::Restore(self.bound.len(), HashSet::new()));
+                    self.frames.push(Frame::Run(body));
+                }
+                Frame::Run(Statement::While(cond, body)) => {
+                    if scoping == Scoping::Static {
+                        self.frames
+                            .push(Frame::Restore(self.bound.len(), HashSet::new()));
+                    }
+                    let moved = moved_by(body, store);
+                    self.frames.push(Frame::Loop(cond, body, 0, moved));
+                }
+                Frame::Parallel(par) if par.is_done() => {}
+                Frame::Restore(mark, keep) => {
+                    for id in self.bound.split_off(mark) {
+                        if keep.contains(&id) {
+                            self.bound.push(id);
+                        } else {
+                            store.remove(&id);
+                        }
+                    }
+                }
+                frame => {
+                    self.frames.push(frame);
+                    return;
+                }
+            }
+        }
+    }
+
+    // `others` are the threads running alongside this one, which its accesses can race with
+    fn step(
+        &mut self,
+        others: &[&Thread],
+        store: &mut Sigma,
+        heap: &mut Heap,
+        rt: &mut Runtime,
+    ) -> EvalResult<()> {
+        let (mut reads, mut writes) = (Vec::new(), Vec::new());
+        // Like outside of a `par`, which variables leak from a branch is worked out before it runs
+        let keep = |stmnt, store: &Sigma, heap: &Heap, rt: &Runtime| match rt.scoping {
+            Scoping::Dynamic => None,
+            Scoping::Static => Some(leaked_names(stmnt, store, heap, &rt.enums)),
+        };
+        match self.frames.pop() {
+            Some(Frame::Run(stmnt @ Statement::Conditional(cond, then_s, else_s))) => {
+                expr_heap_reads(cond, &mut reads);
+                self.access(others, reads, writes, store)?;
+                let keep = keep(stmnt, store, heap, rt);
+                let value = eval_expr(cond, store, heap).and_then(|v| get_bool(v, heap))?;
+                if let Some(keep) = keep {
+                    self.frames.push(Frame::Restore(self.bound.len(), keep));
+                }
+                self.frames
+                    .push(Frame::Run(if value { then_s } else { else_s }));
+            }
+            Some(Frame::Run(stmnt @ Statement::Choose(s1, s2))) => {
+                if let Some(keep) = keep(stmnt, store, heap, rt) {
+                    self.frames.push(Frame::Restore(self.bound.len(), keep));
+                }
+                self.frames.push(Frame::Run(match rt.oracle.choose(2) {
+                    0 => s1,
+                    _ => s2,
+                }));
+            }
+            // The fields the case binds are dropped along with what its body binds
+            Some(Frame::Run(stmnt @ Statement::Match(expr, cases))) => {
+                expr_heap_reads(expr, &mut reads);
+                self.access(others, reads, writes, store)?;
+                let keep = keep(stmnt, store, heap, rt);
+                let value = eval_expr(expr, store, heap)?;
+                let mark = self.bound.len();
+                let before: HashSet<Ident> = store.keys().cloned().collect();
+                let result = select_case(cases, value, store, heap);
+                self.bound
+                    .extend(store.keys().filter(|id| !before.contains(*id)).cloned());
+                let body = result?;
+                if let Some(keep) = keep {
+                    self.frames.push(Frame::Restore(mark, keep));
+                }
+                self.frames.push(Frame::Run(body));
+            }
+            Some(Frame::Run(stmnt @ Statement::Parallel(s1, s2))) => {
+                if let Some(keep) = keep(stmnt, store, heap, rt) {
+                    self.frames.push(Frame::Restore(self.bound.len(), keep));
+                }
+                let par = Interleaving::new(s1, s2, store, rt.scoping);
+                self.frames.push(Frame::Parallel(Box::new(par)));
+            }
+            // What the branches access is accessed by this thread too
+            Some(Frame::Parallel(mut par)) => {
+                let before: HashSet<Ident> = store.keys().cloned().collect();
+                let result = par.step(others, store, heap, rt);
+                self.bound
+                    .extend(store.keys().filter(|id| !before.contains(*id)).cloned());
+                for thread in &par.threads {
+                    self.reads.extend(&thread.reads);
+                    self.writes.extend(&thread.writes);
+                }
+                self.frames.push(Frame::Parallel(par));
+                result?;
+            }
+            Some(Frame::Loop(cond, body, count, moved)) => {
+                if count > 0 || rt.scoping == Scoping::Static {
+                    store.retain(|id, _| !moved.contains(id));
+                }
+                expr_heap_reads(cond, &mut reads);
+                self.access(others, reads, writes, store)?;
+                let value = eval_expr(cond, store, heap).and_then(|v| get_bool(v, heap))?;
+                // The same limit on iterations as outside of a `par`
+                if value && count < MAX_ITERATIONS {
+                    self.frames.push(Frame::Loop(cond, body, count + 1, moved));
+                    self.frames.push(Frame::Run(body));
+                }
+            }
+            Some(Frame::Run(stmnt)) => {
+                heap_accesses(stmnt, &mut reads, &mut writes);
+                self.access(others, reads, writes, store)?;
+                let before: HashSet<Ident> = store.keys().cloned().collect();
+                let result = eval_stmnt(stmnt, store, heap, rt);
+                self.bound
+                    .extend(store.keys().filter(|id| !before.contains(*id)).cloned());
+                result?;
+            }
+            Some(frame @ Frame::Restore(..)) => self.frames.push(frame),
+            None => {}
+        }
+        self.settle(store, rt.scoping);
+        Ok(())
+    }
+
+    // Records the heap accesses of a step, which race with any conflicting access by the other
+    // threads. Variables that aren't bound to a location yet don't access anything.
+    fn access(
+        &mut self,
+        others: &[&Thread],
+        reads: Vec<Ident>,
+        writes: Vec<Ident>,
+        store: &Sigma,
+    ) -> EvalResult<()> {
+        let location = |id: &Ident| match store.get(id) {
+            Some(Value::Location(location)) => Some(*location),
+            _ => None,
+        };
+        for variable in reads {
+            if let Some(location) = location(&variable) {
+                if others.iter().any(|other| other.writes.contains(&location)) {
+                    return Err(DataRace { location, variable });
+                }
+                self.reads.insert(location);
+            }
+        }
+        for variable in writes {
+            if let Some(location) = location(&variable) {
+                if others.iter().any(|other| {
+                    other.writes.contains(&location) || other.reads.contains(&location)
+                }) {
+                    return Err(DataRace { location, variable });
+                }
+                self.writes.insert(location);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The variables that a single step of `stmnt` reads from and writes to the heap through, without
+// running it
+fn heap_accesses(stmnt: &Statement, reads: &mut Vec<Ident>, writes: &mut Vec<Ident>) {
+    match stmnt {
+        Statement::StoreAssign(_, expr)
+        | Statement::HeapNew(_, expr)
+        | Statement::Destructure(_, expr)
+        | Statement::Print(expr)
+        | Statement::Assume(expr)
+        | Statement::Contract(_, expr)
+        | Statement::Const(_, expr) => expr_heap_reads(expr, reads),
+        Statement::HeapUpdate(id, expr) => {
+            writes.push(id.clone());
+            expr_heap_reads(expr, reads);
+        }
+        Statement::Assert(cond, message) => {
+            expr_heap_reads(cond, reads);
+            if let Some(message) = message {
+                expr_heap_reads(message, reads);
+            }
+        }
+        Statement::Labeled(_, body) => heap_accesses(body, reads, writes),
+        Statement::Sequence(_, _)
+        | Statement::Conditional(_, _, _)
+        | Statement::While(_, _)
+        | Statement::Scope(_)
+        | Statement::Choose(_, _)
+        | Statement::Parallel(_, _)
+        | Statement::Match(_, _) => unreachable!("{} is split into steps", stmnt),
+        Statement::HeapAlias(_, _)
+        | Statement::Borrow(_, _, _)
+        | Statement::AddressOf(_, _)
+        | Statement::StoreInput(_)
+        | Statement::HeapInput(_)
+        | Statement::Havoc(_)
+        | Statement::Enum(_, _)
+        | Statement::Skip => {}
+        // Imports only appear at the start of a file, never inside of a `par`
+        Statement::Import(_, _) | Statement::Module(_, _, _) => {}
     }
 }
 
-const fn get_loc(v: &Value) -> EvalResult<usize> {
+fn expr_heap_reads(expr: &Expr, reads: &mut Vec<Ident>) {
+    match expr {
+        Expr::HeapRead(id) => reads.push(id.clone()),
+        Expr::StoreRead(_) | Expr::Constant(_) => {}
+        Expr::NatAdd(a, b)
+        | Expr::NatSub(a, b)
+        | Expr::NatLeq(a, b)
+        | Expr::BoolAnd(a, b)
+        | Expr::StrConcat(a, b)
+        | Expr::Equal(a, b) => {
+            expr_heap_reads(a, reads);
+            expr_heap_reads(b, reads);
+        }
+        Expr::IntNeg(a) | Expr::ToInt(a) | Expr::ToNat(a) | Expr::BoolNot(a) | Expr::StrLen(a) => {
+            expr_heap_reads(a, reads)
+        }
+        Expr::Tuple(components) | Expr::Construct(_, _, components) => {
+            for component in components {
+                expr_heap_reads(component, reads);
+            }
+        }
+    }
+}
+
+fn read_nat(rt: &mut Runtime) -> EvalResult<u64> {
+    let token = rt.input.next_token().ok_or(InputExhausted)?;
+    token.parse().map_err(|_| InvalidInput(token))
+}
+
+/// The variables bound before a loop that its body takes the address of, which moves them to the
+/// heap. Their type changes, so like in the typechecker they aren't bound after an iteration.
+fn moved_by(body: &Statement, store: &Sigma) -> HashSet<Ident> {
+    interval::statements(body)
+        .into_iter()
+        .filter_map(|stmnt| match stmnt {
+            Statement::AddressOf(_, x)
+                if !matches!(store.get(x), None | Some(Value::Location(_))) =>
+            {
+                Some(x.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The variables that are still bound after `stmnt`, according to the typechecker. If `stmnt`
+/// doesn't typecheck, or the store holds a location that isn't on the heap, nothing that it binds
+/// is considered to leak.
+fn leaked_names(
+    stmnt: &Statement,
+    store: &Sigma,
+    heap: &Heap,
+    enums: &[Statement],
+) -> HashSet<Ident> {
+    let Ok(mut sigma) = store
+        .iter()
+        .map(|(id, v)| Ok((id.clone(), v.ty(heap)?)))
+        .collect::<EvalResult<_>>()
+    else {
+        return store.keys().cloned().collect();
+    };
+    // Declaring the enums again first lets the typechecker know about them
+    let program = enums.iter().rev().fold(stmnt.clone(), |acc, decl| {
+        Statement::Sequence(Box::new(decl.clone()), Box::new(acc))
+    });
+    match typechecker::typecheck_with(&mut sigma, &program) {
+        Ok(()) => sigma.into_keys().collect(),
+        Err(_) => store.keys().cloned().collect(),
+    }
+}
+
+fn mismatch(expected: Type, got: &Value, heap: &Heap) -> crate::error::EvalError {
+    match got.ty(heap) {
+        Ok(got) => TypeMismatch { expected, got },
+        Err(e) => e,
+    }
+}
+
+fn get_nat(v: Value, heap: &Heap) -> EvalResult<u64> {
+    match v {
+        Value::Number(i) => Ok(i),
+        v => Err(mismatch(Type::Number, &v, heap)),
+    }
+}
+
+fn get_int(v: Value, heap: &Heap) -> EvalResult<i64> {
+    match v {
+        Value::Int(i) => Ok(i),
+        v => Err(mismatch(Type::Int, &v, heap)),
+    }
+}
+
+// Evaluates an operation on two nats or two ints, the type of `a` decides which one is expected
+fn eval_numeric(
+    (a, b): (&Expr, &Expr),
+    store: &Sigma,
+    heap: &Heap,
+    nat_op: fn(u64, u64) -> Value,
+    int_op: fn(i64, i64) -> Value,
+) -> EvalResult<Value> {
+    match eval_expr(a, store, heap)? {
+        Value::Number(a) => {
+            let b = eval_expr(b, store, heap).and_then(|v| get_nat(v, heap))?;
+            Ok(nat_op(a, b))
+        }
+        Value::Int(a) => {
+            let b = eval_expr(b, store, heap).and_then(|v| get_int(v, heap))?;
+            Ok(int_op(a, b))
+        }
+        v => Err(mismatch(Type::Number, &v, heap)),
+    }
+}
+
+fn get_bool(v: Value, heap: &Heap) -> EvalResult<bool> {
+    match v {
+        Value::Bool(b) => Ok(b),
+        v => Err(mismatch(Type::Boolean, &v, heap)),
+    }
+}
+
+fn get_str(v: Value, heap: &Heap) -> EvalResult<String> {
+    match v {
+        Value::Str(s) => Ok(s),
+        v => Err(mismatch(Type::String, &v, heap)),
+    }
+}
+
+// Only values of a storable type can be bound in the store or saved on the heap
+fn get_storable(v: Value, heap: &Heap) -> EvalResult<Value> {
+    match v.ty(heap)? {
+        ty if ty.is_storable() => Ok(v),
+        ty => Err(NotStorable(ty)),
+    }
+}
+
+fn get_loc(v: &Value, heap: &Heap) -> EvalResult<usize> {
     match v {
-        Value::Number(_) => Err(TypeMismatch {
-            expected: Location,
-            got: Number,
-        }),
         Value::Location(l) => Ok(*l),
+        v => Err(mismatch(Type::location(Type::Number), v, heap)),
     }
 }
 
@@ -192,7 +1109,7 @@ mod tests {
             Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
             Box::new(Statement::StoreAssign("y".into(), Expr::Constant(Nat(2)))),
         );
-        let (store, heap) = eval_program(&program).unwrap();
+        let (store, heap, _) = eval_program(&program).unwrap();
         assert_eq!(store.get("x"), Some(&Value::Number(1)));
         assert_eq!(store.get("y"), Some(&Value::Number(2)));
         assert_eq!(heap.len(), 0);
@@ -204,11 +1121,11 @@ mod tests {
             Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
             Box::new(Statement::HeapNew("y".into(), Expr::Constant(Nat(2)))),
         );
-        let (store, heap) = eval_program(&program).unwrap();
+        let (store, heap, _) = eval_program(&program).unwrap();
         assert_eq!(store.get("x"), Some(&Value::Number(1)));
         assert_eq!(store.get("y"), Some(&Value::Location(0)));
         assert_eq!(heap.len(), 1);
-        assert_eq!(heap[0], 2);
+        assert_eq!(heap[0], Value::Number(2));
     }
 
     #[test]
@@ -223,12 +1140,12 @@ mod tests {
                 )),
             )),
         );
-        let (store, heap) = eval_program(&program).unwrap();
+        let (store, heap, _) = eval_program(&program).unwrap();
         assert_eq!(store.get("x"), Some(&Value::Number(1)));
         assert_eq!(store.get("y"), Some(&Value::Location(0)));
         assert_eq!(store.get("z"), Some(&Value::Location(0)));
         assert_eq!(heap.len(), 1);
-        assert_eq!(heap[0], 3);
+        assert_eq!(heap[0], Value::Number(3));
     }
 
     #[test]
@@ -249,13 +1166,13 @@ mod tests {
                 Box::new(Statement::HeapNew("y".into(), Expr::HeapRead("z".into()))),
             )),
         );
-        let (store, heap) = eval_program(&program).unwrap();
+        let (store, heap, _) = eval_program(&program).unwrap();
         assert_eq!(store.get("x"), Some(&Value::Number(1)));
         assert_eq!(store.get("y"), Some(&Value::Location(1)));
         assert_eq!(store.get("z"), Some(&Value::Location(0)));
         assert_eq!(heap.len(), 2);
-        assert_eq!(heap[0], 3);
-        assert_eq!(heap[1], 3);
+        assert_eq!(heap[0], Value::Number(3));
+        assert_eq!(heap[1], Value::Number(3));
     }
 
     #[test]
@@ -283,14 +1200,14 @@ mod tests {
                 )),
             )),
         );
-        let (store, heap) = eval_program(&program).unwrap();
+        let (store, heap, _) = eval_program(&program).unwrap();
         assert_eq!(store.get("x"), Some(&Value::Location(0)));
         assert_eq!(store.get("y"), Some(&Value::Location(2)));
         assert_eq!(store.get("z"), Some(&Value::Location(1)));
         assert_eq!(heap.len(), 3);
-        assert_eq!(heap[0], 1);
-        assert_eq!(heap[1], 3);
-        assert_eq!(heap[2], 4);
+        assert_eq!(heap[0], Value::Number(1));
+        assert_eq!(heap[1], Value::Number(3));
+        assert_eq!(heap[2], Value::Number(4));
     }
 
     #[test]
@@ -304,8 +1221,8 @@ mod tests {
                 Expr::Constant(Bool(true)),
                 Box::new(Statement::While(
                     Expr::BoolNot(Box::new(Expr::NatLeq(
+                        Box::new(Expr::Constant(Nat(1))),
                         Box::new(Expr::Constant(Nat(0))),
-                        Box::new(Expr::Constant(Nat(-1))),
                     ))),
                     Box::new(Statement::HeapUpdate(
                         "wzedt".into(),
@@ -317,9 +1234,543 @@ mod tests {
                 )),
             )),
         );
-        let (store, heap) = eval_program(&program).unwrap();
+        let (store, heap, _) = eval_program(&program).unwrap();
         assert_eq!(store.get("wzedt"), Some(&Value::Location(0)));
         assert_eq!(store.get("elax"), Some(&Value::Location(0)));
         assert_eq!(heap.len(), 1);
     }
+
+    #[test]
+    fn test_scope_drops_bindings() {
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::Scope(Box::new(Statement::Sequence(
+                Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(2)))),
+                Box::new(Statement::HeapNew("y".into(), Expr::StoreRead("x".into()))),
+            )))),
+        );
+        let (store, heap, _) = eval_program(&program).unwrap();
+        assert_eq!(store.get("x"), Some(&Value::Number(2)));
+        assert_eq!(store.get("y"), None);
+        assert_eq!(heap.len(), 1);
+        assert_eq!(heap[0], Value::Number(2));
+    }
+
+    #[test]
+    fn test_static_scoping() {
+        // Only x is bound with the same type in both branches
+        let program = Statement::Sequence(
+            Box::new(Statement::Conditional(
+                Expr::Constant(Bool(true)),
+                Box::new(Statement::Sequence(
+                    Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+                    Box::new(Statement::StoreAssign("a".into(), Expr::Constant(Nat(1)))),
+                )),
+                Box::new(Statement::Sequence(
+                    Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(2)))),
+                    Box::new(Statement::HeapNew("a".into(), Expr::Constant(Nat(2)))),
+                )),
+            )),
+            Box::new(Statement::While(
+                Expr::NatLeq(
+                    Box::new(Expr::StoreRead("x".into())),
+                    Box::new(Expr::Constant(Nat(1))),
+                ),
+                Box::new(Statement::Sequence(
+                    Box::new(Statement::StoreAssign(
+                        "x".into(),
+                        Expr::NatAdd(
+                            Box::new(Expr::StoreRead("x".into())),
+                            Box::new(Expr::Constant(Nat(1))),
+                        ),
+                    )),
+                    Box::new(Statement::StoreAssign("b".into(), Expr::Constant(Nat(3)))),
+                )),
+            )),
+        );
+
+        let (store, _, _) = eval_program(&program).unwrap();
+        assert_eq!(store.len(), 3);
+
+        let (store, _, _) = eval_program_scoped(&program, Scoping::Static).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get("x"), Some(&Value::Number(2)));
+    }
+
+    #[test]
+    fn test_print_transcript() {
+        let program = Statement::Sequence(
+            Box::new(Statement::HeapNew("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::Sequence(
+                Box::new(Statement::Print(Expr::NatAdd(
+                    Box::new(Expr::HeapRead("x".into())),
+                    Box::new(Expr::Constant(Nat(2))),
+                ))),
+                Box::new(Statement::Print(Expr::NatLeq(
+                    Box::new(Expr::HeapRead("x".into())),
+                    Box::new(Expr::Constant(Nat(0))),
+                ))),
+            )),
+        );
+        let (_, _, transcript) = eval_program(&program).unwrap();
+        assert_eq!(transcript, vec!["3".to_string(), "false".to_string()]);
+    }
+
+    #[test]
+    fn test_read_input() {
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreInput("n".into())),
+            Box::new(Statement::HeapInput("p".into())),
+        );
+        let mut output = Vec::new();
+        let mut input = Tokens::new("4\n  7 9".as_bytes());
+        let mut rt = Runtime::new(&mut output, &mut input);
+        let (store, heap) = eval_program_with(&program, HashMap::new(), &mut rt).unwrap();
+        assert_eq!(store.get("n"), Some(&Value::Number(4)));
+        assert_eq!(store.get("p"), Some(&Value::Location(0)));
+        assert_eq!(heap, vec![Value::Number(7)]);
+        assert_eq!(input.next_token(), Some("9".to_string()));
+
+        let mut input = VecDeque::from(vec!["4".to_string(), "seven".to_string()]);
+        let mut rt = Runtime::new(&mut output, &mut input);
+        let result = eval_program_with(&program, HashMap::new(), &mut rt);
+        assert!(matches!(result, Err(InvalidInput(s)) if s == "seven"));
+
+        let result = eval_program(&program);
+        assert!(matches!(result, Err(InputExhausted)));
+    }
+
+    #[test]
+    fn test_strings() {
+        let program = Statement::Sequence(
+            Box::new(Statement::HeapNew(
+                "s".into(),
+                Expr::Constant(Str("foo".into())),
+            )),
+            Box::new(Statement::Sequence(
+                Box::new(Statement::HeapUpdate(
+                    "s".into(),
+                    Expr::StrConcat(
+                        Box::new(Expr::HeapRead("s".into())),
+                        Box::new(Expr::Constant(Str("bar".into()))),
+                    ),
+                )),
+                Box::new(Statement::Sequence(
+                    Box::new(Statement::StoreAssign(
+                        "n".into(),
+                        Expr::StrLen(Box::new(Expr::HeapRead("s".into()))),
+                    )),
+                    Box::new(Statement::Print(Expr::Equal(
+                        Box::new(Expr::HeapRead("s".into())),
+                        Box::new(Expr::Constant(Str("foobar".into()))),
+                    ))),
+                )),
+            )),
+        );
+        let (store, heap, transcript) = eval_program(&program).unwrap();
+        assert_eq!(store.get("n"), Some(&Value::Number(6)));
+        assert_eq!(heap, vec![Value::Str("foobar".into())]);
+        assert_eq!(transcript, vec!["true".to_string()]);
+    }
+
+    #[test]
+    fn test_assert_and_assume() {
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::Assert(
+                Expr::NatLeq(
+                    Box::new(Expr::StoreRead("x".into())),
+                    Box::new(Expr::Constant(Nat(0))),
+                ),
+                Some(Expr::Constant(Str("x is positive".into()))),
+            )),
+        );
+        match eval_program(&program) {
+            Err(AssertionFailed { message, store, .. }) => {
+                assert_eq!(message, Some("x is positive".to_string()));
+                assert_eq!(store.get("x"), Some(&Value::Number(1)));
+            }
+            result => panic!("expected an assertion failure, got {:?}", result),
+        }
+
+        let program = Statement::Sequence(
+            Box::new(Statement::Assume(Expr::Constant(Bool(false)))),
+            Box::new(Statement::Assert(Expr::Constant(Bool(false)), None)),
+        );
+        assert!(matches!(eval_program(&program), Err(Pruned)));
+    }
+
+    #[test]
+    fn test_nat_and_int() {
+        // Subtracting nats stops at 0, while ints can go negative
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign(
+                "n".into(),
+                Expr::NatSub(
+                    Box::new(Expr::Constant(Nat(2))),
+                    Box::new(Expr::Constant(Nat(5))),
+                ),
+            )),
+            Box::new(Statement::StoreAssign(
+                "i".into(),
+                Expr::NatSub(
+                    Box::new(Expr::ToInt(Box::new(Expr::Constant(Nat(2))))),
+                    Box::new(Expr::Constant(Int(5))),
+                ),
+            )),
+        );
+        let (store, _, _) = eval_program(&program).unwrap();
+        assert_eq!(store.get("n"), Some(&Value::Number(0)));
+        assert_eq!(store.get("i"), Some(&Value::Int(-3)));
+
+        // A negative int can't be converted to a nat
+        let program = Statement::Sequence(
+            Box::new(program),
+            Box::new(Statement::StoreAssign(
+                "m".into(),
+                Expr::ToNat(Box::new(Expr::StoreRead("i".into()))),
+            )),
+        );
+        assert!(matches!(eval_program(&program), Err(Underflow(-3))));
+    }
+
+    #[test]
+    fn test_tuples() {
+        // Tuples can be saved on the heap, and taken apart again from there
+        let pair = Expr::Tuple(vec![Expr::Constant(Nat(7)), Expr::Constant(Int(-2))]);
+        let program = Statement::Sequence(
+            Box::new(Statement::HeapNew("p".into(), pair)),
+            Box::new(Statement::Sequence(
+                Box::new(Statement::Destructure(
+                    vec!["q".into(), "r".into()],
+                    Expr::HeapRead("p".into()),
+                )),
+                Box::new(Statement::Print(Expr::HeapRead("p".into()))),
+            )),
+        );
+        let (store, heap, transcript) = eval_program(&program).unwrap();
+        assert_eq!(store.get("q"), Some(&Value::Number(7)));
+        assert_eq!(store.get("r"), Some(&Value::Int(-2)));
+        assert_eq!(
+            heap,
+            vec![Value::Tuple(vec![Value::Number(7), Value::Int(-2)])]
+        );
+        assert_eq!(transcript, vec!["(7, -2)".to_string()]);
+
+        // Destructuring fails when the number of names doesn't match the tuple
+        let program = Statement::Destructure(
+            vec!["a".into(), "b".into(), "c".into()],
+            Expr::Tuple(vec![Expr::Constant(Nat(1)), Expr::Constant(Nat(2))]),
+        );
+        assert!(matches!(
+            eval_program(&program),
+            Err(TupleArity { expected: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_match() {
+        // A tree with one node, whose children are both the same leaf on the heap
+        let node = Expr::Construct(
+            "Tree".into(),
+            "Node".into(),
+            vec![
+                Expr::StoreRead("leaf".into()),
+                Expr::Constant(Nat(4)),
+                Expr::StoreRead("leaf".into()),
+            ],
+        );
+        let case = Statement::Match(
+            Expr::HeapRead("root".into()),
+            vec![
+                (
+                    Pattern::Variant("Tree".into(), "Leaf".into(), vec![]),
+                    Statement::Skip,
+                ),
+                (
+                    Pattern::Variant(
+                        "Tree".into(),
+                        "Node".into(),
+                        vec![Some("l".into()), Some("v".into()), None],
+                    ),
+                    Statement::Print(Expr::HeapRead("l".into())),
+                ),
+            ],
+        );
+        let program = Statement::Sequence(
+            Box::new(Statement::HeapNew(
+                "leaf".into(),
+                Expr::Construct("Tree".into(), "Leaf".into(), vec![]),
+            )),
+            Box::new(Statement::Sequence(
+                Box::new(Statement::HeapNew("root".into(), node)),
+                Box::new(case),
+            )),
+        );
+        let (store, _, transcript) = eval_program(&program).unwrap();
+        assert_eq!(store.get("l"), Some(&Value::Location(0)));
+        assert_eq!(store.get("v"), Some(&Value::Number(4)));
+        assert_eq!(transcript, vec!["Tree::Leaf".to_string()]);
+
+        // Only variants can be matched, by patterns with one binder for each field
+        let program = Statement::Match(
+            Expr::Constant(Nat(1)),
+            vec![(Pattern::Wildcard, Statement::Skip)],
+        );
+        assert!(matches!(
+            eval_program(&program),
+            Err(NotAnEnum(Type::Number))
+        ));
+        let program = Statement::Match(
+            Expr::Construct("Tree".into(), "Leaf".into(), vec![]),
+            vec![(
+                Pattern::Variant("Tree".into(), "Leaf".into(), vec![None]),
+                Statement::Skip,
+            )],
+        );
+        assert!(matches!(
+            eval_program(&program),
+            Err(Arity {
+                expected: 0,
+                got: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_enum_in_loop() {
+        let program = crate::parser::parse(
+            "enum E = A | B\nlet i = 0\nwhile i < 1 do\nlet e = E::A\nlet i = i + 1\nend\n\
+            if true then\nlet y = 1\nelse\nlet y = 2\nfi\nprint y",
+        )
+        .unwrap();
+        crate::typechecker::typecheck(&program).unwrap();
+        let (_, _, transcript) = eval_program_scoped(&program, Scoping::Static).unwrap();
+        assert_eq!(transcript, vec!["1".to_string()]);
+    }
+
+    #[test]
+    fn test_address_of() {
+        let program = Statement::Sequence(
+            Box::new(Statement::Sequence(
+                Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+                Box::new(Statement::AddressOf("p".into(), "x".into())),
+            )),
+            Box::new(Statement::HeapUpdate(
+                "p".into(),
+                Expr::NatAdd(
+                    Box::new(Expr::HeapRead("x".into())),
+                    Box::new(Expr::Constant(Nat(1))),
+                ),
+            )),
+        );
+        let (store, heap, _) = eval_program(&program).unwrap();
+        assert_eq!(store.get("x"), Some(&Value::Location(0)));
+        assert_eq!(store.get("p"), Some(&Value::Location(0)));
+        assert_eq!(heap, vec![Value::Number(2)]);
+
+        // A loop that moves `x` binds it again in every iteration, so it's unbound in between,
+        // inside of a `par` too
+        let luup =
+            "let x = 0\nlet i = 0\nwhile i < 2 do\nlet x = i\nlet p = &x\nlet i = i + 1\nend";
+        for source in [
+            format!("pragma address_of\n{}", luup),
+            format!("pragma address_of\npar\n{}\n||\nskip\nend", luup),
+        ] {
+            let program = crate::parser::parse(&source).unwrap();
+            crate::typechecker::typecheck(&program).unwrap();
+            let (store, heap, _) = eval_program(&program).unwrap();
+            assert_eq!(store.get("x"), None);
+            assert_eq!(store.get("p"), Some(&Value::Location(2)));
+            assert_eq!(
+                heap,
+                vec![Value::Number(0), Value::Number(1), Value::Number(2)]
+            );
+            let (store, _, _) = eval_program_scoped(&program, Scoping::Static).unwrap();
+            assert_eq!(store.keys().collect::<Vec<_>>(), ["i"]);
+        }
+    }
+
+    #[test]
+    fn test_choose() {
+        // let y = 7, then choose to print 1 or 2, then havoc y
+        let program = Statement::Sequence(
+            Box::new(Statement::Sequence(
+                Box::new(Statement::StoreAssign("y".into(), Expr::Constant(Nat(7)))),
+                Box::new(Statement::Choose(
+                    Box::new(Statement::Print(Expr::Constant(Nat(1)))),
+                    Box::new(Statement::Print(Expr::Constant(Nat(2)))),
+                )),
+            )),
+            Box::new(Statement::Havoc("y".into())),
+        );
+        let (store, _, transcript) = eval_program(&program).unwrap();
+        assert_eq!(transcript, vec!["1".to_string()]);
+        assert_eq!(store.get("y"), Some(&Value::Number(0)));
+
+        let runs = eval_all(
+            &program,
+            &HashMap::new(),
+            &VecDeque::new(),
+            Scoping::Dynamic,
+            3,
+            None,
+        );
+        let outcomes: Vec<_> = runs
+            .into_iter()
+            .map(|(result, transcript)| (transcript[0].clone(), result.unwrap().0["y"].clone()))
+            .collect();
+        let expected: Vec<_> = ["1", "2"]
+            .iter()
+            .flat_map(|p| (0..3).map(move |y| (p.to_string(), Value::Number(y))))
+            .collect();
+        assert_eq!(outcomes, expected);
+    }
+
+    #[test]
+    fn test_parallel() {
+        let print = |n| Box::new(Statement::Print(Expr::Constant(Nat(n))));
+        let program =
+            Statement::Parallel(Box::new(Statement::Sequence(print(1), print(2))), print(3));
+        let transcripts: Vec<_> = eval_all(
+            &program,
+            &HashMap::new(),
+            &VecDeque::new(),
+            Scoping::Dynamic,
+            1,
+            None,
+        )
+        .into_iter()
+        .map(|(result, transcript)| {
+            result.unwrap();
+            transcript.concat()
+        })
+        .collect();
+        assert_eq!(transcripts, vec!["123", "132", "312"]);
+        let runs = eval_all(
+            &program,
+            &HashMap::new(),
+            &VecDeque::new(),
+            Scoping::Dynamic,
+            1,
+            Some(0),
+        );
+        assert_eq!(runs.len(), 1);
+
+        // Updating a cell through an alias, while the other branch reads it
+        let program = Statement::Sequence(
+            Box::new(Statement::Sequence(
+                Box::new(Statement::HeapNew("x".into(), Expr::Constant(Nat(0)))),
+                Box::new(Statement::HeapAlias("y".into(), "x".into())),
+            )),
+            Box::new(Statement::Parallel(
+                Box::new(Statement::Print(Expr::HeapRead("x".into()))),
+                Box::new(Statement::HeapUpdate("y".into(), Expr::Constant(Nat(1)))),
+            )),
+        );
+        assert!(matches!(
+            eval_program(&program),
+            Err(DataRace { location: 0, variable }) if variable == "y"
+        ));
+
+        // Only the case that runs accesses the heap, so writing through `q` in the other one
+        // doesn't race with reading through `p`
+        let write = Statement::HeapUpdate("q".into(), Expr::Constant(Nat(1)));
+        let read = Statement::Print(Expr::HeapRead("p".into()));
+        let store = HashMap::from([
+            (
+                "o".to_string(),
+                Value::Variant("E".into(), "B".into(), vec![]),
+            ),
+            ("p".to_string(), Value::Location(0)),
+            ("q".to_string(), Value::Location(0)),
+        ]);
+        let run = |program: &Statement| {
+            let mut transcript = Vec::new();
+            let mut input = VecDeque::new();
+            let mut rt = Runtime::new(&mut transcript, &mut input);
+            eval_stmnt(
+                program,
+                &mut store.clone(),
+                &mut vec![Value::Number(0)],
+                &mut rt,
+            )
+        };
+        let program = Statement::Parallel(
+            Box::new(Statement::Match(
+                Expr::StoreRead("o".into()),
+                vec![
+                    (
+                        Pattern::Variant("E".into(), "A".into(), vec![]),
+                        write.clone(),
+                    ),
+                    (
+                        Pattern::Variant("E".into(), "B".into(), vec![]),
+                        Statement::Skip,
+                    ),
+                ],
+            )),
+            Box::new(read.clone()),
+        );
+        run(&program).unwrap();
+
+        // The same goes for the alternative a `choose` doesn't pick
+        let program = Statement::Parallel(
+            Box::new(Statement::Choose(
+                Box::new(Statement::Skip),
+                Box::new(write.clone()),
+            )),
+            Box::new(read.clone()),
+        );
+        run(&program).unwrap();
+
+        // The branches of a nested `par` interleave with the other branch of the outer one
+        let program =
+            Statement::Parallel(Box::new(Statement::Parallel(print(1), print(2))), print(3));
+        let transcripts: Vec<_> = eval_all(
+            &program,
+            &HashMap::new(),
+            &VecDeque::new(),
+            Scoping::Dynamic,
+            1,
+            None,
+        )
+        .into_iter()
+        .map(|(result, transcript)| {
+            result.unwrap();
+            transcript.concat()
+        })
+        .collect();
+        assert!(transcripts.contains(&"132".to_string()));
+        assert!(transcripts.contains(&"231".to_string()));
+
+        // And race with it just the same
+        let program = Statement::Parallel(
+            Box::new(Statement::Parallel(
+                Box::new(Statement::Skip),
+                Box::new(write),
+            )),
+            Box::new(read),
+        );
+        assert!(matches!(run(&program), Err(DataRace { location: 0, .. })));
+    }
+
+    #[test]
+    fn test_value_types() {
+        let heap = vec![Value::Number(1)];
+        let pair = Value::Tuple(vec![Value::Location(0), Value::Bool(true)]);
+        assert_eq!(
+            pair.ty(&heap).unwrap(),
+            Type::Tuple(vec![Type::location(Type::Number), Type::Boolean])
+        );
+        assert!(matches!(
+            get_storable(pair, &heap),
+            Err(NotStorable(Type::Tuple(_)))
+        ));
+
+        // A location that isn't on the heap has no type
+        let dangling = Value::Tuple(vec![Value::Number(1), Value::Location(1)]);
+        assert!(matches!(dangling.ty(&heap), Err(InvalidDereference)));
+    }
 }
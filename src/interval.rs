@@ -0,0 +1,1152 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::evaluator::{Sigma, Value, MAX_ITERATIONS};
+use crate::syntax::{Constant, Expr, Ident, Pattern, Statement};
+use crate::typechecker::{is_qualified, qualify, Type};
+
+/// The nats or ints from `lo` to `hi`, both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub lo: i128,
+    pub hi: i128,
+}
+
+/// Every nat
+pub const NAT: Interval = Interval {
+    lo: 0,
+    hi: u64::MAX as i128,
+};
+
+/// Every int
+pub const INT: Interval = Interval {
+    lo: i64::MIN as i128,
+    hi: i64::MAX as i128,
+};
+
+impl Interval {
+    pub fn exact(n: i128) -> Interval {
+        Interval { lo: n, hi: n }
+    }
+
+    fn join(self, other: Interval) -> Interval {
+        Interval {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    // `None` if the result is empty
+    fn meet(self, other: Interval) -> Option<Interval> {
+        let (lo, hi) = (self.lo.max(other.lo), self.hi.min(other.hi));
+        (lo <= hi).then_some(Interval { lo, hi })
+    }
+
+    // Bounds that keep growing are pushed out to the bounds of `range`
+    fn widen(self, new: Interval, range: Interval) -> Interval {
+        Interval {
+            lo: if new.lo < self.lo { range.lo } else { self.lo },
+            hi: if new.hi > self.hi { range.hi } else { self.hi },
+        }
+    }
+
+    // Arithmetic that doesn't fit in `range` gives 0, like in the evaluator
+    fn wrap(self, range: Interval) -> (Interval, bool) {
+        match self.meet(range) {
+            Some(fits) if fits == self => (self, false),
+            Some(fits) => (fits.join(Interval::exact(0)), true),
+            None => (Interval::exact(0), true),
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lo == self.hi {
+            write!(f, "{}", self.lo)
+        } else {
+            write!(f, "[{}, {}]", self.lo, self.hi)
+        }
+    }
+}
+
+/// Every value a variable or heap cell could have at some point in the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbsValue {
+    Nat(Interval),
+    Int(Interval),
+    /// `None` if it could be either
+    Bool(Option<bool>),
+    /// A location allocated by one of these statements, numbered like `Analysis::points`
+    Location(BTreeSet<usize>),
+    Tuple(Vec<AbsValue>),
+    /// Any value, for strings, enums and anything else that isn't tracked
+    Top,
+}
+
+impl AbsValue {
+    /// Any value of the type
+    pub fn of_type(ty: &Type) -> AbsValue {
+        match ty {
+            Type::Number => AbsValue::Nat(NAT),
+            Type::Int => AbsValue::Int(INT),
+            Type::Boolean => AbsValue::Bool(None),
+            Type::Tuple(components) => {
+                AbsValue::Tuple(components.iter().map(AbsValue::of_type).collect())
+            }
+            Type::Location(_) | Type::String | Type::Enum(_) => AbsValue::Top,
+        }
+    }
+
+    fn join(&self, other: &AbsValue) -> AbsValue {
+        match (self, other) {
+            (AbsValue::Nat(a), AbsValue::Nat(b)) => AbsValue::Nat(a.join(*b)),
+            (AbsValue::Int(a), AbsValue::Int(b)) => AbsValue::Int(a.join(*b)),
+            (AbsValue::Bool(a), AbsValue::Bool(b)) if a == b => AbsValue::Bool(*a),
+            (AbsValue::Bool(_), AbsValue::Bool(_)) => AbsValue::Bool(None),
+            (AbsValue::Location(a), AbsValue::Location(b)) => AbsValue::Location(a | b),
+            (AbsValue::Tuple(a), AbsValue::Tuple(b)) if a.len() == b.len() => {
+                AbsValue::Tuple(a.iter().zip(b).map(|(a, b)| a.join(b)).collect())
+            }
+            _ => AbsValue::Top,
+        }
+    }
+
+    fn widen(&self, new: &AbsValue) -> AbsValue {
+        match (self, new) {
+            (AbsValue::Nat(a), AbsValue::Nat(b)) => AbsValue::Nat(a.widen(*b, NAT)),
+            (AbsValue::Int(a), AbsValue::Int(b)) => AbsValue::Int(a.widen(*b, INT)),
+            (AbsValue::Tuple(a), AbsValue::Tuple(b)) if a.len() == b.len() => {
+                AbsValue::Tuple(a.iter().zip(b).map(|(a, b)| a.widen(b)).collect())
+            }
+            _ => self.join(new),
+        }
+    }
+
+    // Any value of the same kind, for `havoc`
+    fn havoc(&self) -> AbsValue {
+        match self {
+            AbsValue::Nat(_) => AbsValue::Nat(NAT),
+            AbsValue::Int(_) => AbsValue::Int(INT),
+            v => v.clone(),
+        }
+    }
+
+    pub fn interval(&self) -> Option<Interval> {
+        match self {
+            AbsValue::Nat(i) | AbsValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    // The same kind of value, restricted to `interval`
+    fn with_interval(&self, interval: Interval) -> AbsValue {
+        match self {
+            AbsValue::Nat(_) => AbsValue::Nat(interval),
+            AbsValue::Int(_) => AbsValue::Int(interval),
+            v => v.clone(),
+        }
+    }
+}
+
+impl From<&Value> for AbsValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Number(n) => AbsValue::Nat(Interval::exact(*n as i128)),
+            Value::Int(i) => AbsValue::Int(Interval::exact(*i as i128)),
+            Value::Bool(b) => AbsValue::Bool(Some(*b)),
+            Value::Tuple(values) => AbsValue::Tuple(values.iter().map(AbsValue::from).collect()),
+            Value::Str(_) | Value::Location(_) | Value::Variant(_, _, _) => AbsValue::Top,
+        }
+    }
+}
+
+impl fmt::Display for AbsValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbsValue::Nat(i) if *i == NAT => write!(f, "nat"),
+            AbsValue::Int(i) if *i == INT => write!(f, "int"),
+            AbsValue::Nat(i) | AbsValue::Int(i) => write!(f, "{}", i),
+            AbsValue::Bool(Some(b)) => write!(f, "{}", b),
+            AbsValue::Bool(None) => write!(f, "bool"),
+            AbsValue::Location(sites) => {
+                let sites: Vec<_> = sites.iter().map(|s| format!("#{}", s)).collect();
+                write!(f, "{{{}}}", sites.join(", "))
+            }
+            AbsValue::Tuple(components) => {
+                let components: Vec<_> = components.iter().map(AbsValue::to_string).collect();
+                write!(f, "({})", components.join(", "))
+            }
+            AbsValue::Top => write!(f, "⊤"),
+        }
+    }
+}
+
+/// What the analysis knows at a program point: the value of every store variable, and of the heap
+/// cells allocated by each statement
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    pub store: BTreeMap<Ident, AbsValue>,
+    pub heap: BTreeMap<usize, AbsValue>,
+}
+
+impl State {
+    // Like the typechecker, only the variables bound on both paths are kept
+    fn join(&self, other: &State) -> State {
+        let store = self
+            .store
+            .iter()
+            .filter_map(|(id, v)| other.store.get(id).map(|w| (id.clone(), v.join(w))))
+            .collect();
+        let mut heap = self.heap.clone();
+        for (site, v) in &other.heap {
+            let joined = heap.get(site).map_or_else(|| v.clone(), |w| w.join(v));
+            heap.insert(*site, joined);
+        }
+        State { store, heap }
+    }
+
+    // Keeps the variables bound on either path, like after a `par` where both of them run
+    fn merge(&self, other: &State) -> State {
+        let mut merged = self.join(other);
+        for (id, v) in self.store.iter().chain(&other.store) {
+            merged.store.entry(id.clone()).or_insert_with(|| v.clone());
+        }
+        merged
+    }
+
+    fn widen(&self, new: &State) -> State {
+        let store = new
+            .store
+            .iter()
+            .map(|(id, v)| {
+                (
+                    id.clone(),
+                    self.store
+                        .get(id)
+                        .map_or_else(|| v.clone(), |old| old.widen(v)),
+                )
+            })
+            .collect();
+        let heap = new
+            .heap
+            .iter()
+            .map(|(site, v)| {
+                (
+                    *site,
+                    self.heap
+                        .get(site)
+                        .map_or_else(|| v.clone(), |old| old.widen(v)),
+                )
+            })
+            .collect();
+        State { store, heap }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let store = self.store.iter().map(|(id, v)| format!("{} = {}", id, v));
+        let heap = self
+            .heap
+            .iter()
+            .map(|(site, v)| format!("#{} = {}", site, v));
+        let all: Vec<_> = store.chain(heap).collect();
+        if all.is_empty() {
+            write!(f, "∅")
+        } else {
+            write!(f, "{}", all.join(", "))
+        }
+    }
+}
+
+fn join_all(states: impl IntoIterator<Item = Option<State>>) -> Option<State> {
+    states
+        .into_iter()
+        .fold(None, |joined, state| match (joined, state) {
+            (Some(a), Some(b)) => Some(a.join(&b)),
+            (a, b) => a.or(b),
+        })
+}
+
+/// Something the analysis found that could go wrong, or that doesn't do anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// An addition whose result might not fit in a nat or an int, which makes it 0
+    Overflow(Expr),
+    /// The condition of an `if` or `while` that's always true or always false
+    ConstantCondition(&'static str, Expr, bool),
+    /// A `while` loop whose condition is false the first time it's checked
+    NeverRuns(Expr),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Overflow(e) => write!(f, "`{}` might overflow", e),
+            Diagnostic::ConstantCondition(stmnt, cond, value) => {
+                write!(
+                    f,
+                    "The condition `{}` of this `{}` is always {}",
+                    cond, stmnt, value
+                )
+            }
+            Diagnostic::NeverRuns(cond) => write!(f, "The loop `while {}` never runs", cond),
+        }
+    }
+}
+
+/// A statement in the program, with the state before it
+#[derive(Debug, Clone)]
+pub struct Point {
+    /// The statement, or just its first line for blocks, written like in the source
+    pub stmnt: String,
+    /// `None` if the statement can never run
+    pub state: Option<State>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    /// Every statement other than sequences and `skip`, in the order they appear in the program
+    pub points: Vec<Point>,
+    /// The state at the head of every loop that runs, where its condition is checked before every
+    /// iteration, by the number of the loop
+    pub heads: BTreeMap<usize, State>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Computes the range of every nat and int in `program`, which must typecheck, starting with the
+/// variables in `store`. Heap cells are told apart by the statement that allocated them, and
+/// loops are run until their state stops changing, pushing bounds that keep growing out to the
+/// bounds of their type. Like the evaluator, a loop is also left once its body has run
+/// `evaluator::MAX_ITERATIONS` times.
+pub fn analyze(program: &Statement, store: &Sigma) -> Analysis {
+    let mut analyzer = Analyzer::default();
+    for (id, stmnt) in statements(program).into_iter().enumerate() {
+        // A match can be reached even when the declaration of its enum is in code that never runs
+        if let Statement::Enum(name, variants) = stmnt {
+            analyzer.enums.insert(name.clone(), variants.clone());
+        }
+        analyzer.ids.insert(stmnt, id);
+        analyzer.points.push((stmnt, None));
+    }
+    let state = State {
+        store: store
+            .iter()
+            .map(|(id, v)| (id.clone(), AbsValue::from(v)))
+            .collect(),
+        heap: BTreeMap::new(),
+    };
+    analyzer.stmnt(program, Some(state));
+    let diagnostics = analyzer.diagnostics.into_values().flatten().collect();
+    let points = analyzer
+        .points
+        .into_iter()
+        .map(|(stmnt, state)| Point {
+            stmnt: header(stmnt),
+            state,
+        })
+        .collect();
+    Analysis {
+        points,
+        heads: analyzer.heads,
+        diagnostics,
+    }
+}
+
+/// The values `e` could have in `state`
+pub fn eval(state: &State, e: &Expr) -> AbsValue {
+    Analyzer::default().expr(state, e, &mut Vec::new())
+}
+
+/// Every statement in `program` other than sequences and `skip`, in the order they appear. Labels
+/// are left out, leaving the statement they label.
+pub(crate) fn statements(program: &Statement) -> Vec<&Statement> {
+    fn visit<'a>(stmnt: &'a Statement, stmnts: &mut Vec<&'a Statement>) {
+        match stmnt {
+            Statement::Sequence(s1, s2) => {
+                visit(s1, stmnts);
+                visit(s2, stmnts);
+                return;
+            }
+            Statement::Labeled(_, s) => return visit(s, stmnts),
+            Statement::Skip => return,
+            _ => {}
+        }
+        stmnts.push(stmnt);
+        match stmnt {
+            Statement::Conditional(_, s1, s2)
+            | Statement::Choose(s1, s2)
+            | Statement::Parallel(s1, s2) => {
+                visit(s1, stmnts);
+                visit(s2, stmnts);
+            }
+            Statement::While(_, body) | Statement::Scope(body) | Statement::Module(_, _, body) => {
+                visit(body, stmnts)
+            }
+            Statement::Match(_, cases) => cases.iter().for_each(|(_, body)| visit(body, stmnts)),
+            _ => {}
+        }
+    }
+    let mut stmnts = Vec::new();
+    visit(program, &mut stmnts);
+    stmnts
+}
+
+// Blocks are written up to where their body starts
+pub(crate) fn header(stmnt: &Statement) -> String {
+    match stmnt {
+        Statement::Conditional(cond, _, _) => format!("if {} then", cond),
+        Statement::While(cond, _) => format!("while {} do", cond),
+        Statement::Scope(_) => "do".to_owned(),
+        Statement::Choose(_, _) => "choose".to_owned(),
+        Statement::Parallel(_, _) => "par".to_owned(),
+        Statement::Match(e, _) => format!("match {}", e),
+        Statement::Module(name, _, _) => format!("module {}", name),
+        stmnt => stmnt.to_string(),
+    }
+}
+
+#[derive(Default)]
+struct Analyzer<'a> {
+    // The number of every statement, by its address in the program
+    ids: HashMap<*const Statement, usize>,
+    // Every statement, and the join of the states it was reached with
+    points: Vec<(&'a Statement, Option<State>)>,
+    heads: BTreeMap<usize, State>,
+    diagnostics: BTreeMap<usize, Vec<Diagnostic>>,
+    // Sites that allocated more than one cell, which can't be updated in place
+    summaries: BTreeSet<usize>,
+    enums: HashMap<Ident, Vec<(Ident, Vec<Type>)>>,
+    // The variables exported by every module, by module id
+    modules: HashMap<String, BTreeMap<Ident, AbsValue>>,
+    // What the other branch of every `par` being analyzed might write to the store
+    relies: Vec<Writes>,
+    // What the branches of the `par`s being analyzed have written to the store so far
+    guarantees: Vec<Writes>,
+    // How many of the loops and `par`s being analyzed are still looking for their fixpoint, which
+    // means whatever is found inside of them is analyzed again
+    exploring: usize,
+}
+
+// The values written to each store variable
+type Writes = BTreeMap<Ident, AbsValue>;
+
+impl<'a> Analyzer<'a> {
+    // Forgets what was found inside `stmnt`, before analyzing it for the last time
+    fn clear(&mut self, stmnt: &Statement) {
+        for inner in statements(stmnt) {
+            let id = self.ids[&(inner as *const _)];
+            self.points[id].1 = None;
+            self.heads.remove(&id);
+            self.diagnostics.remove(&id);
+        }
+    }
+
+    fn stmnt(&mut self, stmnt: &Statement, state: Option<State>) -> Option<State> {
+        match stmnt {
+            Statement::Sequence(s1, s2) => {
+                let state = self.stmnt(s1, state);
+                return self.stmnt(s2, state);
+            }
+            Statement::Labeled(_, s) => return self.stmnt(s, state),
+            Statement::Skip => return state,
+            _ => {}
+        }
+        let id = self.ids[&(stmnt as *const _)];
+        let state = state.map(|state| self.interfere(state));
+        let point = &mut self.points[id].1;
+        *point = join_all([point.take(), state.clone()]);
+        let mut state = state?;
+        let mut found = Vec::new();
+        let after = match stmnt {
+            Statement::StoreAssign(x, e) => {
+                let v = self.expr(&state, e, &mut found);
+                state.store.insert(x.clone(), v);
+                Some(state)
+            }
+            Statement::HeapNew(p, e) => {
+                let v = self.expr(&state, e, &mut found);
+                self.allocate(&mut state, id, p, v);
+                Some(state)
+            }
+            Statement::HeapInput(p) => {
+                self.allocate(&mut state, id, p, AbsValue::Nat(NAT));
+                Some(state)
+            }
+            // Like in the evaluator, `x` only moves to the heap the first time its address is taken
+            Statement::AddressOf(p, x) => match state.store.get(x).cloned() {
+                Some(v @ AbsValue::Location(_)) => {
+                    state.store.insert(p.clone(), v);
+                    Some(state)
+                }
+                v => {
+                    self.allocate(&mut state, id, p, v.unwrap_or(AbsValue::Top));
+                    state.store.insert(x.clone(), state.store[p].clone());
+                    Some(state)
+                }
+            },
+            Statement::HeapUpdate(p, e) => {
+                let v = self.expr(&state, e, &mut found);
+                let sites = match state.store.get(p) {
+                    Some(AbsValue::Location(sites)) => sites.clone(),
+                    // A location from an enum could be any of them
+                    _ => state.heap.keys().copied().collect(),
+                };
+                match self.single_site(&state, p) {
+                    Some(site) => {
+                        state.heap.insert(site, v);
+                    }
+                    None => {
+                        for site in sites {
+                            let joined =
+                                state.heap.get(&site).map_or(AbsValue::Top, |w| w.join(&v));
+                            state.heap.insert(site, joined);
+                        }
+                    }
+                }
+                Some(state)
+            }
+            Statement::HeapAlias(q, p) | Statement::Borrow(q, p, _) => {
+                let v = state.store.get(p).cloned().unwrap_or(AbsValue::Top);
+                state.store.insert(q.clone(), v);
+                Some(state)
+            }
+            Statement::Destructure(ids, e) => {
+                let components = match self.expr(&state, e, &mut found) {
+                    AbsValue::Tuple(components) if components.len() == ids.len() => components,
+                    _ => vec![AbsValue::Top; ids.len()],
+                };
+                state.store.extend(ids.iter().cloned().zip(components));
+                Some(state)
+            }
+            Statement::StoreInput(x) => {
+                state.store.insert(x.clone(), AbsValue::Nat(NAT));
+                Some(state)
+            }
+            Statement::Havoc(x) => {
+                let v = state.store.get(x).map_or(AbsValue::Top, AbsValue::havoc);
+                state.store.insert(x.clone(), v);
+                Some(state)
+            }
+            Statement::Print(e) => {
+                self.expr(&state, e, &mut found);
+                Some(state)
+            }
+            // Execution stops when the condition is false
+            Statement::Assert(cond, message) => {
+                self.expr(&state, cond, &mut found);
+                if let Some(message) = message {
+                    self.expr(&state, message, &mut found);
+                }
+                self.filter(state, cond, true)
+            }
+            Statement::Assume(cond) | Statement::Contract(_, cond) => {
+                self.expr(&state, cond, &mut found);
+                self.filter(state, cond, true)
+            }
+            Statement::Conditional(cond, then_s, else_s) => {
+                if let AbsValue::Bool(Some(value)) = self.expr(&state, cond, &mut found) {
+                    found.push(Diagnostic::ConstantCondition("if", cond.clone(), value));
+                }
+                let then_state = self.filter(state.clone(), cond, true);
+                let else_state = self.filter(state, cond, false);
+                let then_state = self.stmnt(then_s, then_state);
+                let else_state = self.stmnt(else_s, else_state);
+                join_all([then_state, else_state])
+            }
+            Statement::While(cond, body) => self.luup(id, state, cond, body, &mut found),
+            // Nothing bound in the scope is visible after it
+            Statement::Scope(body) => self.stmnt(body, Some(state.clone())).map(|mut after| {
+                after.store.retain(|id, _| state.store.contains_key(id));
+                after
+            }),
+            Statement::Choose(s1, s2) => {
+                let s1_state = self.stmnt(s1, Some(state.clone()));
+                let s2_state = self.stmnt(s2, Some(state));
+                join_all([s1_state, s2_state])
+            }
+            Statement::Parallel(s1, s2) => self.parallel(state, s1, s2),
+            Statement::Match(e, cases) => {
+                self.expr(&state, e, &mut found);
+                let mut states = Vec::new();
+                for (pattern, body) in cases {
+                    let mut case_state = state.clone();
+                    if let Pattern::Variant(name, variant, binders) = pattern {
+                        let fields = self.enums[name]
+                            .iter()
+                            .find(|(v, _)| v == variant)
+                            .unwrap()
+                            .1
+                            .clone();
+                        for (binder, ty) in binders.iter().zip(&fields) {
+                            let Some(id) = binder else {
+                                continue;
+                            };
+                            // A location from an enum could be any of the cells allocated so far
+                            let v = match ty {
+                                Type::Location(_) => {
+                                    AbsValue::Location(case_state.heap.keys().copied().collect())
+                                }
+                                ty => AbsValue::of_type(ty),
+                            };
+                            case_state.store.insert(id.clone(), v);
+                        }
+                        self.record(&case_state, binders.iter().flatten());
+                    }
+                    states.push(self.stmnt(body, Some(case_state)));
+                }
+                join_all(states)
+            }
+            // Modules start from an empty store, and only run once. Their variables aren't the
+            // ones of any `par` they're imported in.
+            Statement::Module(name, module_id, body) => {
+                let exports = match self.modules.get(module_id) {
+                    Some(exports) => exports.clone(),
+                    None => {
+                        let module_state = State {
+                            store: BTreeMap::new(),
+                            heap: state.heap.clone(),
+                        };
+                        let relies = std::mem::take(&mut self.relies);
+                        let guarantees = std::mem::take(&mut self.guarantees);
+                        let after = self.stmnt(body, Some(module_state));
+                        self.relies = relies;
+                        self.guarantees = guarantees;
+                        let after = after?;
+                        state.heap = after.heap;
+                        let mut exports = after.store;
+                        exports.retain(|id, _| !is_qualified(id));
+                        self.modules.insert(module_id.clone(), exports.clone());
+                        exports
+                    }
+                };
+                let exports: Vec<_> = exports
+                    .into_iter()
+                    .map(|(id, v)| (qualify(name, &id), v))
+                    .collect();
+                let names: Vec<_> = exports.iter().map(|(id, _)| id.clone()).collect();
+                state.store.extend(exports);
+                self.record(&state, &names);
+                Some(state)
+            }
+            Statement::Const(_, _) | Statement::Enum(_, _) | Statement::Import(_, _) => Some(state),
+            Statement::Sequence(_, _) | Statement::Labeled(_, _) | Statement::Skip => {
+                unreachable!()
+            }
+        };
+        if let Some(after) = &after {
+            self.record(after, written(stmnt));
+        }
+        let diagnostics = self.diagnostics.entry(id).or_default();
+        for diagnostic in found {
+            if !diagnostics.contains(&diagnostic) {
+                diagnostics.push(diagnostic);
+            }
+        }
+        after
+    }
+
+    // Either branch can take a step between any two steps of the other, so each one is analyzed
+    // with what the other might write to the store joined into it before every statement, until
+    // that stops changing. The heap doesn't need this, since the evaluator stops with a
+    // `DataRace` as soon as a branch reads or writes a cell the other one wrote.
+    fn parallel(&mut self, state: State, s1: &Statement, s2: &Statement) -> Option<State> {
+        let mut writes = [Writes::new(), Writes::new()];
+        self.exploring += 1;
+        loop {
+            let next = [
+                widen_writes(&writes[0], self.branch(state.clone(), s1, &writes[1]).1),
+                widen_writes(&writes[1], self.branch(state.clone(), s2, &writes[0]).1),
+            ];
+            if next == writes {
+                break;
+            }
+            writes = next;
+        }
+        self.exploring -= 1;
+        self.clear(s1);
+        self.clear(s2);
+        let s1_state = self.branch(state.clone(), s1, &writes[1]).0;
+        let s2_state = self.branch(state, s2, &writes[0]).0;
+        Some(s1_state?.merge(&s2_state?))
+    }
+
+    // Analyzes a branch of a `par`, returning the state after it and what it wrote to the store
+    fn branch(
+        &mut self,
+        state: State,
+        stmnt: &Statement,
+        rely: &Writes,
+    ) -> (Option<State>, Writes) {
+        self.relies.push(rely.clone());
+        self.guarantees.push(Writes::new());
+        let after = self.stmnt(stmnt, Some(state));
+        self.relies.pop();
+        (after, self.guarantees.pop().unwrap())
+    }
+
+    // Joins in what the other branches of the `par`s being analyzed might have written
+    fn interfere(&self, mut state: State) -> State {
+        for (id, v) in self.relies.iter().flatten() {
+            if let Some(w) = state.store.get_mut(id) {
+                *w = w.join(v);
+            }
+        }
+        state
+    }
+
+    // Remembers the values of `ids` in `state` as written by the branches being analyzed
+    fn record<'i>(&mut self, state: &State, ids: impl IntoIterator<Item = &'i Ident>) {
+        if self.guarantees.is_empty() {
+            return;
+        }
+        for id in ids {
+            let Some(v) = state.store.get(id) else {
+                continue;
+            };
+            for writes in &mut self.guarantees {
+                let joined = writes.get(id).map_or_else(|| v.clone(), |w| w.join(v));
+                writes.insert(id.clone(), joined);
+            }
+        }
+    }
+
+    // The state at the head of the loop is widened until it holds both when the loop is reached
+    // and after every iteration. It's then narrowed once, by running the body one more time. A
+    // loop inside of a loop or `par` that's still looking for its fixpoint only needs the state
+    // after it, so it's left at that, and analyzed for good once the outer one is.
+    fn luup(
+        &mut self,
+        id: usize,
+        entry: State,
+        cond: &Expr,
+        body: &Statement,
+        found: &mut Vec<Diagnostic>,
+    ) -> Option<State> {
+        self.expr(&entry, cond, found);
+        if self.filter(entry.clone(), cond, true).is_none() {
+            found.push(Diagnostic::NeverRuns(cond.clone()));
+            self.clear(body);
+            return self.filter(entry, cond, false);
+        }
+        let exploring = self.exploring > 0;
+        self.exploring += 1;
+        let mut head = entry.clone();
+        loop {
+            let body_state = self.filter(head.clone(), cond, true);
+            let after = self.stmnt(body, body_state);
+            let next = head.widen(&join_all([Some(entry.clone()), after]).unwrap());
+            if next == head {
+                break;
+            }
+            head = next;
+        }
+        let body_state = self.filter(head.clone(), cond, true);
+        let after = self.stmnt(body, body_state);
+        head = join_all([Some(entry.clone()), after]).unwrap();
+
+        // The evaluator leaves the loop after `MAX_ITERATIONS` iterations even if the condition
+        // still holds, so the body is also run that many times from the entry without widening.
+        // Running it again for every iterate of an outer loop would take exponential time in how
+        // deeply loops are nested, so until then any state at the head where it holds will do.
+        let cut_off = if exploring {
+            self.filter(head.clone(), cond, true)
+        } else {
+            let mut cut_off = Some(entry);
+            for _ in 0..MAX_ITERATIONS {
+                let body_state = cut_off.and_then(|state| self.filter(state, cond, true));
+                cut_off = self.stmnt(body, body_state);
+            }
+            cut_off.and_then(|state| self.filter(state, cond, true))
+        };
+        self.exploring -= 1;
+
+        if !exploring {
+            self.clear(body);
+            let body_state = self.filter(head.clone(), cond, true);
+            self.stmnt(body, body_state);
+        }
+        if let AbsValue::Bool(Some(true)) = self.expr(&head, cond, &mut Vec::new()) {
+            found.push(Diagnostic::ConstantCondition("while", cond.clone(), true));
+        }
+        let joined = join_all([self.heads.remove(&id), Some(head.clone())]).unwrap();
+        self.heads.insert(id, joined);
+        join_all([self.filter(head, cond, false), cut_off])
+    }
+
+    fn allocate(&mut self, state: &mut State, site: usize, p: &Ident, v: AbsValue) {
+        // Cells allocated here before could still be reachable
+        let v = match state.heap.get(&site) {
+            Some(old) => {
+                self.summaries.insert(site);
+                old.join(&v)
+            }
+            None => v,
+        };
+        state.heap.insert(site, v);
+        state
+            .store
+            .insert(p.clone(), AbsValue::Location(BTreeSet::from([site])));
+    }
+
+    // The only cell `p` can point to, if it can be updated in place
+    fn single_site(&self, state: &State, p: &Ident) -> Option<usize> {
+        match state.store.get(p) {
+            Some(AbsValue::Location(sites)) if sites.len() == 1 => {
+                let site = *sites.iter().next().unwrap();
+                (!self.summaries.contains(&site)).then_some(site)
+            }
+            _ => None,
+        }
+    }
+
+    fn expr(&self, state: &State, e: &Expr, found: &mut Vec<Diagnostic>) -> AbsValue {
+        let mut sub = |a: &Expr| self.expr(state, a, found);
+        match e {
+            Expr::StoreRead(x) => state.store.get(x).cloned().unwrap_or(AbsValue::Top),
+            Expr::HeapRead(p) => match state.store.get(p) {
+                Some(AbsValue::Location(sites)) if !sites.is_empty() => sites
+                    .iter()
+                    .map(|site| state.heap.get(site).cloned().unwrap_or(AbsValue::Top))
+                    .reduce(|a, b| a.join(&b))
+                    .unwrap(),
+                _ => AbsValue::Top,
+            },
+            Expr::Constant(Constant::Nat(n)) => AbsValue::Nat(Interval::exact(*n as i128)),
+            Expr::Constant(Constant::Int(i)) => AbsValue::Int(Interval::exact(*i as i128)),
+            Expr::Constant(Constant::Bool(b)) => AbsValue::Bool(Some(*b)),
+            Expr::Constant(Constant::Str(_)) => AbsValue::Top,
+            Expr::NatAdd(a, b) => {
+                let (a, b) = (sub(a), sub(b));
+                let (sum, range) = match (&a, &b) {
+                    (AbsValue::Nat(a), AbsValue::Nat(b)) => ((a.lo + b.lo, a.hi + b.hi), NAT),
+                    (AbsValue::Int(a), AbsValue::Int(b)) => ((a.lo + b.lo, a.hi + b.hi), INT),
+                    _ => return a.join(&b),
+                };
+                let (sum, overflows) = Interval {
+                    lo: sum.0,
+                    hi: sum.1,
+                }
+                .wrap(range);
+                if overflows {
+                    found.push(Diagnostic::Overflow(e.clone()));
+                }
+                a.with_interval(sum)
+            }
+            // Subtracting a larger nat gives 0
+            Expr::NatSub(a, b) => match (sub(a), sub(b)) {
+                (AbsValue::Nat(a), AbsValue::Nat(b)) => AbsValue::Nat(Interval {
+                    lo: (a.lo - b.hi).max(0),
+                    hi: (a.hi - b.lo).max(0),
+                }),
+                (AbsValue::Int(a), AbsValue::Int(b)) => AbsValue::Int(
+                    Interval {
+                        lo: a.lo - b.hi,
+                        hi: a.hi - b.lo,
+                    }
+                    .wrap(INT)
+                    .0,
+                ),
+                (a, b) => a.join(&b),
+            },
+            Expr::NatLeq(a, b) => match (sub(a).interval(), sub(b).interval()) {
+                (Some(a), Some(b)) if a.hi <= b.lo => AbsValue::Bool(Some(true)),
+                (Some(a), Some(b)) if a.lo > b.hi => AbsValue::Bool(Some(false)),
+                _ => AbsValue::Bool(None),
+            },
+            Expr::IntNeg(a) => match sub(a) {
+                AbsValue::Int(a) => AbsValue::Int(
+                    Interval {
+                        lo: -a.hi,
+                        hi: -a.lo,
+                    }
+                    .wrap(INT)
+                    .0,
+                ),
+                a => a,
+            },
+            // Conversions that don't fit stop the program
+            Expr::ToInt(a) => match sub(a).interval().and_then(|a| a.meet(INT)) {
+                Some(a) => AbsValue::Int(a),
+                None => AbsValue::Int(INT),
+            },
+            Expr::ToNat(a) => match sub(a).interval().and_then(|a| a.meet(NAT)) {
+                Some(a) => AbsValue::Nat(a),
+                None => AbsValue::Nat(NAT),
+            },
+            Expr::BoolAnd(a, b) => match (sub(a), sub(b)) {
+                (AbsValue::Bool(Some(false)), _) | (_, AbsValue::Bool(Some(false))) => {
+                    AbsValue::Bool(Some(false))
+                }
+                (AbsValue::Bool(Some(true)), AbsValue::Bool(Some(true))) => {
+                    AbsValue::Bool(Some(true))
+                }
+                _ => AbsValue::Bool(None),
+            },
+            Expr::BoolNot(a) => match sub(a) {
+                AbsValue::Bool(b) => AbsValue::Bool(b.map(|b| !b)),
+                _ => AbsValue::Bool(None),
+            },
+            Expr::StrConcat(a, b) => {
+                sub(a);
+                sub(b);
+                AbsValue::Top
+            }
+            Expr::StrLen(a) => {
+                sub(a);
+                AbsValue::Nat(NAT)
+            }
+            Expr::Equal(a, b) => match (sub(a), sub(b)) {
+                (AbsValue::Bool(Some(a)), AbsValue::Bool(Some(b))) => AbsValue::Bool(Some(a == b)),
+                (a, b) => match (a.interval(), b.interval()) {
+                    (Some(a), Some(b)) if a.lo == a.hi && a == b => AbsValue::Bool(Some(true)),
+                    (Some(a), Some(b)) if a.meet(b).is_none() => AbsValue::Bool(Some(false)),
+                    _ => AbsValue::Bool(None),
+                },
+            },
+            Expr::Tuple(components) => AbsValue::Tuple(components.iter().map(sub).collect()),
+            Expr::Construct(_, _, args) => {
+                args.iter().for_each(|a| {
+                    sub(a);
+                });
+                AbsValue::Top
+            }
+        }
+    }
+
+    // The part of `state` where `cond` is `value`, or `None` if there isn't any
+    fn filter(&self, state: State, cond: &Expr, value: bool) -> Option<State> {
+        if self.expr(&state, cond, &mut Vec::new()) == AbsValue::Bool(Some(!value)) {
+            return None;
+        }
+        match cond {
+            Expr::BoolNot(a) => self.filter(state, a, !value),
+            Expr::BoolAnd(a, b) if value => {
+                let state = self.filter(state, a, true)?;
+                self.filter(state, b, true)
+            }
+            Expr::BoolAnd(a, b) => join_all([
+                self.filter(state.clone(), a, false),
+                self.filter(state, b, false),
+            ]),
+            // `a <= b`, or `b + 1 <= a` when it's false
+            Expr::NatLeq(a, b) if value => self.refine_leq(state, a, b, 0),
+            Expr::NatLeq(a, b) => self.refine_leq(state, b, a, 1),
+            Expr::Equal(a, b) if value => {
+                let (a_val, b_val) = (
+                    self.expr(&state, a, &mut Vec::new()),
+                    self.expr(&state, b, &mut Vec::new()),
+                );
+                match (a_val.interval(), b_val.interval()) {
+                    (Some(ai), Some(bi)) => {
+                        let both = ai.meet(bi)?;
+                        let state = self.narrow(state, a, &a_val, both);
+                        Some(self.narrow(state, b, &b_val, both))
+                    }
+                    _ => Some(state),
+                }
+            }
+            _ => Some(state),
+        }
+    }
+
+    // Restricts `state` to where `a + offset <= b`
+    fn refine_leq(&self, state: State, a: &Expr, b: &Expr, offset: i128) -> Option<State> {
+        let (a_val, b_val) = (
+            self.expr(&state, a, &mut Vec::new()),
+            self.expr(&state, b, &mut Vec::new()),
+        );
+        let (Some(ai), Some(bi)) = (a_val.interval(), b_val.interval()) else {
+            return Some(state);
+        };
+        let ai = ai.meet(Interval {
+            lo: ai.lo,
+            hi: bi.hi - offset,
+        })?;
+        let bi = bi.meet(Interval {
+            lo: ai.lo + offset,
+            hi: bi.hi,
+        })?;
+        let state = self.narrow(state, a, &a_val, ai);
+        Some(self.narrow(state, b, &b_val, bi))
+    }
+
+    // Only variables, and cells that only one variable can point to, are narrowed
+    fn narrow(&self, mut state: State, e: &Expr, v: &AbsValue, interval: Interval) -> State {
+        match e {
+            Expr::StoreRead(x) => {
+                state.store.insert(x.clone(), v.with_interval(interval));
+            }
+            Expr::HeapRead(p) => {
+                if let Some(site) = self.single_site(&state, p) {
+                    state.heap.insert(site, v.with_interval(interval));
+                }
+            }
+            _ => {}
+        }
+        state
+    }
+}
+
+// The store variables a statement that isn't a block binds or updates
+fn written(stmnt: &Statement) -> Vec<&Ident> {
+    match stmnt {
+        Statement::StoreAssign(x, _)
+        | Statement::HeapNew(x, _)
+        | Statement::HeapAlias(x, _)
+        | Statement::Borrow(x, _, _)
+        | Statement::StoreInput(x)
+        | Statement::HeapInput(x)
+        | Statement::Havoc(x) => vec![x],
+        Statement::AddressOf(p, x) => vec![p, x],
+        Statement::Destructure(ids, _) => ids.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Values that keep changing are widened, like at the head of a loop
+fn widen_writes(old: &Writes, new: Writes) -> Writes {
+    let mut widened = old.clone();
+    for (id, v) in new {
+        let v = old
+            .get(&id)
+            .map_or_else(|| v.clone(), |o| o.widen(&o.join(&v)));
+        widened.insert(id, v);
+    }
+    widened
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{analyze, AbsValue, Diagnostic, Interval};
+    use crate::parser::parse;
+    use crate::syntax::{Constant, Expr};
+
+    #[test]
+    fn test_loop() {
+        let program =
+            parse("let x <- 0\nlet inc = 25\nwhile *x < 100 do\nx <- *x + inc\nend\nprint *x")
+                .unwrap();
+        let analysis = analyze(&program, &HashMap::new());
+        assert!(analysis.diagnostics.is_empty());
+        // The loop stops once `*x` is over 100, which it can only pass by at most 25
+        let last = analysis.points.last().unwrap();
+        assert_eq!(last.stmnt, "print *x");
+        let state = last.state.as_ref().unwrap();
+        assert_eq!(state.heap[&0], AbsValue::Nat(Interval { lo: 101, hi: 125 }));
+        assert_eq!(state.to_string(), "inc = 25, x = {#0}, #0 = [101, 125]");
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let program =
+            parse("read x\nlet y = x + 1\nlet z = 0\nwhile z < 5 do\nlet y = 1\nend").unwrap();
+        let analysis = analyze(&program, &HashMap::new());
+        let cond = Expr::NatLeq(
+            Box::new(Expr::StoreRead("z".into())),
+            Box::new(Expr::Constant(Constant::Nat(5))),
+        );
+        assert!(matches!(&analysis.diagnostics[0], Diagnostic::Overflow(_)));
+        assert_eq!(
+            analysis.diagnostics[1],
+            Diagnostic::ConstantCondition("while", cond.clone(), true)
+        );
+        assert_eq!(analysis.points.len(), 5);
+
+        let program = parse("let z = 3\nwhile z < 2 do\nlet z = z + 1\nend").unwrap();
+        let analysis = analyze(&program, &HashMap::new());
+        assert!(matches!(
+            &analysis.diagnostics[..],
+            [Diagnostic::NeverRuns(_)]
+        ));
+        assert!(analysis.points[2].state.is_none());
+    }
+
+    #[test]
+    fn test_parallel() {
+        // `let a = 5` can run between `let a = 1` and `let b = a`
+        let source = "par\nlet a = 1\nlet b = a\n||\nlet a = 5\nend\nif b == 5 then\nprint 1\n\
+            else\nprint 2\nfi";
+        let analysis = analyze(&parse(source).unwrap(), &HashMap::new());
+        assert!(analysis.diagnostics.is_empty());
+        let state = |stmnt| {
+            let point = analysis.points.iter().find(|p| p.stmnt == stmnt).unwrap();
+            point.state.clone().unwrap()
+        };
+        assert_eq!(
+            state("let b = a").store["a"],
+            AbsValue::Nat(Interval { lo: 1, hi: 5 })
+        );
+        assert_eq!(
+            state("print 1").store["b"],
+            AbsValue::Nat(Interval::exact(5))
+        );
+    }
+
+    #[test]
+    fn test_cut_off() {
+        // The evaluator stops the loop after 6 iterations, with `x` at 6, so both branches can run
+        let source = "let x = 0\nwhile x < 100 do\nlet x = x + 1\nend\nif x == 101 then\n\
+            print 1\nelse\nprint 2\nfi";
+        let analysis = analyze(&parse(source).unwrap(), &HashMap::new());
+        assert!(analysis.diagnostics.is_empty());
+        let after = analysis.points[3].state.as_ref().unwrap();
+        assert_eq!(after.store["x"], AbsValue::Nat(Interval { lo: 6, hi: 101 }));
+        assert!(analysis.points[5].state.is_some());
+
+        let analysis = analyze(
+            &parse("while true do\nskip\nend\nprint 1").unwrap(),
+            &HashMap::new(),
+        );
+        assert!(analysis.points.last().unwrap().state.is_some());
+    }
+
+    #[test]
+    fn test_dead_enum() {
+        // The declaration never runs, but the match still needs the enum's variants
+        let source = "let x = 1\nif x < 0 then\nenum E = A | B\nelse\nskip\nfi\nlet e = E::A\n\
+            match e\ncase E::A then\nskip\ncase E::B then\nskip\nend";
+        let program = parse(source).unwrap();
+        crate::typechecker::typecheck(&program).unwrap();
+        let analysis = analyze(&program, &HashMap::new());
+        assert!(analysis.points[2].state.is_none());
+        assert!(analysis.points.last().unwrap().state.is_some());
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        // Inner loops are only analyzed for good once the loops around them are, so this doesn't
+        // run the innermost body exponentially often
+        let depth = 6;
+        let mut source = String::new();
+        for i in 0..depth {
+            source += &format!("let i{} = 0\nwhile i{} < 3 do\n", i, i);
+        }
+        source += "print i0\n";
+        for i in (0..depth).rev() {
+            source += &format!("let i{} = i{} + 1\nend\n", i, i);
+        }
+        let program = parse(&source).unwrap();
+        let start = std::time::Instant::now();
+        let analysis = analyze(&program, &HashMap::new());
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert!(analysis.diagnostics.is_empty());
+        let print = analysis.points[2 * depth].state.as_ref().unwrap();
+        assert_eq!(print.store["i0"], AbsValue::Nat(Interval { lo: 0, hi: 3 }));
+    }
+
+    #[test]
+    fn test_address_of() {
+        // Taking the address of `x` again points to the cell it already moved to
+        let source =
+            "pragma address_of\nlet x = 1\nlet p = &x\nlet q = &x\nq <- 5\nlet y = *p\nprint y";
+        let analysis = analyze(&parse(source).unwrap(), &HashMap::new());
+        let state = analysis.points.last().unwrap().state.as_ref().unwrap();
+        assert_eq!(state.store["p"], state.store["q"]);
+        assert_eq!(state.store["y"], AbsValue::Nat(Interval::exact(5)));
+        assert_eq!(state.heap.len(), 1);
+    }
+}
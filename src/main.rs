@@ -1,27 +1,211 @@
-use crate::error::ImpParseError;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::BufReader;
+use std::path::Path;
 
+use crate::evaluator::{Input, Runtime, Scoping, Stdout, Tokens, Value};
+use crate::oracle::{FirstChoice, Oracle, Seeded};
+use crate::syntax::Constant;
+use crate::typechecker::Type;
+use crate::warnings::WarningKind;
+
+pub mod arithmetic;
+pub mod consts;
+pub mod derivation;
 pub mod error;
 pub mod evaluator;
+pub mod interval;
+pub mod module;
+pub mod oracle;
+pub mod ownership;
 pub mod parser;
+pub mod points_to;
+pub mod security;
 pub mod syntax;
+pub mod termination;
 mod test;
 pub mod typechecker;
+pub mod typed;
+pub mod verifier;
+pub mod warnings;
+
+const USAGE: &str =
+    "Expected 'cargo run [analyze|verify] [--static-scoping] [--arg <name>=<number>]... [-D <name>=<value>]... [--input <file>] [--oracle first|random=<seed>|all] [--max-switches <n>] [--ownership] [--secure] [--derivation text|latex] [--typed] [--allow <warning>]... <file>'";
+
+// How many values `havoc` tries with `--oracle all`
+const HAVOC_VALUES: usize = 3;
+
+// How `choose` and `havoc` are resolved
+enum OracleChoice {
+    First,
+    Random(u64),
+    // Run the program once for every possible sequence of choices
+    All,
+}
+
+// How the typing derivation is printed
+enum DerivationFormat {
+    Text,
+    Latex,
+}
+
+// What to do with the program once it typechecks
+enum Command {
+    Run,
+    // Print the range of every variable at every statement
+    Analyze,
+    // Prove the program's contracts
+    Verify,
+}
+
+struct Options {
+    file: String,
+    command: Command,
+    scoping: Scoping,
+    // Store variables that are bound before the program starts
+    args: Vec<(String, u64)>,
+    // Overrides for the values of constants
+    defines: HashMap<String, Constant>,
+    // Where `read` gets its input from, stdin if this isn't set
+    input: Option<String>,
+    oracle: OracleChoice,
+    // How many times a `par` can switch between its branches
+    max_switches: Option<usize>,
+    // Whether to check that heap locations follow the ownership rules
+    ownership: bool,
+    // Whether to check that no `high` data flows into anything `low`
+    secure: bool,
+    // Prints the typing derivation of the program before running it
+    derivation: Option<DerivationFormat>,
+    // Prints the program with the type of every expression and binding before running it
+    typed: bool,
+    // The kinds of warnings that aren't printed
+    allowed: HashSet<WarningKind>,
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    match &args[..] {
-        [_, file] => {
-            let source = std::fs::read_to_string(file).unwrap();
-            run_str(&source);
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    });
+    run_file(Path::new(&options.file), &options);
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut args = args.peekable();
+    let command = match args
+        .next_if(|arg| arg == "analyze" || arg == "verify")
+        .as_deref()
+    {
+        Some("analyze") => Command::Analyze,
+        Some(_) => Command::Verify,
+        None => Command::Run,
+    };
+    let mut file = None;
+    let mut scoping = Scoping::Dynamic;
+    let mut arg_values = Vec::new();
+    let mut defines = HashMap::new();
+    let mut input = None;
+    let mut oracle = OracleChoice::First;
+    let mut max_switches = None;
+    let mut ownership = false;
+    let mut secure = false;
+    let mut derivation = None;
+    let mut typed = false;
+    let mut allowed = HashSet::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--static-scoping" => scoping = Scoping::Static,
+            "--ownership" => ownership = true,
+            "--secure" => secure = true,
+            "--typed" => typed = true,
+            "--arg" => {
+                let binding = args.next().ok_or("Missing value for --arg")?;
+                let (name, value) = binding
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected <name>=<number>, got '{}'", binding))?;
+                let value = value
+                    .parse()
+                    .map_err(|_| format!("Expected a number for '{}', got '{}'", name, value))?;
+                arg_values.push((name.to_owned(), value));
+            }
+            "-D" => {
+                let binding = args.next().ok_or("Missing value for -D")?;
+                let (name, value) = binding
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected <name>=<value>, got '{}'", binding))?;
+                defines.insert(name.to_owned(), parse_constant(value));
+            }
+            "--allow" => {
+                let value = args.next().ok_or("Missing warning for --allow")?;
+                allowed.insert(value.parse()?);
+            }
+            "--input" => input = Some(args.next().ok_or("Missing file for --input")?),
+            "--max-switches" => {
+                let value = args.next().ok_or("Missing value for --max-switches")?;
+                let value = value.parse().map_err(|_| {
+                    format!("Expected a number for --max-switches, got '{}'", value)
+                })?;
+                max_switches = Some(value);
+            }
+            "--derivation" => {
+                let value = args.next().ok_or("Missing format for --derivation")?;
+                derivation = Some(match value.as_str() {
+                    "text" => DerivationFormat::Text,
+                    "latex" => DerivationFormat::Latex,
+                    _ => return Err(format!("Unknown derivation format '{}'", value)),
+                });
+            }
+            "--oracle" => {
+                let value = args.next().ok_or("Missing value for --oracle")?;
+                oracle = match value.as_str() {
+                    "first" => OracleChoice::First,
+                    "all" => OracleChoice::All,
+                    _ => {
+                        let seed = value
+                            .strip_prefix("random=")
+                            .and_then(|seed| seed.parse().ok())
+                            .ok_or_else(|| format!("Unknown oracle '{}'", value))?;
+                        OracleChoice::Random(seed)
+                    }
+                };
+            }
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
         }
-        _ => eprintln!("Expected 'cargo run <file>'"),
+    }
+    Ok(Options {
+        file: file.ok_or("Missing program file")?,
+        command,
+        scoping,
+        args: arg_values,
+        defines,
+        input,
+        oracle,
+        max_switches,
+        ownership,
+        secure,
+        derivation,
+        typed,
+        allowed,
+    })
+}
+
+// Numbers and booleans are read as such, anything else is a string. Negative numbers are ints.
+fn parse_constant(value: &str) -> Constant {
+    match value {
+        "true" => Constant::Bool(true),
+        "false" => Constant::Bool(false),
+        _ => value
+            .parse()
+            .map(Constant::Nat)
+            .or_else(|_| value.parse().map(Constant::Int))
+            .unwrap_or_else(|_| Constant::Str(value.to_owned())),
     }
 }
 
-fn run_str(source: &str) {
-    let parsed = parser::parse(&source).unwrap_or_else(|e| {
-        let ImpParseError::Other(s) = e;
-        eprintln!("Parser Error:\n{}", s);
+fn run_file(path: &Path, options: &Options) {
+    let parsed = module::load_with(path, &options.defines).unwrap_or_else(|e| {
+        eprintln!("Parser Error:\n{}", e);
         std::process::exit(1);
     });
 
@@ -29,12 +213,144 @@ fn run_str(source: &str) {
     println!("===============");
     println!("{:?}", &parsed);
 
-    let typecheck = typechecker::typecheck(&parsed);
+    let mut sigma: HashMap<_, _> = options
+        .args
+        .iter()
+        .map(|(name, _)| (name.clone(), Type::Number))
+        .collect();
+    // A program that doesn't typecheck has no derivation, its error is reported below
+    if let Some(format) = &options.derivation {
+        if let Ok(derivation) = typechecker::typecheck_derived(&mut sigma.clone(), &parsed) {
+            println!("\nDerivation");
+            println!("===============");
+            match format {
+                DerivationFormat::Text => print!("{}", derivation),
+                DerivationFormat::Latex => print!("{}", derivation.to_latex()),
+            }
+        }
+    }
+    if let Ok(typed) = typechecker::typecheck_typed(&mut sigma.clone(), &parsed) {
+        if options.typed {
+            println!("\nTyped");
+            println!("===============");
+            println!("{}", typed);
+        }
+        for warning in warnings::check(&typed, &sigma, &options.allowed) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    // The arguments are all low
+    let mut labels = HashMap::new();
+    let typecheck = if options.ownership {
+        let owned = typechecker::typecheck_owned(&mut sigma, &parsed);
+        if options.secure {
+            owned.and_then(|_| security::check_with(&mut labels, &parsed))
+        } else {
+            owned
+        }
+    } else if options.secure {
+        typechecker::typecheck_secure(&mut sigma, &mut labels, &parsed)
+    } else {
+        typechecker::typecheck_with(&mut sigma, &parsed)
+    };
     match typecheck {
+        Ok(_) if matches!(options.command, Command::Verify) => {
+            let sigma = options
+                .args
+                .iter()
+                .map(|(name, _)| (name.clone(), Type::Number))
+                .collect();
+            println!("\nVerification");
+            println!("===============");
+            match verifier::verify(&parsed, &sigma) {
+                Ok(obligations) => {
+                    for obligation in obligations {
+                        println!("{}", obligation);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(_) if matches!(options.command, Command::Analyze) => {
+            let store = options
+                .args
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::Number(*value)))
+                .collect();
+            let analysis = interval::analyze(&parsed, &store);
+            println!("\nAnalysis");
+            println!("===============");
+            for (id, point) in analysis.points.iter().enumerate() {
+                match &point.state {
+                    Some(state) => println!("#{} {}\n    {}", id, point.stmnt, state),
+                    None => println!("#{} {}\n    unreachable", id, point.stmnt),
+                }
+            }
+            println!("\nTermination");
+            println!("===============");
+            for report in termination::check(&parsed, &store) {
+                println!("{}", report);
+            }
+            for diagnostic in analysis.diagnostics {
+                eprintln!("Warning: {}", diagnostic);
+            }
+            for aliased in points_to::analyze(&parsed, &store).aliased_updates {
+                eprintln!("Warning: {}", aliased);
+            }
+        }
         Ok(_) => {
             println!("\nEvaluated");
             println!("===============");
-            println!("{:?}", evaluator::eval_program(&parsed))
+            let mut input: Box<dyn Input> = match &options.input {
+                Some(path) => {
+                    let file = std::fs::File::open(path).unwrap_or_else(|e| {
+                        eprintln!("Could not open input file '{}': {}", path, e);
+                        std::process::exit(1);
+                    });
+                    Box::new(Tokens::new(BufReader::new(file)))
+                }
+                None => Box::new(Tokens::new(std::io::stdin().lock())),
+            };
+            let store = options
+                .args
+                .iter()
+                .map(|(name, value)| (name.clone(), Value::Number(*value)))
+                .collect();
+            let oracle: Box<dyn Oracle> = match options.oracle {
+                OracleChoice::First => Box::new(FirstChoice),
+                OracleChoice::Random(seed) => Box::new(Seeded::new(seed)),
+                OracleChoice::All => {
+                    // Every run needs the same input, so read all of it up front
+                    let tokens: VecDeque<_> = std::iter::from_fn(|| input.next_token()).collect();
+                    let runs = evaluator::eval_all(
+                        &parsed,
+                        &store,
+                        &tokens,
+                        options.scoping,
+                        HAVOC_VALUES,
+                        options.max_switches,
+                    );
+                    for (result, transcript) in runs {
+                        for line in transcript {
+                            println!("{}", line);
+                        }
+                        println!("===============");
+                        println!("{:?}", result);
+                    }
+                    return;
+                }
+            };
+            let mut output = Stdout;
+            let mut rt = Runtime::new(&mut output, input.as_mut());
+            rt.scoping = options.scoping;
+            rt.oracle = oracle;
+            rt.max_switches = options.max_switches;
+            let result = evaluator::eval_program_with(&parsed, store, &mut rt);
+            println!("===============");
+            println!("{:?}", result)
         }
         Err(e) => eprintln!("Error: {:?}", e),
     }
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::consts;
+use crate::error::ImpParseError;
+use crate::parser;
+use crate::syntax::{Constant, Expr, Ident, Pattern, Statement};
+use crate::typechecker::{qualify, Type};
+
+/// Parses the program in `path`, and replaces every `import` with the module it refers to.
+/// Imports are resolved relative to the file they are written in.
+pub fn load(path: &Path) -> Result<Statement, ImpParseError> {
+    load_with(path, &HashMap::new())
+}
+
+/// Like `load`, but `defines` overrides the values of constants declared in `path`. Constants of
+/// imported modules can't be overridden.
+pub fn load_with(
+    path: &Path,
+    defines: &HashMap<Ident, Constant>,
+) -> Result<Statement, ImpParseError> {
+    let mut loader = Loader {
+        defines: defines.clone(),
+        ..Loader::default()
+    };
+    loader.load(path, None)
+}
+
+#[derive(Default)]
+struct Loader {
+    // Overrides for the constants of the file that was loaded first
+    defines: HashMap<Ident, Constant>,
+    // Every module that has been loaded, by canonical path
+    loaded: HashMap<PathBuf, Statement>,
+    // The name every enum declared by a module is renamed to, by canonical path. A module's enums
+    // are qualified with the import names it was first loaded through, like `shapes.lib.Option`,
+    // which no other module can be loaded through.
+    enums: HashMap<PathBuf, HashMap<Ident, Ident>>,
+    // The chain of files currently being loaded, to detect import cycles
+    stack: Vec<PathBuf>,
+}
+
+impl Loader {
+    // `prefix` is what the module's enums are qualified with, or `None` for the file loaded first
+    fn load(&mut self, path: &Path, prefix: Option<&str>) -> Result<Statement, ImpParseError> {
+        let path = canonicalize(path)?;
+        if let Some(program) = self.loaded.get(&path) {
+            return Ok(program.clone());
+        }
+        if let Some(start) = self.stack.iter().position(|p| p == &path) {
+            let cycle = self.stack[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(ImpParseError::ImportCycle(cycle));
+        }
+
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| ImpParseError::ModuleNotFound(format!("{}: {}", path.display(), e)))?;
+        let program = parser::parse(&source)?;
+        let defines = if self.stack.is_empty() {
+            std::mem::take(&mut self.defines)
+        } else {
+            HashMap::new()
+        };
+        let program = consts::inline(program, &defines)?;
+
+        self.stack.push(path.clone());
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut imports = HashMap::new();
+        let resolved = self.resolve(program, dir, prefix, &mut imports);
+        self.stack.pop();
+        let resolved = resolved?;
+
+        // Every enum gets the same name wherever it's used, so that two modules can declare enums
+        // with the same name, and a module imported with two names still has a single enum
+        let own: HashMap<Ident, Ident> = declared_enums(&resolved)
+            .into_iter()
+            .map(|name| match prefix {
+                Some(prefix) => (name.clone(), qualify(prefix, &name)),
+                None => (name.clone(), name),
+            })
+            .collect();
+        let mut renames = own.clone();
+        for (name, module) in &imports {
+            for (e, renamed) in &self.enums[module] {
+                renames.insert(qualify(name, e), renamed.clone());
+            }
+        }
+        let resolved = rename_stmnt(resolved, &renames);
+        self.enums.insert(path.clone(), own);
+        self.loaded.insert(path, resolved.clone());
+        Ok(resolved)
+    }
+
+    // `imports` are the modules this file already imported, by name
+    fn resolve(
+        &mut self,
+        stmnt: Statement,
+        dir: &Path,
+        prefix: Option<&str>,
+        imports: &mut HashMap<Ident, PathBuf>,
+    ) -> Result<Statement, ImpParseError> {
+        match stmnt {
+            Statement::Import(file, name) => {
+                let path = dir.join(&file);
+                let name = match name {
+                    Some(name) => name,
+                    None => module_name(&path)?,
+                };
+                if name.contains('.') {
+                    return Err(ImpParseError::Other(format!(
+                        "Module name '{}' can't be qualified",
+                        name
+                    )));
+                }
+                // The second module's globals would replace the first one's
+                if imports.contains_key(&name) {
+                    return Err(ImpParseError::DuplicateModule(name));
+                }
+                let module_prefix = match prefix {
+                    Some(prefix) => qualify(prefix, &name),
+                    None => name.clone(),
+                };
+                let body = self.load(&path, Some(&module_prefix))?;
+                let canonical = canonicalize(&path)?;
+                let id = canonical.display().to_string();
+                imports.insert(name.clone(), canonical);
+                Ok(Statement::Module(name, id, Box::new(body)))
+            }
+            // Imports only appear at the start of a file, so they're never nested any deeper
+            Statement::Sequence(s1, s2) => Ok(Statement::Sequence(
+                Box::new(self.resolve(*s1, dir, prefix, imports)?),
+                Box::new(self.resolve(*s2, dir, prefix, imports)?),
+            )),
+            stmnt => Ok(stmnt),
+        }
+    }
+}
+
+// Enums are only declared at the top level, see `consts::inline`
+fn declared_enums(stmnt: &Statement) -> Vec<Ident> {
+    match stmnt {
+        Statement::Enum(name, _) => vec![name.clone()],
+        Statement::Sequence(s1, s2) => {
+            let mut names = declared_enums(s1);
+            names.extend(declared_enums(s2));
+            names
+        }
+        _ => Vec::new(),
+    }
+}
+
+// Renames every declaration of and reference to an enum in `renames`. Modules have been renamed
+// already, when they were loaded.
+fn rename_stmnt(stmnt: Statement, renames: &HashMap<Ident, Ident>) -> Statement {
+    let stmnt_box = |s: Box<Statement>| Box::new(rename_stmnt(*s, renames));
+    let expr = |e: Expr| rename_expr(e, renames);
+    match stmnt {
+        Statement::Enum(name, variants) => Statement::Enum(
+            rename(name, renames),
+            variants
+                .into_iter()
+                .map(|(variant, fields)| {
+                    let fields = fields.into_iter().map(|f| rename_type(f, renames));
+                    (variant, fields.collect())
+                })
+                .collect(),
+        ),
+        Statement::Match(e, cases) => Statement::Match(
+            expr(e),
+            cases
+                .into_iter()
+                .map(|(pattern, body)| {
+                    let pattern = match pattern {
+                        Pattern::Variant(name, variant, binders) => {
+                            Pattern::Variant(rename(name, renames), variant, binders)
+                        }
+                        Pattern::Wildcard => Pattern::Wildcard,
+                    };
+                    (pattern, rename_stmnt(body, renames))
+                })
+                .collect(),
+        ),
+        Statement::StoreAssign(id, e) => Statement::StoreAssign(id, expr(e)),
+        Statement::HeapNew(id, e) => Statement::HeapNew(id, expr(e)),
+        Statement::HeapUpdate(id, e) => Statement::HeapUpdate(id, expr(e)),
+        Statement::Destructure(ids, e) => Statement::Destructure(ids, expr(e)),
+        Statement::Const(id, e) => Statement::Const(id, expr(e)),
+        Statement::Print(e) => Statement::Print(expr(e)),
+        Statement::Assert(cond, message) => Statement::Assert(expr(cond), message.map(expr)),
+        Statement::Assume(cond) => Statement::Assume(expr(cond)),
+        Statement::Contract(clause, cond) => Statement::Contract(clause, expr(cond)),
+        Statement::Sequence(s1, s2) => Statement::Sequence(stmnt_box(s1), stmnt_box(s2)),
+        Statement::Conditional(cond, s1, s2) => {
+            Statement::Conditional(expr(cond), stmnt_box(s1), stmnt_box(s2))
+        }
+        Statement::While(cond, body) => Statement::While(expr(cond), stmnt_box(body)),
+        Statement::Scope(body) => Statement::Scope(stmnt_box(body)),
+        Statement::Labeled(level, body) => Statement::Labeled(level, stmnt_box(body)),
+        Statement::Choose(s1, s2) => Statement::Choose(stmnt_box(s1), stmnt_box(s2)),
+        Statement::Parallel(s1, s2) => Statement::Parallel(stmnt_box(s1), stmnt_box(s2)),
+        stmnt => stmnt,
+    }
+}
+
+fn rename_expr(expr: Expr, renames: &HashMap<Ident, Ident>) -> Expr {
+    let sub = |e: Box<Expr>| Box::new(rename_expr(*e, renames));
+    match expr {
+        Expr::Construct(name, variant, args) => Expr::Construct(
+            rename(name, renames),
+            variant,
+            args.into_iter().map(|e| rename_expr(e, renames)).collect(),
+        ),
+        Expr::Tuple(components) => Expr::Tuple(
+            components
+                .into_iter()
+                .map(|e| rename_expr(e, renames))
+                .collect(),
+        ),
+        Expr::NatAdd(a, b) => Expr::NatAdd(sub(a), sub(b)),
+        Expr::NatSub(a, b) => Expr::NatSub(sub(a), sub(b)),
+        Expr::NatLeq(a, b) => Expr::NatLeq(sub(a), sub(b)),
+        Expr::BoolAnd(a, b) => Expr::BoolAnd(sub(a), sub(b)),
+        Expr::StrConcat(a, b) => Expr::StrConcat(sub(a), sub(b)),
+        Expr::Equal(a, b) => Expr::Equal(sub(a), sub(b)),
+        Expr::IntNeg(a) => Expr::IntNeg(sub(a)),
+        Expr::ToInt(a) => Expr::ToInt(sub(a)),
+        Expr::ToNat(a) => Expr::ToNat(sub(a)),
+        Expr::BoolNot(a) => Expr::BoolNot(sub(a)),
+        Expr::StrLen(a) => Expr::StrLen(sub(a)),
+        Expr::StoreRead(_) | Expr::HeapRead(_) | Expr::Constant(_) => expr,
+    }
+}
+
+fn rename_type(ty: Type, renames: &HashMap<Ident, Ident>) -> Type {
+    match ty {
+        Type::Enum(name) => Type::Enum(rename(name, renames)),
+        Type::Location(pointee) => Type::location(rename_type(*pointee, renames)),
+        Type::Tuple(components) => Type::Tuple(
+            components
+                .into_iter()
+                .map(|c| rename_type(c, renames))
+                .collect(),
+        ),
+        ty => ty,
+    }
+}
+
+fn rename(name: Ident, renames: &HashMap<Ident, Ident>) -> Ident {
+    renames.get(&name).cloned().unwrap_or(name)
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, ImpParseError> {
+    path.canonicalize()
+        .map_err(|e| ImpParseError::ModuleNotFound(format!("{}: {}", path.display(), e)))
+}
+
+// Without `as`, a module is named after its file, so the file name needs to be an identifier
+fn module_name(path: &Path) -> Result<String, ImpParseError> {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let mut chars = stem.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(stem.to_owned())
+    } else {
+        Err(ImpParseError::Other(format!(
+            "'{}' is not a valid module name, use `import \"...\" as <name>`",
+            stem
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::error::ImpParseError;
+    use crate::evaluator::{eval_program, Value};
+    use crate::typechecker::typecheck;
+
+    #[test]
+    fn test_load_modules() {
+        let program = super::load(Path::new("examples/modules/main.imp")).unwrap();
+        typecheck(&program).unwrap();
+
+        // The counter module is imported twice, but only runs once
+        let (store, heap, transcript) = eval_program(&program).unwrap();
+        assert_eq!(
+            transcript,
+            vec!["loading counter".to_string(), "3".to_string()]
+        );
+        assert_eq!(store.get("counter.step"), Some(&Value::Number(2)));
+        assert_eq!(store.get("shapes.counter.step"), None);
+        assert_eq!(store.get("c.count"), store.get("counter.count"));
+        assert_eq!(heap.len(), 1);
+    }
+
+    #[test]
+    fn test_module_enums() {
+        // Both modules declare `Option`, and `m.Option` is the same enum as `maybe.Option`
+        let program = super::load(Path::new("examples/modules/enums.imp")).unwrap();
+        typecheck(&program).unwrap();
+        let (store, _, transcript) = eval_program(&program).unwrap();
+        assert_eq!(transcript, vec!["1".to_string(), "a".to_string()]);
+        assert_eq!(
+            store.get("maybe.found"),
+            Some(&Value::Variant(
+                "maybe.Option".into(),
+                "Some".into(),
+                vec![Value::Number(1)]
+            ))
+        );
+        assert_eq!(
+            store.get("none"),
+            Some(&Value::Variant("Option".into(), "Nothing".into(), vec![]))
+        );
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let result = super::load(Path::new("examples/modules/cycle_a.imp"));
+        match result {
+            Err(ImpParseError::ImportCycle(cycle)) => {
+                assert_eq!(cycle.len(), 3);
+                assert!(cycle[0].ends_with("cycle_a.imp"));
+                assert!(cycle[1].ends_with("cycle_b.imp"));
+                assert!(cycle[2].ends_with("cycle_a.imp"));
+            }
+            result => panic!("expected an import cycle, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_module() {
+        let result = super::load(Path::new("examples/modules/duplicate.imp"));
+        match result {
+            Err(ImpParseError::DuplicateModule(name)) => assert_eq!(name, "counter"),
+            result => panic!("expected a duplicate module, got {:?}", result),
+        }
+    }
+}
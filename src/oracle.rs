@@ -0,0 +1,132 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Resolves the nondeterminism in `choose` and `havoc` statements
+pub trait Oracle {
+    /// Picks one of `options` alternatives, numbered from 0
+    fn choose(&mut self, options: usize) -> usize;
+    /// The value `havoc` assigns to a nat
+    fn arbitrary_nat(&mut self) -> u64;
+    /// The value `havoc` assigns to an int
+    fn arbitrary_int(&mut self) -> i64;
+}
+
+// Lets the caller keep a handle on an oracle it gives to the evaluator
+impl<O: Oracle> Oracle for Rc<RefCell<O>> {
+    fn choose(&mut self, options: usize) -> usize {
+        self.borrow_mut().choose(options)
+    }
+
+    fn arbitrary_nat(&mut self) -> u64 {
+        self.borrow_mut().arbitrary_nat()
+    }
+
+    fn arbitrary_int(&mut self) -> i64 {
+        self.borrow_mut().arbitrary_int()
+    }
+}
+
+/// Always takes the first alternative, and havocs to 0
+pub struct FirstChoice;
+
+impl Oracle for FirstChoice {
+    fn choose(&mut self, _: usize) -> usize {
+        0
+    }
+
+    fn arbitrary_nat(&mut self) -> u64 {
+        0
+    }
+
+    fn arbitrary_int(&mut self) -> i64 {
+        0
+    }
+}
+
+/// Makes pseudo-random choices, which are the same every time for the same seed
+pub struct Seeded {
+    state: u64,
+}
+
+impl Seeded {
+    pub fn new(seed: u64) -> Self {
+        Seeded { state: seed }
+    }
+
+    // splitmix64, which is fine with any seed including 0
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Oracle for Seeded {
+    fn choose(&mut self, options: usize) -> usize {
+        (self.next() % options as u64) as usize
+    }
+
+    fn arbitrary_nat(&mut self) -> u64 {
+        self.next()
+    }
+
+    fn arbitrary_int(&mut self) -> i64 {
+        self.next() as i64
+    }
+}
+
+/// Enumerates every sequence of choices, one run of the program at a time. `havoc` only picks
+/// from the nats below `havoc_values`, or the ints of the same size around 0, to keep the number
+/// of runs finite.
+pub struct Exhaustive {
+    havoc_values: usize,
+    // The choice made at each point of the current run, and how many options there were
+    path: Vec<(usize, usize)>,
+    position: usize,
+}
+
+impl Exhaustive {
+    pub fn new(havoc_values: usize) -> Self {
+        Exhaustive {
+            havoc_values: havoc_values.max(1),
+            path: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Moves on to the next sequence of choices, returning false once every one has been tried
+    pub fn next_run(&mut self) -> bool {
+        // Choices after the point where the last run stopped were never made
+        self.path.truncate(self.position);
+        self.position = 0;
+        while let Some((choice, options)) = self.path.pop() {
+            if choice + 1 < options {
+                self.path.push((choice + 1, options));
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Oracle for Exhaustive {
+    fn choose(&mut self, options: usize) -> usize {
+        if self.position == self.path.len() {
+            self.path.push((0, options));
+        }
+        let (choice, _) = self.path[self.position];
+        self.position += 1;
+        choice
+    }
+
+    fn arbitrary_nat(&mut self) -> u64 {
+        self.choose(self.havoc_values) as u64
+    }
+
+    fn arbitrary_int(&mut self) -> i64 {
+        let n = self.havoc_values as i64;
+        self.choose(2 * self.havoc_values - 1) as i64 - (n - 1)
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use crate::error::TypeError;
+use crate::syntax::{Expr, Ident, Statement};
+use crate::typechecker::Type;
+
+/// Checks that `program`, which must already typecheck from `sigma`, follows Rust's rules for heap
+/// locations: `q = p` moves the location out of `p`, `q = &p` and `q = &mut p` borrow it until the
+/// end of the block `q` is bound in, and a location can't be written through a shared borrow, or
+/// used at all while it's mutably borrowed.
+///
+/// Locations that are only reachable through the fields of an enum aren't tracked.
+pub fn check(program: &Statement, sigma: &HashMap<Ident, Type>) -> Result<(), TypeError> {
+    let vars = sigma
+        .iter()
+        .filter(|(_, ty)| matches!(ty, Type::Location(_)))
+        .map(|(id, _)| (id.clone(), Access::Owned))
+        .collect();
+    Ownership { vars }.stmnt(program)
+}
+
+// How a variable holding a location can be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Access {
+    Owned,
+    Moved,
+    // Borrows of the location owned by another variable
+    Shared(Ident),
+    Mutable(Ident),
+}
+
+type Vars = HashMap<Ident, Access>;
+
+#[derive(Clone, Default)]
+struct Ownership {
+    vars: Vars,
+}
+
+impl Ownership {
+    fn stmnt(&mut self, stmnt: &Statement) -> Result<(), TypeError> {
+        match stmnt {
+            Statement::StoreAssign(_, expr)
+            | Statement::Destructure(_, expr)
+            | Statement::Print(expr)
+            | Statement::Assume(expr)
+            | Statement::Contract(_, expr)
+            | Statement::Const(_, expr) => self.expr(expr),
+            Statement::HeapNew(id, expr) => {
+                self.expr(expr)?;
+                self.bind(id, Access::Owned)
+            }
+            Statement::HeapInput(id) => self.bind(id, Access::Owned),
+            Statement::HeapUpdate(id, expr) => {
+                self.expr(expr)?;
+                self.write(id)
+            }
+            Statement::HeapAlias(alias, id) => {
+                let access = self.take(id)?;
+                self.bind(alias, access)
+            }
+            Statement::Borrow(alias, id, mutable) => self.borrow(alias, id, *mutable),
+            // `p` points to the cell `x` moved into, and is the only way to change it
+            Statement::AddressOf(p, x) => {
+                self.vars.entry(x.clone()).or_insert(Access::Owned);
+                self.borrow(p, x, true)
+            }
+            Statement::Assert(cond, message) => {
+                self.expr(cond)?;
+                message.as_ref().map_or(Ok(()), |m| self.expr(m))
+            }
+            Statement::Labeled(_, stmnt) => self.stmnt(stmnt),
+            Statement::Sequence(s1, s2) => {
+                self.stmnt(s1)?;
+                self.stmnt(s2)
+            }
+            Statement::Conditional(cond, then_s, else_s) => {
+                self.expr(cond)?;
+                let branches = vec![self.block(then_s)?, self.block(else_s)?];
+                self.join(branches, true)
+            }
+            Statement::Choose(s1, s2) => {
+                let branches = vec![self.block(s1)?, self.block(s2)?];
+                self.join(branches, true)
+            }
+            Statement::Match(expr, cases) => {
+                self.expr(expr)?;
+                let branches = cases
+                    .iter()
+                    .map(|(_, body)| self.block(body))
+                    .collect::<Result<_, _>>()?;
+                self.join(branches, true)
+            }
+            // The body can run any number of times, so keep going until it stops changing anything
+            Statement::While(cond, body) => loop {
+                self.expr(cond)?;
+                let before = self.vars.clone();
+                let after = self.block(body)?;
+                self.join(vec![before.clone(), after], false)?;
+                if self.vars == before {
+                    return Ok(());
+                }
+            },
+            Statement::Scope(body) => {
+                let inner = self.block(body)?;
+                self.join(vec![inner], false)
+            }
+            // The branches can run in either order
+            Statement::Parallel(s1, s2) => {
+                let mut swapped = self.clone();
+                swapped.stmnt(s2)?;
+                swapped.stmnt(s1)?;
+                self.stmnt(s1)?;
+                self.stmnt(s2)
+            }
+            // A module only shares its globals, which it owns
+            Statement::Module(_, _, body) => Ownership::default().stmnt(body),
+            Statement::StoreInput(_)
+            | Statement::Havoc(_)
+            | Statement::Import(_, _)
+            | Statement::Enum(_, _)
+            | Statement::Skip => Ok(()),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), TypeError> {
+        match expr {
+            Expr::HeapRead(id) => self.read(id),
+            // A location can only be read from the store to put it in an enum, which takes it
+            Expr::StoreRead(id) if self.vars.contains_key(id) => self.take(id).map(|_| ()),
+            Expr::StoreRead(_) | Expr::Constant(_) => Ok(()),
+            Expr::NatAdd(a, b)
+            | Expr::NatSub(a, b)
+            | Expr::NatLeq(a, b)
+            | Expr::BoolAnd(a, b)
+            | Expr::StrConcat(a, b)
+            | Expr::Equal(a, b) => {
+                self.expr(a)?;
+                self.expr(b)
+            }
+            Expr::IntNeg(a)
+            | Expr::ToInt(a)
+            | Expr::ToNat(a)
+            | Expr::BoolNot(a)
+            | Expr::StrLen(a) => self.expr(a),
+            Expr::Tuple(components) | Expr::Construct(_, _, components) => {
+                components.iter().try_for_each(|e| self.expr(e))
+            }
+        }
+    }
+
+    // Variables that aren't tracked, like the fields bound by a `match`, own their location
+    fn access(&self, id: &Ident) -> Access {
+        self.vars.get(id).cloned().unwrap_or(Access::Owned)
+    }
+
+    fn borrowed(&self, owner: &Ident, only_mutable: bool) -> bool {
+        self.vars.values().any(|access| match access {
+            Access::Shared(o) => !only_mutable && o == owner,
+            Access::Mutable(o) => o == owner,
+            _ => false,
+        })
+    }
+
+    fn read(&self, id: &Ident) -> Result<(), TypeError> {
+        match self.access(id) {
+            Access::Moved => Err(TypeError::UseAfterMove(id.clone())),
+            Access::Owned if self.borrowed(id, true) => Err(TypeError::BorrowConflict(id.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&self, id: &Ident) -> Result<(), TypeError> {
+        match self.access(id) {
+            Access::Moved => Err(TypeError::UseAfterMove(id.clone())),
+            Access::Shared(_) => Err(TypeError::WriteThroughShared(id.clone())),
+            Access::Owned if self.borrowed(id, false) => Err(TypeError::BorrowConflict(id.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    // Moves the location out of `id`, except for shared borrows, which are copied
+    fn take(&mut self, id: &Ident) -> Result<Access, TypeError> {
+        let access = self.access(id);
+        match access {
+            Access::Moved => return Err(TypeError::UseAfterMove(id.clone())),
+            Access::Owned if self.borrowed(id, false) => {
+                return Err(TypeError::BorrowConflict(id.clone()))
+            }
+            Access::Shared(_) => return Ok(access),
+            Access::Owned | Access::Mutable(_) => {}
+        }
+        self.vars.insert(id.clone(), Access::Moved);
+        Ok(access)
+    }
+
+    fn borrow(&mut self, alias: &Ident, id: &Ident, mutable: bool) -> Result<(), TypeError> {
+        let access = match (self.access(id), mutable) {
+            (Access::Moved, _) => return Err(TypeError::UseAfterMove(id.clone())),
+            (Access::Owned, false) if !self.borrowed(id, true) => Access::Shared(id.clone()),
+            (Access::Owned, true) if !self.borrowed(id, false) => Access::Mutable(id.clone()),
+            (Access::Shared(owner), false) => Access::Shared(owner),
+            _ => return Err(TypeError::BorrowConflict(id.clone())),
+        };
+        self.bind(alias, access)
+    }
+
+    // Binding over a location that's still borrowed would leave the borrows pointing elsewhere
+    fn bind(&mut self, id: &Ident, access: Access) -> Result<(), TypeError> {
+        if self.access(id) == Access::Owned && self.borrowed(id, false) {
+            return Err(TypeError::BorrowConflict(id.clone()));
+        }
+        self.vars.insert(id.clone(), access);
+        Ok(())
+    }
+
+    fn block(&self, stmnt: &Statement) -> Result<Vars, TypeError> {
+        let mut inner = self.clone();
+        inner.stmnt(stmnt)?;
+        Ok(inner.vars)
+    }
+
+    // Combines the results of blocks that may have run. A variable is only usable afterwards if
+    // every block left it the same way, and the borrows of variables that go out of scope end.
+    // With `leak_new`, the variables that every block bound stay bound, like in the typechecker.
+    fn join(&mut self, branches: Vec<Vars>, leak_new: bool) -> Result<(), TypeError> {
+        let mut joined = Vars::new();
+        let mut names: Vec<&Ident> = self.vars.keys().collect();
+        if leak_new {
+            names.extend(branches[0].keys().filter(|id| !self.vars.contains_key(*id)));
+        }
+        for id in names {
+            let accesses: Option<Vec<&Access>> = branches.iter().map(|b| b.get(id)).collect();
+            let access = match accesses {
+                Some(accesses) if accesses.iter().all(|a| *a == accesses[0]) => accesses[0].clone(),
+                Some(_) => Access::Moved,
+                None => continue,
+            };
+            joined.insert(id.clone(), access);
+        }
+        for access in joined.values() {
+            if let Access::Shared(owner) | Access::Mutable(owner) = access {
+                if !joined.contains_key(owner) {
+                    return Err(TypeError::BorrowOutlivesOwner(owner.clone()));
+                }
+            }
+        }
+        self.vars = joined;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::error::TypeError;
+    use crate::parser::parse;
+
+    fn check(source: &str) -> Result<(), TypeError> {
+        let program = parse(source).unwrap();
+        super::check(&program, &HashMap::new())
+    }
+
+    #[test]
+    fn test_moves() {
+        assert!(check("let p <- 1\nq = p\nq <- 2").is_ok());
+        assert!(
+            matches!(check("let p <- 1\nq = p\nprint *p"), Err(TypeError::UseAfterMove(p)) if p == "p")
+        );
+        // Moving in a loop moves again on the next iteration
+        let program = "let p <- 1\nlet q <- 2\nwhile true do\nr = q\nq = p\nend";
+        assert!(matches!(check(program), Err(TypeError::UseAfterMove(p)) if p == "p"));
+    }
+
+    #[test]
+    fn test_borrows() {
+        assert!(check("let p <- 1\nr = &p\ns = &r\nprint *r + *s + *p").is_ok());
+        assert!(matches!(
+            check("let p <- 1\nr = &p\nr <- 2"),
+            Err(TypeError::WriteThroughShared(_))
+        ));
+        assert!(matches!(
+            check("let p <- 1\nr = &mut p\nprint *p"),
+            Err(TypeError::BorrowConflict(_))
+        ));
+        assert!(matches!(
+            check("let p <- 1\nr = &p\np <- 2"),
+            Err(TypeError::BorrowConflict(_))
+        ));
+
+        // Borrows end with the block they're bound in
+        assert!(check("let p <- 1\ndo\nr = &mut p\nr <- 2\nend\np <- 3").is_ok());
+        let program = "let r <- 0\ndo\nlet p <- 1\nr = &p\nend";
+        assert!(matches!(check(program), Err(TypeError::BorrowOutlivesOwner(p)) if p == "p"));
+    }
+}
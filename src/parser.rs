@@ -1,3 +1,5 @@
+use std::iter::Peekable;
+
 use pest::{pratt_parser::PrattParser, Parser};
 use pest_derive::Parser;
 
@@ -5,7 +7,8 @@ use lazy_static::lazy_static;
 
 use crate::{
     error::ImpParseError,
-    syntax::{Constant, Expr, Statement},
+    syntax::{Clause, Constant, Expr, Level, Pattern, Statement},
+    typechecker::Type,
 };
 
 #[derive(Parser)]
@@ -17,41 +20,96 @@ lazy_static! {
         use pest::pratt_parser::{Assoc::*, Op};
 
         PrattParser::new()
-            .op(Op::infix(Rule::add, Left))
+            .op(Op::infix(Rule::and, Left))
+            .op(Op::infix(Rule::eq, Left))
+            .op(Op::infix(Rule::add, Left)
+                | Op::infix(Rule::sub, Left)
+                | Op::infix(Rule::concat, Left))
             .op(Op::infix(Rule::less, Left))
-            .op(Op::prefix(Rule::not))
+            .op(Op::prefix(Rule::not)
+                | Op::prefix(Rule::neg)
+                | Op::prefix(Rule::len)
+                | Op::prefix(Rule::to_int)
+                | Op::prefix(Rule::to_nat))
     };
 }
 
 pub fn parse(source: &str) -> Result<Statement, ImpParseError> {
     let pairs =
         ImpParser::parse(Rule::program, source).map_err(|e| ImpParseError::Other(e.to_string()))?;
-    
-    let statements = pairs.filter_map(|pair| match pair.as_rule() {
-        Rule::EOI => None,
-        _ => Some(build_stmnt(pair)),
-    });
-
-    Ok(statements.fold(Statement::Skip, |acc, next| {
-        Statement::Sequence(Box::new(acc), Box::new(next))
+
+    // `&x` changes how store variables work, so programs have to opt in to it
+    let mut address_of = false;
+    for pair in pairs.clone().filter(|pair| pair.as_rule() == Rule::pragma) {
+        match pair.into_inner().as_str() {
+            "address_of" => address_of = true,
+            pragma => return Err(ImpParseError::Other(format!("Unknown pragma '{}'", pragma))),
+        }
+    }
+    if !address_of
+        && pairs
+            .clone()
+            .flatten()
+            .any(|pair| pair.as_rule() == Rule::address_of)
+    {
+        return Err(ImpParseError::Other(
+            "`&x` can only be used after `pragma address_of`".to_owned(),
+        ));
+    }
+
+    // The program is a list of pragmas and imports, then a single top-level block followed by EOI
+    let mut imports = Vec::new();
+    let mut body = Statement::Skip;
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::import => imports.push(build_stmnt(pair)),
+            Rule::block => body = build_stmnt(pair),
+            _ => {}
+        }
+    }
+
+    Ok(imports.into_iter().rev().fold(body, |acc, import| {
+        Statement::Sequence(Box::new(import), Box::new(acc))
     }))
 }
 
 pub fn build_stmnt(pair: pest::iterators::Pair<Rule>) -> Statement {
     match pair.as_rule() {
-        Rule::store_assign => {
+        Rule::const_decl => {
             let mut pairs = pair.into_inner();
             let ident = pairs.next().unwrap().as_str().to_owned();
+            let rhs = build_expr(pairs.next().unwrap());
+            Statement::Const(ident, rhs)
+        }
+        Rule::address_of => {
+            let mut pairs = pair.into_inner();
+            let ident = pairs.next().unwrap().as_str().to_owned();
+            let target = pairs.next().unwrap().as_str().to_owned();
+            Statement::AddressOf(ident, target)
+        }
+        Rule::destructure => {
+            let mut pairs = pair.into_inner().peekable();
+            let level = build_level(&mut pairs);
+            let mut pairs = pairs.collect::<Vec<_>>();
+            let rhs = build_expr(pairs.pop().unwrap());
+            let idents = pairs.iter().map(|pair| pair.as_str().to_owned()).collect();
+            labeled(level, Statement::Destructure(idents, rhs))
+        }
+        Rule::store_assign => {
+            let mut pairs = pair.into_inner().peekable();
+            let level = build_level(&mut pairs);
+            let ident = pairs.next().unwrap().as_str().to_owned();
             let rhs_pair = pairs.next().unwrap();
             let rhs = build_expr(rhs_pair);
-            Statement::StoreAssign(ident, rhs)
+            labeled(level, Statement::StoreAssign(ident, rhs))
         }
         Rule::heap_new => {
-            let mut pairs = pair.into_inner();
+            let mut pairs = pair.into_inner().peekable();
+            let level = build_level(&mut pairs);
             let ident = pairs.next().unwrap().as_str().to_owned();
             let rhs_pair = pairs.next().unwrap();
             let rhs = build_expr(rhs_pair);
-            Statement::HeapNew(ident, rhs)
+            labeled(level, Statement::HeapNew(ident, rhs))
         }
         Rule::heap_update => {
             let mut pairs = pair.into_inner();
@@ -66,6 +124,13 @@ pub fn build_stmnt(pair: pest::iterators::Pair<Rule>) -> Statement {
             let rhs_ident = pairs.next().unwrap().as_str().to_owned();
             Statement::HeapAlias(ident, rhs_ident)
         }
+        Rule::borrow => {
+            let pairs: Vec<_> = pair.into_inner().collect();
+            let ident = pairs[0].as_str().to_owned();
+            let mutable = pairs[1].as_rule() == Rule::mutable;
+            let rhs_ident = pairs[pairs.len() - 1].as_str().to_owned();
+            Statement::Borrow(ident, rhs_ident, mutable)
+        }
         Rule::conditional => {
             let mut pairs = pair.into_inner();
             let cond_pair = pairs.next().unwrap();
@@ -80,10 +145,28 @@ pub fn build_stmnt(pair: pest::iterators::Pair<Rule>) -> Statement {
             let mut pairs = pair.into_inner();
             let cond_pair = pairs.next().unwrap();
             let cond_expr = build_expr(cond_pair);
-            let body_stmnt_pair = pairs.next().unwrap();
-            let body_stmnt = build_stmnt(body_stmnt_pair);
+            let mut invariants = Vec::new();
+            let mut body_stmnt_pair = pairs.next().unwrap();
+            while body_stmnt_pair.as_rule() == Rule::invariant {
+                invariants.push(build_expr(body_stmnt_pair.into_inner().next().unwrap()));
+                body_stmnt_pair = pairs.next().unwrap();
+            }
+            // The invariants are checked at the start of the body
+            let body_stmnt =
+                invariants
+                    .into_iter()
+                    .rfold(build_stmnt(body_stmnt_pair), |body, inv| {
+                        Statement::Sequence(
+                            Box::new(Statement::Contract(Clause::Invariant, inv)),
+                            Box::new(body),
+                        )
+                    });
             Statement::While(cond_expr, Box::new(body_stmnt))
         }
+        Rule::scope => {
+            let body_stmnt = build_stmnt(pair.into_inner().next().unwrap());
+            Statement::Scope(Box::new(body_stmnt))
+        }
         Rule::block => {
             let pairs = pair.into_inner();
             let statements = pairs.filter_map(|pair| match pair.as_rule() {
@@ -95,6 +178,86 @@ pub fn build_stmnt(pair: pest::iterators::Pair<Rule>) -> Statement {
                 Statement::Sequence(Box::new(acc), Box::new(next))
             })
         }
+        Rule::choose => {
+            let mut pairs = pair.into_inner();
+            let first = build_stmnt(pairs.next().unwrap());
+            let second = build_stmnt(pairs.next().unwrap());
+            Statement::Choose(Box::new(first), Box::new(second))
+        }
+        Rule::parallel => {
+            let mut pairs = pair.into_inner();
+            let first = build_stmnt(pairs.next().unwrap());
+            let second = build_stmnt(pairs.next().unwrap());
+            Statement::Parallel(Box::new(first), Box::new(second))
+        }
+        Rule::havoc => {
+            let ident = pair.into_inner().next().unwrap().as_str().to_owned();
+            Statement::Havoc(ident)
+        }
+        Rule::print => {
+            let expr = build_expr(pair.into_inner().next().unwrap());
+            Statement::Print(expr)
+        }
+        Rule::store_input => {
+            let mut pairs = pair.into_inner().peekable();
+            let level = build_level(&mut pairs);
+            let ident = pairs.next().unwrap().as_str().to_owned();
+            labeled(level, Statement::StoreInput(ident))
+        }
+        Rule::heap_input => {
+            let mut pairs = pair.into_inner().peekable();
+            let level = build_level(&mut pairs);
+            let ident = pairs.next().unwrap().as_str().to_owned();
+            labeled(level, Statement::HeapInput(ident))
+        }
+        Rule::assert => {
+            let mut pairs = pair.into_inner();
+            let cond_expr = build_expr(pairs.next().unwrap());
+            let message = pairs.next().map(build_expr);
+            Statement::Assert(cond_expr, message)
+        }
+        Rule::contract => {
+            let mut pairs = pair.into_inner();
+            let clause = match pairs.next().unwrap().as_rule() {
+                Rule::requires => Clause::Requires,
+                _ => Clause::Ensures,
+            };
+            Statement::Contract(clause, build_expr(pairs.next().unwrap()))
+        }
+        Rule::assume => {
+            let cond_expr = build_expr(pair.into_inner().next().unwrap());
+            Statement::Assume(cond_expr)
+        }
+        Rule::import => {
+            let mut pairs = pair.into_inner();
+            let path = pairs.next().unwrap().into_inner().next().unwrap().as_str();
+            let name = pairs.next().map(|pair| pair.as_str().to_owned());
+            Statement::Import(unescape(path), name)
+        }
+        Rule::enum_decl => {
+            let mut pairs = pair.into_inner();
+            let name = pairs.next().unwrap().as_str().to_owned();
+            let variants = pairs
+                .map(|variant| {
+                    let mut pairs = variant.into_inner();
+                    let name = pairs.next().unwrap().as_str().to_owned();
+                    (name, pairs.map(build_type).collect())
+                })
+                .collect();
+            Statement::Enum(name, variants)
+        }
+        Rule::match_stmnt => {
+            let mut pairs = pair.into_inner();
+            let expr = build_expr(pairs.next().unwrap());
+            let cases = pairs
+                .map(|case| {
+                    let mut pairs = case.into_inner();
+                    let pattern = build_pattern(pairs.next().unwrap());
+                    (pattern, build_stmnt(pairs.next().unwrap()))
+                })
+                .collect();
+            Statement::Match(expr, cases)
+        }
         Rule::skip => Statement::Skip,
         _ => panic!("{:?}", pair.as_rule()),
     }
@@ -109,28 +272,121 @@ pub fn build_expr(pair: pest::iterators::Pair<Rule>) -> Expr {
             _ => unreachable!(),
         },
         Rule::number => {
-            let n = pair.as_str().parse::<i64>().unwrap();
+            let n = pair.as_str().parse::<u64>().unwrap();
             Expr::Constant(Constant::Nat(n))
         }
+        Rule::string => {
+            let inner = pair.into_inner().next().unwrap().as_str();
+            Expr::Constant(Constant::Str(unescape(inner)))
+        }
+        Rule::construct => {
+            let mut pairs = pair.into_inner();
+            let name = pairs.next().unwrap().as_str().to_owned();
+            let variant = pairs.next().unwrap().as_str().to_owned();
+            Expr::Construct(name, variant, pairs.map(build_expr).collect())
+        }
+        Rule::tuple => Expr::Tuple(pair.into_inner().map(build_expr).collect()),
         Rule::ident => Expr::StoreRead(pair.as_str().to_string()),
         Rule::deref_ident => Expr::HeapRead(pair.as_str().strip_prefix('*').unwrap().to_string()),
         Rule::compound_expr => PRATT_PARSER
             .map_primary(|primary| build_expr(primary))
             .map_prefix(|op, rhs| match op.as_rule() {
                 Rule::not => Expr::BoolNot(Box::new(rhs)),
+                Rule::len => Expr::StrLen(Box::new(rhs)),
+                // A minus in front of a literal is a negative int literal
+                Rule::neg => match rhs {
+                    Expr::Constant(Constant::Nat(n)) if n <= i64::MIN.unsigned_abs() => {
+                        Expr::Constant(Constant::Int((n as i64).wrapping_neg()))
+                    }
+                    rhs => Expr::IntNeg(Box::new(rhs)),
+                },
+                // And `int` in front of one is a positive int literal
+                Rule::to_int => match rhs {
+                    Expr::Constant(Constant::Nat(n)) if n <= i64::MAX as u64 => {
+                        Expr::Constant(Constant::Int(n as i64))
+                    }
+                    rhs => Expr::ToInt(Box::new(rhs)),
+                },
+                Rule::to_nat => Expr::ToNat(Box::new(rhs)),
                 _ => unreachable!(),
             })
-        .map_infix(|lhs, op, rhs| match op.as_rule() {
-            Rule::add => Expr::NatAdd(Box::new(lhs), Box::new(rhs)),
-            Rule::less => Expr::NatLeq(Box::new(lhs), Box::new(rhs)),
-            Rule::and => Expr::BoolAnd(Box::new(lhs), Box::new(rhs)),
-            _ => unreachable!(),
-        })
-        .parse(pair.into_inner()),
+            .map_infix(|lhs, op, rhs| match op.as_rule() {
+                Rule::add => Expr::NatAdd(Box::new(lhs), Box::new(rhs)),
+                Rule::sub => Expr::NatSub(Box::new(lhs), Box::new(rhs)),
+                Rule::concat => Expr::StrConcat(Box::new(lhs), Box::new(rhs)),
+                Rule::eq => Expr::Equal(Box::new(lhs), Box::new(rhs)),
+                Rule::less => Expr::NatLeq(Box::new(lhs), Box::new(rhs)),
+                Rule::and => Expr::BoolAnd(Box::new(lhs), Box::new(rhs)),
+                _ => unreachable!(),
+            })
+            .parse(pair.into_inner()),
+        _ => panic!("{:?}", pair.as_rule()),
+    }
+}
+
+pub fn build_type(pair: pest::iterators::Pair<Rule>) -> Type {
+    match pair.as_rule() {
+        Rule::nat_ty => Type::Number,
+        Rule::int_ty => Type::Int,
+        Rule::str_ty => Type::String,
+        Rule::ref_ty => Type::location(build_type(pair.into_inner().next().unwrap())),
+        Rule::tuple_ty => Type::Tuple(pair.into_inner().map(build_type).collect()),
+        Rule::enum_name => Type::Enum(pair.as_str().to_owned()),
         _ => panic!("{:?}", pair.as_rule()),
     }
 }
 
+// Bindings can start with a `high` or `low` label
+fn build_level(pairs: &mut Peekable<pest::iterators::Pairs<Rule>>) -> Option<Level> {
+    let pair = pairs.next_if(|pair| pair.as_rule() == Rule::level)?;
+    Some(match pair.as_str() {
+        "high" => Level::High,
+        _ => Level::Low,
+    })
+}
+
+fn labeled(level: Option<Level>, stmnt: Statement) -> Statement {
+    match level {
+        Some(level) => Statement::Labeled(level, Box::new(stmnt)),
+        None => stmnt,
+    }
+}
+
+fn build_pattern(pair: pest::iterators::Pair<Rule>) -> Pattern {
+    let mut pairs = pair.into_inner();
+    let first = pairs.next().unwrap();
+    if first.as_rule() == Rule::wildcard {
+        return Pattern::Wildcard;
+    }
+    let variant = pairs.next().unwrap().as_str().to_owned();
+    let fields = pairs
+        .map(|pair| match pair.as_rule() {
+            Rule::wildcard => None,
+            _ => Some(pair.as_str().to_owned()),
+        })
+        .collect();
+    Pattern::Variant(first.as_str().to_owned(), variant, fields)
+}
+
+// The grammar only allows \", \\, \n and \t as escapes
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(c) => result.push(c),
+            None => unreachable!(),
+        }
+    }
+    result
+}
+
 #[cfg(test)]
 mod test {
     use pest::Parser;
@@ -138,8 +394,10 @@ mod test {
     use crate::syntax::{
         Constant::*,
         Expr::{self, *},
+        Level, Pattern,
         Statement::*,
     };
+    use crate::typechecker::Type;
 
     fn parse_expr(source: &str) -> Expr {
         let pairs = super::ImpParser::parse(super::Rule::expr, source)
@@ -163,6 +421,53 @@ mod test {
         assert_eq!(parse_expr(s), ex);
     }
 
+    #[test]
+    fn test_expr_string() {
+        let s = "len \"a\\\"b\" ++ x == 3";
+        let ex = Equal(
+            Box::new(StrConcat(
+                Box::new(StrLen(Box::new(Constant(Str("a\"b".to_string()))))),
+                Box::new(StoreRead("x".to_string())),
+            )),
+            Box::new(Constant(Nat(3))),
+        );
+
+        assert_eq!(parse_expr(s), ex);
+    }
+
+    #[test]
+    fn test_constant_round_trip() {
+        let constants = [
+            Nat(0),
+            Nat(u64::MAX),
+            Int(0),
+            Int(5),
+            Int(-5),
+            Int(i64::MAX),
+            Int(i64::MIN),
+            Bool(false),
+            Str("a \"b\"\n\\".to_string()),
+        ];
+        for c in constants {
+            assert_eq!(parse_expr(&c.to_string()), Constant(c));
+        }
+    }
+
+    #[test]
+    fn test_expr_int() {
+        // A minus in front of a literal makes an int, subtraction binds like addition
+        let s = "int x - -3 - -y";
+        let ex = NatSub(
+            Box::new(NatSub(
+                Box::new(ToInt(Box::new(StoreRead("x".to_string())))),
+                Box::new(Constant(Int(-3))),
+            )),
+            Box::new(IntNeg(Box::new(StoreRead("y".to_string())))),
+        );
+
+        assert_eq!(parse_expr(s), ex);
+    }
+
     #[test]
     fn test_stmnt_basic() {
         let source = std::fs::read_to_string("examples/test_file.imp").unwrap();
@@ -170,27 +475,237 @@ mod test {
 
         let stmt = Sequence(
             Box::new(Sequence(
-                    Box::new(Sequence(
-                            Box::new(Skip),
-                            Box::new(HeapNew("x".to_string(), Constant(Nat(0)))),
-                    )),
-                    Box::new(StoreAssign("inc".to_string(), Constant(Nat(25)))),
+                Box::new(Sequence(
+                    Box::new(Skip),
+                    Box::new(HeapNew("x".to_string(), Constant(Nat(0)))),
+                )),
+                Box::new(StoreAssign("inc".to_string(), Constant(Nat(25)))),
             )),
             Box::new(While(
-                    NatLeq(
-                        Box::new(HeapRead("x".to_string())),
-                        Box::new(Constant(Nat(100))),
-                    ),
+                NatLeq(
+                    Box::new(HeapRead("x".to_string())),
+                    Box::new(Constant(Nat(100))),
+                ),
+                Box::new(Sequence(
+                    Box::new(Skip),
                     Box::new(HeapUpdate(
-                            "x".to_string(),
-                            NatAdd(
-                                Box::new(HeapRead("x".to_string())),
-                                Box::new(StoreRead("inc".to_string())),
-                            ),
+                        "x".to_string(),
+                        NatAdd(
+                            Box::new(HeapRead("x".to_string())),
+                            Box::new(StoreRead("inc".to_string())),
+                        ),
                     )),
+                )),
             )),
-            );
+        );
 
         assert_eq!(parsed, stmt);
     }
+
+    #[test]
+    fn test_stmnt_import() {
+        let parsed =
+            super::parse("import \"lib.imp\"\nimport \"other.imp\" as o\nlet y = lib.x\n").unwrap();
+
+        let stmt = Sequence(
+            Box::new(Import("lib.imp".to_string(), None)),
+            Box::new(Sequence(
+                Box::new(Import("other.imp".to_string(), Some("o".to_string()))),
+                Box::new(Sequence(
+                    Box::new(Skip),
+                    Box::new(StoreAssign("y".to_string(), StoreRead("lib.x".to_string()))),
+                )),
+            )),
+        );
+
+        assert_eq!(parsed, stmt);
+
+        // Another module's globals can be read and updated through, but not bound again
+        assert!(super::parse("lib.p <- lib.x").is_ok());
+        assert!(super::parse("let lib.x = 1").is_err());
+        assert!(super::parse("let lib.p <- 1").is_err());
+        assert!(super::parse("lib.p = q").is_err());
+        assert!(super::parse("let (lib.x, y) = (1, 2)").is_err());
+        assert!(super::parse("read lib.x").is_err());
+    }
+
+    #[test]
+    fn test_stmnt_scope() {
+        let parsed = super::parse("do\n    let x = 1\nend\nlet y = 2\n").unwrap();
+
+        let stmt = Sequence(
+            Box::new(Sequence(
+                Box::new(Skip),
+                Box::new(Scope(Box::new(Sequence(
+                    Box::new(Skip),
+                    Box::new(StoreAssign("x".to_string(), Constant(Nat(1)))),
+                )))),
+            )),
+            Box::new(StoreAssign("y".to_string(), Constant(Nat(2)))),
+        );
+
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_stmnt_destructure() {
+        let parsed = super::parse("let (q, r) = (x, \"a\")").unwrap();
+
+        let stmt = Sequence(
+            Box::new(Skip),
+            Box::new(Destructure(
+                vec!["q".to_string(), "r".to_string()],
+                Tuple(vec![
+                    StoreRead("x".to_string()),
+                    Constant(Str("a".to_string())),
+                ]),
+            )),
+        );
+
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_stmnt_match() {
+        let source = "enum Tree = Leaf | Node(ref Tree, (nat, str))\nmatch t\n\
+            case Tree::Node(l, _) then\n    skip\ncase _ then\n    skip\nend";
+        let parsed = super::parse(source).unwrap();
+
+        let stmt = Sequence(
+            Box::new(Sequence(
+                Box::new(Skip),
+                Box::new(Enum(
+                    "Tree".to_string(),
+                    vec![
+                        ("Leaf".to_string(), vec![]),
+                        (
+                            "Node".to_string(),
+                            vec![
+                                Type::location(Type::Enum("Tree".to_string())),
+                                Type::Tuple(vec![Type::Number, Type::String]),
+                            ],
+                        ),
+                    ],
+                )),
+            )),
+            Box::new(Match(
+                StoreRead("t".to_string()),
+                vec![
+                    (
+                        Pattern::Variant(
+                            "Tree".to_string(),
+                            "Node".to_string(),
+                            vec![Some("l".to_string()), None],
+                        ),
+                        Sequence(Box::new(Skip), Box::new(Skip)),
+                    ),
+                    (Pattern::Wildcard, Sequence(Box::new(Skip), Box::new(Skip))),
+                ],
+            )),
+        );
+
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_stmnt_address_of() {
+        let parsed = super::parse("pragma address_of\nlet p = &x").unwrap();
+        let stmt = Sequence(
+            Box::new(Skip),
+            Box::new(AddressOf("p".to_string(), "x".to_string())),
+        );
+        assert_eq!(parsed, stmt);
+
+        // `&x` is off by default
+        assert!(super::parse("let p = &x").is_err());
+    }
+
+    #[test]
+    fn test_stmnt_choose() {
+        let parsed = super::parse("choose\nlet x = 1\nor\nskip\nend\nhavoc x").unwrap();
+        let stmt = Sequence(
+            Box::new(Sequence(
+                Box::new(Skip),
+                Box::new(Choose(
+                    Box::new(Sequence(
+                        Box::new(Skip),
+                        Box::new(StoreAssign("x".to_string(), Constant(Nat(1)))),
+                    )),
+                    Box::new(Sequence(Box::new(Skip), Box::new(Skip))),
+                )),
+            )),
+            Box::new(Havoc("x".to_string())),
+        );
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_stmnt_parallel() {
+        let parsed = super::parse("par\nskip\n||\nhavoc x\nend").unwrap();
+        let stmt = Sequence(
+            Box::new(Skip),
+            Box::new(Parallel(
+                Box::new(Sequence(Box::new(Skip), Box::new(Skip))),
+                Box::new(Sequence(Box::new(Skip), Box::new(Havoc("x".to_string())))),
+            )),
+        );
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_stmnt_borrow() {
+        let parsed = super::parse("q = &p\nr = &mut p\nmutex = &mutable").unwrap();
+        let stmt = Sequence(
+            Box::new(Sequence(
+                Box::new(Sequence(
+                    Box::new(Skip),
+                    Box::new(Borrow("q".to_string(), "p".to_string(), false)),
+                )),
+                Box::new(Borrow("r".to_string(), "p".to_string(), true)),
+            )),
+            Box::new(Borrow("mutex".to_string(), "mutable".to_string(), false)),
+        );
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_stmnt_labeled() {
+        let parsed = super::parse("let high x = 1\nlet high = 2\nread low y").unwrap();
+        let stmt = Sequence(
+            Box::new(Sequence(
+                Box::new(Sequence(
+                    Box::new(Skip),
+                    Box::new(Labeled(
+                        Level::High,
+                        Box::new(StoreAssign("x".to_string(), Constant(Nat(1)))),
+                    )),
+                )),
+                // Labels aren't keywords anywhere else
+                Box::new(StoreAssign("high".to_string(), Constant(Nat(2)))),
+            )),
+            Box::new(Labeled(Level::Low, Box::new(StoreInput("y".to_string())))),
+        );
+        assert_eq!(parsed, stmt);
+    }
+
+    #[test]
+    fn test_expr_and() {
+        // `&` binds the loosest, so both sides of it can be comparisons
+        let s = "a < n & s == i & true";
+        let ex = BoolAnd(
+            Box::new(BoolAnd(
+                Box::new(NatLeq(
+                    Box::new(StoreRead("a".to_string())),
+                    Box::new(StoreRead("n".to_string())),
+                )),
+                Box::new(Equal(
+                    Box::new(StoreRead("s".to_string())),
+                    Box::new(StoreRead("i".to_string())),
+                )),
+            )),
+            Box::new(Constant(Bool(true))),
+        );
+
+        assert_eq!(parse_expr(s), ex);
+    }
 }
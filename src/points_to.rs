@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::evaluator::Sigma;
+use crate::interval::{self, AbsValue, State};
+use crate::syntax::{Expr, Ident, Statement};
+
+/// A `HeapUpdate` that might change a cell that's read through another variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasedUpdate {
+    /// The statement doing the update, numbered like `PointsTo::targets`
+    pub update: usize,
+    /// The variable the cell is updated through
+    pub updated: Ident,
+    /// The statement reading the cell, and the variable it's read through
+    pub read: usize,
+    pub read_through: Ident,
+    /// The allocation sites both variables might point to
+    pub sites: BTreeSet<usize>,
+}
+
+impl fmt::Display for AliasedUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sites: Vec<_> = self.sites.iter().map(|s| format!("#{}", s)).collect();
+        write!(
+            f,
+            "Updating `*{}` at #{} might change `*{}` read at #{}, they can both point to {}",
+            self.updated,
+            self.update,
+            self.read_through,
+            self.read,
+            sites.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PointsTo {
+    /// For every statement, numbered like `interval::Analysis::points`, the allocation sites each
+    /// location variable might point to before it runs. `None` if the statement can never run.
+    pub targets: Vec<Option<BTreeMap<Ident, BTreeSet<usize>>>>,
+    pub aliased_updates: Vec<AliasedUpdate>,
+}
+
+impl PointsTo {
+    /// The allocation sites `p` might point to before statement `point`, which are the statements
+    /// that allocated them. A location bound by a `match` might point to any site that allocated a
+    /// cell before it. Empty if it isn't a location, the statement can never run, or there's no
+    /// such statement.
+    pub fn may_point_to(&self, point: usize, p: &str) -> BTreeSet<usize> {
+        self.targets
+            .get(point)
+            .and_then(Option::as_ref)
+            .and_then(|targets| targets.get(p).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Whether `p` and `q` might point to the same cell before statement `point`
+    pub fn may_alias(&self, point: usize, p: &str, q: &str) -> bool {
+        !self
+            .may_point_to(point, p)
+            .is_disjoint(&self.may_point_to(point, q))
+    }
+}
+
+/// Finds what every location variable in `program`, which must typecheck, might point to before
+/// every statement, starting with the variables in `store`. Locations are told apart by the
+/// statement that allocated them, so two variables pointing to the same site may still point to
+/// different cells if the site is in a loop. Only the reads that can run after an update are
+/// checked against it.
+pub fn analyze(program: &Statement, store: &Sigma) -> PointsTo {
+    let analysis = interval::analyze(program, store);
+    let targets: Vec<_> = analysis
+        .points
+        .iter()
+        .map(|point| point.state.as_ref().map(locations))
+        .collect();
+    let stmnts = interval::statements(program);
+    let mut flow = Flow {
+        ids: stmnts
+            .iter()
+            .enumerate()
+            .map(|(id, s)| (*s as *const _, id))
+            .collect(),
+        next: vec![BTreeSet::new(); stmnts.len()],
+    };
+    flow.link(program, BTreeSet::new());
+
+    let mut aliased_updates = Vec::new();
+    for (update, stmnt) in stmnts.iter().enumerate() {
+        let Statement::HeapUpdate(updated, _) = stmnt else {
+            continue;
+        };
+        let Some(updated_sites) = targets[update].as_ref().and_then(|t| t.get(updated)) else {
+            continue;
+        };
+        for read in flow.reachable(update) {
+            let Some(read_targets) = &targets[read] else {
+                continue;
+            };
+            let stmnt = stmnts[read];
+            for read_through in heap_reads(stmnt) {
+                let Some(read_sites) = read_targets.get(read_through) else {
+                    continue;
+                };
+                let sites: BTreeSet<_> = updated_sites.intersection(read_sites).copied().collect();
+                let aliased = AliasedUpdate {
+                    update,
+                    updated: updated.clone(),
+                    read,
+                    read_through: read_through.clone(),
+                    sites,
+                };
+                if read_through != updated
+                    && !aliased.sites.is_empty()
+                    && !aliased_updates.contains(&aliased)
+                {
+                    aliased_updates.push(aliased);
+                }
+            }
+        }
+    }
+    PointsTo {
+        targets,
+        aliased_updates,
+    }
+}
+
+// The statements that can run right after each statement, numbered like `interval::statements`
+struct Flow {
+    ids: HashMap<*const Statement, usize>,
+    next: Vec<BTreeSet<usize>>,
+}
+
+impl Flow {
+    // Links `stmnt` to the statements that can run `after` it, returning the ones it starts with.
+    // Since the branches of a `par` are interleaved, either one can go on after any statement of
+    // the other.
+    fn link(&mut self, stmnt: &Statement, after: BTreeSet<usize>) -> BTreeSet<usize> {
+        match stmnt {
+            Statement::Sequence(s1, s2) => {
+                let after = self.link(s2, after);
+                return self.link(s1, after);
+            }
+            Statement::Labeled(_, s) => return self.link(s, after),
+            Statement::Skip => return after,
+            _ => {}
+        }
+        let id = self.ids[&(stmnt as *const _)];
+        let next = match stmnt {
+            Statement::Conditional(_, s1, s2) | Statement::Choose(s1, s2) => {
+                let mut next = self.link(s1, after.clone());
+                next.extend(self.link(s2, after));
+                next
+            }
+            Statement::Match(_, cases) => cases
+                .iter()
+                .flat_map(|(_, body)| self.link(body, after.clone()))
+                .collect(),
+            Statement::While(_, body) => {
+                let mut next = self.link(body, BTreeSet::from([id]));
+                next.extend(after);
+                next
+            }
+            Statement::Scope(body) | Statement::Module(_, _, body) => self.link(body, after),
+            Statement::Parallel(s1, s2) => {
+                let first = self.link(s1, after.clone());
+                let second = self.link(s2, after);
+                for (branch, other) in [(s1, &second), (s2, &first)] {
+                    for inner in interval::statements(branch) {
+                        let inner = self.ids[&(inner as *const _)];
+                        self.next[inner].extend(other);
+                    }
+                }
+                &first | &second
+            }
+            _ => after,
+        };
+        self.next[id].extend(next);
+        BTreeSet::from([id])
+    }
+
+    // The statements that can run at some point after `stmnt`
+    fn reachable(&self, stmnt: usize) -> BTreeSet<usize> {
+        let mut reached = BTreeSet::new();
+        let mut todo: Vec<usize> = self.next[stmnt].iter().copied().collect();
+        while let Some(id) = todo.pop() {
+            if reached.insert(id) {
+                todo.extend(&self.next[id]);
+            }
+        }
+        reached
+    }
+}
+
+fn locations(state: &State) -> BTreeMap<Ident, BTreeSet<usize>> {
+    state
+        .store
+        .iter()
+        .filter_map(|(id, v)| match v {
+            AbsValue::Location(sites) => Some((id.clone(), sites.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+// The variables read through by the expressions of `stmnt`, but not the statements inside it
+fn heap_reads(stmnt: &Statement) -> Vec<&Ident> {
+    fn visit<'a>(e: &'a Expr, reads: &mut Vec<&'a Ident>) {
+        match e {
+            Expr::HeapRead(p) => reads.push(p),
+            Expr::StoreRead(_) | Expr::Constant(_) => {}
+            Expr::NatAdd(a, b)
+            | Expr::NatSub(a, b)
+            | Expr::NatLeq(a, b)
+            | Expr::BoolAnd(a, b)
+            | Expr::StrConcat(a, b)
+            | Expr::Equal(a, b) => {
+                visit(a, reads);
+                visit(b, reads);
+            }
+            Expr::IntNeg(a)
+            | Expr::ToInt(a)
+            | Expr::ToNat(a)
+            | Expr::BoolNot(a)
+            | Expr::StrLen(a) => visit(a, reads),
+            Expr::Tuple(es) | Expr::Construct(_, _, es) => es.iter().for_each(|e| visit(e, reads)),
+        }
+    }
+    let mut reads = Vec::new();
+    match stmnt {
+        Statement::StoreAssign(_, e)
+        | Statement::HeapNew(_, e)
+        | Statement::HeapUpdate(_, e)
+        | Statement::Destructure(_, e)
+        | Statement::Conditional(e, _, _)
+        | Statement::While(e, _)
+        | Statement::Print(e)
+        | Statement::Assume(e)
+        | Statement::Contract(_, e)
+        | Statement::Match(e, _) => visit(e, &mut reads),
+        Statement::Assert(cond, message) => {
+            visit(cond, &mut reads);
+            if let Some(message) = message {
+                visit(message, &mut reads);
+            }
+        }
+        _ => {}
+    }
+    reads
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeSet, HashMap};
+
+    use super::analyze;
+    use crate::parser::parse;
+    use crate::typechecker::typecheck;
+
+    #[test]
+    fn test_points_to() {
+        let program = parse(
+            "let p <- 1\nlet q <- 2\nread x\nif x < 1 then\nr = p\nelse\nr = q\nfi\nr <- 3\n\
+            print *q",
+        )
+        .unwrap();
+        typecheck(&program).unwrap();
+        let points_to = analyze(&program, &HashMap::new());
+        // After the `if`, `r` is either `p` or `q`
+        assert_eq!(points_to.may_point_to(6, "r"), BTreeSet::from([0, 1]));
+        assert_eq!(points_to.may_point_to(4, "r"), BTreeSet::new());
+        assert!(points_to.may_alias(6, "q", "r"));
+        assert!(!points_to.may_alias(6, "p", "q"));
+
+        let [aliased] = &points_to.aliased_updates[..] else {
+            panic!("{:?}", points_to.aliased_updates);
+        };
+        assert_eq!((aliased.update, &aliased.updated[..]), (6, "r"));
+        assert_eq!((aliased.read, &aliased.read_through[..]), (7, "q"));
+        assert_eq!(aliased.sites, BTreeSet::from([1]));
+        assert!(points_to.may_point_to(100, "r").is_empty());
+
+        // Taking the address of `x` twice gives two names for the same cell
+        let program =
+            parse("pragma address_of\nlet x = 1\nlet p = &x\nlet q = &x\nq <- 5\nlet y = *p")
+                .unwrap();
+        typecheck(&program).unwrap();
+        let points_to = analyze(&program, &HashMap::new());
+        assert_eq!(points_to.may_point_to(3, "q"), BTreeSet::from([1]));
+        assert!(points_to.may_alias(3, "p", "q"));
+        let [aliased] = &points_to.aliased_updates[..] else {
+            panic!("{:?}", points_to.aliased_updates);
+        };
+        assert_eq!((aliased.update, &aliased.updated[..]), (3, "q"));
+        assert_eq!((aliased.read, &aliased.read_through[..]), (4, "p"));
+    }
+
+    #[test]
+    fn test_match_binder() {
+        // `r` could be any location allocated before the `match`
+        let program = parse(
+            "enum Cell = Full(ref nat)\nlet p <- 1\nlet q <- 2\nlet c = Cell::Full(p)\n\
+            match c\ncase Cell::Full(r) then\nr <- 3\nend\nprint *q",
+        )
+        .unwrap();
+        typecheck(&program).unwrap();
+        let points_to = analyze(&program, &HashMap::new());
+        assert_eq!(points_to.may_point_to(5, "r"), BTreeSet::from([1, 2]));
+        assert!(points_to.may_alias(5, "q", "r"));
+
+        let [aliased] = &points_to.aliased_updates[..] else {
+            panic!("{:?}", points_to.aliased_updates);
+        };
+        assert_eq!((aliased.update, &aliased.updated[..]), (5, "r"));
+        assert_eq!((aliased.read, &aliased.read_through[..]), (6, "q"));
+    }
+
+    #[test]
+    fn test_update_order() {
+        // `*p` is only read before `q <- 2`, unless that's in a loop or another branch of a `par`
+        let programs = [
+            ("let p <- 1\nprint *p\nq = p\nq <- 2", 0),
+            (
+                "let p <- 1\nlet i = 0\nwhile i < 2 do\nprint *p\nq = p\nq <- 2\n\
+                let i = i + 1\nend",
+                1,
+            ),
+            ("let p <- 1\nq = p\npar\nprint *p\n||\nskip\nq <- 2\nend", 1),
+        ];
+        for (source, expected) in programs {
+            let program = parse(source).unwrap();
+            typecheck(&program).unwrap();
+            let points_to = analyze(&program, &HashMap::new());
+            assert_eq!(points_to.aliased_updates.len(), expected, "{}", source);
+        }
+    }
+}
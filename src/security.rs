@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::TypeError;
+use crate::syntax::{Expr, Ident, Level, Pattern, Statement};
+use crate::typechecker::{is_qualified, qualify};
+
+/// Checks that nothing `low` in `program` depends on `high` data, starting from the labels in
+/// `labels` and leaving the label of every variable the program binds in it. Unlabeled bindings
+/// are `low`, and a variable keeps the label it was first bound with.
+///
+/// High data can't be assigned to a low variable (an explicit flow), and nothing low can be
+/// assigned in a branch or loop whose condition is high (an implicit flow). Every alias of a heap
+/// location has the same label. Printing, reading input, `assert` and `assume` are all visible,
+/// so they only work with low data, outside of high branches.
+pub fn check_with(
+    labels: &mut HashMap<Ident, Level>,
+    program: &Statement,
+) -> Result<(), TypeError> {
+    let mut flow = Flow {
+        labels: std::mem::take(labels),
+        unknown: HashSet::new(),
+    };
+    let checked = flow.stmnt(program, Level::Low);
+    *labels = flow.labels;
+    checked
+}
+
+struct Flow {
+    labels: HashMap<Ident, Level>,
+    // Locations bound by a `match`, which can point to a cell of any label
+    unknown: HashSet<Ident>,
+}
+
+impl Flow {
+    fn stmnt(&mut self, stmnt: &Statement, pc: Level) -> Result<(), TypeError> {
+        match stmnt {
+            Statement::StoreAssign(id, expr) | Statement::HeapNew(id, expr) => {
+                self.bind(id, self.level(expr).max(pc))
+            }
+            Statement::Destructure(ids, expr) => {
+                let level = self.level(expr).max(pc);
+                ids.iter().try_for_each(|id| self.bind(id, level))
+            }
+            Statement::HeapUpdate(id, expr) => {
+                let level = self.level(expr).max(pc);
+                // The cell could be low, whatever the variable's label is
+                let cell = if self.unknown.contains(id) {
+                    Level::Low
+                } else {
+                    self.label(id)
+                };
+                expect_flow(level, cell, id)
+            }
+            Statement::HeapAlias(alias, id)
+            | Statement::Borrow(alias, id, _)
+            | Statement::AddressOf(alias, id) => self.alias(alias, id, pc),
+            Statement::StoreInput(id) | Statement::HeapInput(id) => {
+                expect_flow(pc, Level::Low, "read")?;
+                self.bind(id, Level::Low)
+            }
+            Statement::Havoc(id) => self.bind(id, pc),
+            Statement::Print(expr) => expect_flow(self.level(expr).max(pc), Level::Low, "print"),
+            Statement::Assert(cond, message) => {
+                let level = message
+                    .iter()
+                    .fold(self.level(cond), |l, m| l.max(self.level(m)));
+                expect_flow(level.max(pc), Level::Low, "assert")
+            }
+            Statement::Assume(cond) => expect_flow(self.level(cond).max(pc), Level::Low, "assume"),
+            Statement::Contract(_, cond) => {
+                expect_flow(self.level(cond).max(pc), Level::Low, "contract")
+            }
+            Statement::Labeled(level, stmnt) => {
+                for id in bound_names(stmnt) {
+                    self.declare(id, *level)?;
+                }
+                self.stmnt(stmnt, pc)
+            }
+            Statement::Sequence(s1, s2)
+            | Statement::Choose(s1, s2)
+            | Statement::Parallel(s1, s2) => {
+                self.stmnt(s1, pc)?;
+                self.stmnt(s2, pc)
+            }
+            Statement::Conditional(cond, then_s, else_s) => {
+                let pc = self.level(cond).max(pc);
+                self.stmnt(then_s, pc)?;
+                self.stmnt(else_s, pc)
+            }
+            Statement::While(cond, body) => self.stmnt(body, self.level(cond).max(pc)),
+            Statement::Scope(body) => self.stmnt(body, pc),
+            Statement::Match(expr, cases) => {
+                let pc = self.level(expr).max(pc);
+                for (pattern, body) in cases {
+                    if let Pattern::Variant(_, _, fields) = pattern {
+                        for id in fields.iter().flatten() {
+                            self.bind(id, pc)?;
+                            self.unknown.insert(id.clone());
+                        }
+                    }
+                    self.stmnt(body, pc)?;
+                }
+                Ok(())
+            }
+            // A module's globals keep their labels when they're qualified with its name
+            Statement::Module(name, _, body) => {
+                let mut module = Flow {
+                    labels: HashMap::new(),
+                    unknown: HashSet::new(),
+                };
+                module.stmnt(body, Level::Low)?;
+                for (id, level) in module
+                    .labels
+                    .into_iter()
+                    .filter(|(id, _)| !is_qualified(id))
+                {
+                    self.labels.insert(qualify(name, &id), level);
+                }
+                Ok(())
+            }
+            Statement::Const(_, _)
+            | Statement::Import(_, _)
+            | Statement::Enum(_, _)
+            | Statement::Skip => Ok(()),
+        }
+    }
+
+    fn level(&self, expr: &Expr) -> Level {
+        match expr {
+            Expr::StoreRead(id) | Expr::HeapRead(id) => self.label(id),
+            Expr::Constant(_) => Level::Low,
+            Expr::NatAdd(a, b)
+            | Expr::NatSub(a, b)
+            | Expr::NatLeq(a, b)
+            | Expr::BoolAnd(a, b)
+            | Expr::StrConcat(a, b)
+            | Expr::Equal(a, b) => self.level(a).max(self.level(b)),
+            Expr::IntNeg(a)
+            | Expr::ToInt(a)
+            | Expr::ToNat(a)
+            | Expr::BoolNot(a)
+            | Expr::StrLen(a) => self.level(a),
+            Expr::Tuple(components) | Expr::Construct(_, _, components) => components
+                .iter()
+                .map(|e| self.level(e))
+                .max()
+                .unwrap_or(Level::Low),
+        }
+    }
+
+    fn label(&self, id: &str) -> Level {
+        self.labels.get(id).copied().unwrap_or(Level::Low)
+    }
+
+    fn declare(&mut self, id: &Ident, level: Level) -> Result<(), TypeError> {
+        match self.labels.get(id) {
+            Some(label) if *label != level => Err(TypeError::InsecureFlow(id.clone())),
+            _ => {
+                self.labels.insert(id.clone(), level);
+                Ok(())
+            }
+        }
+    }
+
+    // Binds `id` to data of the given level, which includes the level of the context
+    fn bind(&mut self, id: &Ident, level: Level) -> Result<(), TypeError> {
+        let label = self.label(id);
+        expect_flow(level, label, id)?;
+        self.labels.insert(id.clone(), label);
+        self.unknown.remove(id);
+        Ok(())
+    }
+
+    // Which cell `alias` points to depends on the context, and every alias of a cell has its label
+    fn alias(&mut self, alias: &Ident, id: &Ident, pc: Level) -> Result<(), TypeError> {
+        let level = self.label(id);
+        let unknown = self.unknown.contains(id);
+        let mismatched = self.labels.get(alias).is_some_and(|label| *label != level)
+            || (self.labels.contains_key(alias) && self.unknown.contains(alias) != unknown);
+        if mismatched {
+            return Err(TypeError::InsecureFlow(alias.clone()));
+        }
+        expect_flow(pc, level, alias)?;
+        self.labels.insert(alias.clone(), level);
+        if unknown {
+            self.unknown.insert(alias.clone());
+        }
+        Ok(())
+    }
+}
+
+fn expect_flow(from: Level, to: Level, name: &str) -> Result<(), TypeError> {
+    if from <= to {
+        Ok(())
+    } else {
+        Err(TypeError::InsecureFlow(name.to_owned()))
+    }
+}
+
+// The variables a labeled binding binds
+fn bound_names(stmnt: &Statement) -> Vec<&Ident> {
+    match stmnt {
+        Statement::StoreAssign(id, _)
+        | Statement::HeapNew(id, _)
+        | Statement::StoreInput(id)
+        | Statement::HeapInput(id) => vec![id],
+        Statement::Destructure(ids, _) => ids.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::error::TypeError;
+    use crate::parser::parse;
+
+    fn check(source: &str) -> Result<(), TypeError> {
+        super::check_with(&mut HashMap::new(), &parse(source).unwrap())
+    }
+
+    fn insecure(source: &str, name: &str) -> bool {
+        matches!(check(source), Err(TypeError::InsecureFlow(n)) if n == name)
+    }
+
+    #[test]
+    fn test_flows() {
+        assert!(check("let high h = 1\nlet high g = h + 1\nlet x = 2\nlet g = x").is_ok());
+        assert!(insecure("let high h = 1\nlet x = h", "x"));
+        assert!(insecure("let high h = 1\nprint h", "print"));
+        // Implicit flows through a branch on a high condition
+        assert!(insecure(
+            "let high h = 1\nlet x = 0\nif h < 1 then\nlet x = 1\nelse\nskip\nfi",
+            "x"
+        ));
+        assert!(insecure(
+            "let high h = 1\nwhile h < 3 do\nprint 1\nend",
+            "print"
+        ));
+        // A variable keeps its first label
+        assert!(insecure("let high h = 1\nlet low h = 2", "h"));
+    }
+
+    #[test]
+    fn test_heap_flows() {
+        // Aliases share the label of the cell
+        assert!(check("let high p <- 1\nq = p\nq <- 2\nlet high h = *q").is_ok());
+        assert!(insecure("let high p <- 1\nq = p\nlet x = *q", "x"));
+        assert!(insecure("let p <- 1\nlet high q <- 2\nq = p", "q"));
+        assert!(insecure("let high h = 1\nlet p <- 1\nq = p\nq <- h", "q"));
+        assert!(insecure("let high p <- 1\nq = p\nprint *q", "print"));
+        // A low cell updated through an alias in a high branch
+        assert!(insecure(
+            "let p <- 0\nq = p\nlet high h = 1\nif h < 1 then\nq <- 1\nelse\nskip\nfi",
+            "q"
+        ));
+    }
+}
@@ -1,9 +1,15 @@
+use std::fmt;
+
+use crate::typechecker::Type;
+
 pub type Ident = String;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Constant {
-    Nat(i64),
+    Nat(u64),
+    Int(i64),
     Bool(bool),
+    Str(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -11,10 +17,51 @@ pub enum Expr {
     StoreRead(Ident),
     HeapRead(Ident),
     Constant(Constant),
+    /// Arithmetic and comparisons work on two nats or two ints. Subtracting a larger nat from a
+    /// smaller one gives 0.
     NatAdd(Box<Expr>, Box<Expr>),
+    NatSub(Box<Expr>, Box<Expr>),
     NatLeq(Box<Expr>, Box<Expr>),
+    IntNeg(Box<Expr>),
+    /// Converts a nat to an int
+    ToInt(Box<Expr>),
+    /// Converts an int to a nat, failing if it's negative
+    ToNat(Box<Expr>),
     BoolAnd(Box<Expr>, Box<Expr>),
     BoolNot(Box<Expr>),
+    StrConcat(Box<Expr>, Box<Expr>),
+    StrLen(Box<Expr>),
+    Equal(Box<Expr>, Box<Expr>),
+    /// A tuple of two or more values
+    Tuple(Vec<Expr>),
+    /// `Enum::Variant(a, b)`, a value of an enum built from one of its variants
+    Construct(Ident, Ident, Vec<Expr>),
+}
+
+/// A security label for the information flow checker, where `Low` data can flow into `High`
+/// variables but not the other way around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Low,
+    High,
+}
+
+/// What a `Contract` statement states, for the verifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clause {
+    /// A precondition, assumed to hold where it's written
+    Requires,
+    /// A postcondition, proven to hold where it's written
+    Ensures,
+    /// A loop invariant, which the parser puts at the start of the loop's body
+    Invariant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// `Enum::Variant(a, _)`, binding each field of the variant to a name, or ignoring it
+    Variant(Ident, Ident, Vec<Option<Ident>>),
+    Wildcard,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -23,8 +70,223 @@ pub enum Statement {
     HeapNew(Ident, Expr),
     HeapUpdate(Ident, Expr),
     HeapAlias(Ident, Ident),
+    /// `q = &p` or `q = &mut p`, an alias that the ownership checker treats as a shared or mutable
+    /// borrow of `p` rather than a move
+    Borrow(Ident, Ident, bool),
+    /// `let p = &x`, moving `x` into a new heap cell and binding `p` to its location. Only allowed
+    /// after `pragma address_of`.
+    AddressOf(Ident, Ident),
+    /// `let (a, b) = e`, binding each component of a tuple in the store
+    Destructure(Vec<Ident>, Expr),
     Sequence(Box<Statement>, Box<Statement>),
     Conditional(Expr, Box<Statement>, Box<Statement>),
     While(Expr, Box<Statement>),
+    Scope(Box<Statement>),
+    /// Runs one of the two statements, picked by the evaluator's oracle
+    Choose(Box<Statement>, Box<Statement>),
+    /// Assigns an arbitrary value to a nat or int variable
+    Havoc(Ident),
+    /// `par S1 || S2 end`, running both statements with their steps interleaved
+    Parallel(Box<Statement>, Box<Statement>),
+    Print(Expr),
+    StoreInput(Ident),
+    HeapInput(Ident),
+    /// A condition that must hold, with an optional message for when it doesn't
+    Assert(Expr, Option<Expr>),
+    /// A condition that is assumed to hold, execution stops quietly if it doesn't
+    Assume(Expr),
+    /// `requires e`, `ensures e` or `while c invariant e do`, a condition for the verifier. The
+    /// evaluator checks it like an `assert`.
+    Contract(Clause, Expr),
+    /// An unresolved `import "path" as name`, replaced by `Module` once the file has been loaded
+    Import(String, Option<Ident>),
+    /// A binding labeled with `high` or `low`, like `let high x = e` or `read low x`
+    Labeled(Level, Box<Statement>),
+    /// A constant declared with `const`. Reads of it are replaced by its value before typechecking.
+    Const(Ident, Expr),
+    /// `enum Name = A | B(nat, ref Name)`, declaring an enum and the fields of each of its variants
+    Enum(Ident, Vec<(Ident, Vec<Type>)>),
+    /// Runs the statement of the first case whose pattern matches the value
+    Match(Expr, Vec<(Pattern, Statement)>),
+    /// A loaded module: the name its globals are qualified with, a unique id for the file it was
+    /// loaded from, and its program
+    Module(Ident, String, Box<Statement>),
     Skip,
 }
+
+// Written like in the source. The parser reads `int 5` and `-5` back as int literals.
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Nat(n) => write!(f, "{}", n),
+            Constant::Int(i) if *i >= 0 => write!(f, "int {}", i),
+            Constant::Int(i) => write!(f, "-{}", i.unsigned_abs()),
+            Constant::Bool(b) => write!(f, "{}", b),
+            Constant::Str(s) => {
+                let escaped = s
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+                    .replace('\t', "\\t");
+                write!(f, "\"{}\"", escaped)
+            }
+        }
+    }
+}
+
+// Operands that are operators themselves are wrapped in parentheses
+struct Operand<'a>(&'a Expr);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expr::StoreRead(_)
+            | Expr::HeapRead(_)
+            | Expr::Constant(_)
+            | Expr::Tuple(_)
+            | Expr::Construct(_, _, _) => write!(f, "{}", self.0),
+            expr => write!(f, "({})", expr),
+        }
+    }
+}
+
+fn comma_separated<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::StoreRead(x) => write!(f, "{}", x),
+            Expr::HeapRead(x) => write!(f, "*{}", x),
+            Expr::Constant(c) => write!(f, "{}", c),
+            Expr::NatAdd(a, b) => write!(f, "{} + {}", Operand(a), Operand(b)),
+            Expr::NatSub(a, b) => write!(f, "{} - {}", Operand(a), Operand(b)),
+            Expr::NatLeq(a, b) => write!(f, "{} < {}", Operand(a), Operand(b)),
+            Expr::IntNeg(a) => write!(f, "-{}", Operand(a)),
+            Expr::ToInt(a) => write!(f, "int {}", Operand(a)),
+            Expr::ToNat(a) => write!(f, "nat {}", Operand(a)),
+            Expr::BoolAnd(a, b) => write!(f, "{} & {}", Operand(a), Operand(b)),
+            Expr::BoolNot(a) => write!(f, "~{}", Operand(a)),
+            Expr::StrConcat(a, b) => write!(f, "{} ++ {}", Operand(a), Operand(b)),
+            Expr::StrLen(a) => write!(f, "len {}", Operand(a)),
+            Expr::Equal(a, b) => write!(f, "{} == {}", Operand(a), Operand(b)),
+            Expr::Tuple(components) => write!(f, "({})", comma_separated(components)),
+            Expr::Construct(name, variant, args) if args.is_empty() => {
+                write!(f, "{}::{}", name, variant)
+            }
+            Expr::Construct(name, variant, args) => {
+                write!(f, "{}::{}({})", name, variant, comma_separated(args))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Low => write!(f, "low"),
+            Level::High => write!(f, "high"),
+        }
+    }
+}
+
+impl fmt::Display for Clause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Clause::Requires => write!(f, "requires"),
+            Clause::Ensures => write!(f, "ensures"),
+            Clause::Invariant => write!(f, "invariant"),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Variant(name, variant, binders) if binders.is_empty() => {
+                write!(f, "{}::{}", name, variant)
+            }
+            Pattern::Variant(name, variant, binders) => {
+                let binders: Vec<_> = binders
+                    .iter()
+                    .map(|b| b.as_deref().unwrap_or("_"))
+                    .collect();
+                write!(f, "{}::{}({})", name, variant, binders.join(", "))
+            }
+            Pattern::Wildcard => write!(f, "_"),
+        }
+    }
+}
+
+// Written on one line, with `;` between the statements of a sequence
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::StoreAssign(id, expr) => write!(f, "let {} = {}", id, expr),
+            Statement::HeapNew(id, expr) => write!(f, "let {} <- {}", id, expr),
+            Statement::HeapUpdate(id, expr) => write!(f, "{} <- {}", id, expr),
+            Statement::HeapAlias(alias, id) => write!(f, "{} = {}", alias, id),
+            Statement::Borrow(alias, id, true) => write!(f, "{} = &mut {}", alias, id),
+            Statement::Borrow(alias, id, false) => write!(f, "{} = &{}", alias, id),
+            Statement::AddressOf(p, x) => write!(f, "let {} = &{}", p, x),
+            Statement::Destructure(ids, expr) => write!(f, "let ({}) = {}", ids.join(", "), expr),
+            Statement::Sequence(s1, s2) => write!(f, "{}; {}", s1, s2),
+            Statement::Conditional(cond, then_s, else_s) => {
+                write!(f, "if {} then {} else {} fi", cond, then_s, else_s)
+            }
+            Statement::While(cond, body) => write!(f, "while {} do {} end", cond, body),
+            Statement::Scope(body) => write!(f, "do {} end", body),
+            Statement::Choose(s1, s2) => write!(f, "choose {} or {} end", s1, s2),
+            Statement::Havoc(id) => write!(f, "havoc {}", id),
+            Statement::Parallel(s1, s2) => write!(f, "par {} || {} end", s1, s2),
+            Statement::Print(expr) => write!(f, "print {}", expr),
+            Statement::StoreInput(id) => write!(f, "read {}", id),
+            Statement::HeapInput(id) => write!(f, "read {} <-", id),
+            Statement::Assert(cond, None) => write!(f, "assert {}", cond),
+            Statement::Assert(cond, Some(message)) => write!(f, "assert {}, {}", cond, message),
+            Statement::Assume(cond) => write!(f, "assume {}", cond),
+            Statement::Contract(clause, cond) => write!(f, "{} {}", clause, cond),
+            Statement::Import(path, None) => write!(f, "import {:?}", path),
+            Statement::Import(path, Some(name)) => write!(f, "import {:?} as {}", path, name),
+            Statement::Labeled(level, stmnt) => match stmnt.as_ref() {
+                Statement::StoreAssign(id, expr) => write!(f, "let {} {} = {}", level, id, expr),
+                Statement::HeapNew(id, expr) => write!(f, "let {} {} <- {}", level, id, expr),
+                Statement::StoreInput(id) => write!(f, "read {} {}", level, id),
+                Statement::HeapInput(id) => write!(f, "read {} {} <-", level, id),
+                Statement::Destructure(ids, expr) => {
+                    write!(f, "let {} ({}) = {}", level, ids.join(", "), expr)
+                }
+                stmnt => write!(f, "{}", stmnt),
+            },
+            Statement::Const(id, expr) => write!(f, "const {} = {}", id, expr),
+            Statement::Enum(name, variants) => {
+                let variants: Vec<_> = variants
+                    .iter()
+                    .map(|(variant, fields)| {
+                        if fields.is_empty() {
+                            variant.clone()
+                        } else {
+                            format!("{}({})", variant, comma_separated(fields))
+                        }
+                    })
+                    .collect();
+                write!(f, "enum {} = {}", name, variants.join(" | "))
+            }
+            Statement::Match(expr, cases) => {
+                write!(f, "match {}", expr)?;
+                for (pattern, body) in cases {
+                    write!(f, " case {} then {}", pattern, body)?;
+                }
+                write!(f, " end")
+            }
+            // Modules don't have a syntax of their own, since they're loaded from an import
+            Statement::Module(name, _, body) => write!(f, "module {} do {} end", name, body),
+            Statement::Skip => write!(f, "skip"),
+        }
+    }
+}
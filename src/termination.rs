@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::evaluator::Sigma;
+use crate::interval::{self, eval, AbsValue, Analysis, Interval, State, INT, NAT};
+use crate::syntax::{Expr, Pattern, Statement};
+use crate::typechecker::qualify;
+
+/// Whether a loop always stops by its condition becoming false
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// With a ranking function that's never negative while the loop runs and decreases by at
+    /// least 1 every iteration, or `None` if the loop never runs
+    Terminating(Option<Expr>),
+    /// The loop never stops once it starts
+    NonTerminating,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopReport {
+    /// The loop, numbered like `interval::Analysis::points`
+    pub point: usize,
+    pub cond: Expr,
+    pub verdict: Verdict,
+}
+
+impl fmt::Display for LoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} while {} do: ", self.point, self.cond)?;
+        match &self.verdict {
+            Verdict::Terminating(Some(ranking)) => {
+                write!(f, "terminates, `{}` decreases every iteration", ranking)
+            }
+            Verdict::Terminating(None) => write!(f, "never runs"),
+            Verdict::NonTerminating => write!(f, "never terminates once it starts"),
+            Verdict::Unknown => write!(f, "might not terminate"),
+        }
+    }
+}
+
+/// Tries to prove that every `while` in `program`, which must typecheck, terminates, starting
+/// with the variables in `store`. A loop like `while a < b do` terminates if `b - a` decreases in
+/// every iteration, which is found by adding up how much the body changes `a` and `b`, with the
+/// ranges from `interval::analyze`. Only variables, heap reads and constants are tracked.
+///
+/// The verdicts are about the loops themselves: the evaluator still stops every loop after
+/// `evaluator::MAX_ITERATIONS` iterations, so a `NonTerminating` loop is only cut off there.
+pub fn check(program: &Statement, store: &Sigma) -> Vec<LoopReport> {
+    let analysis = interval::analyze(program, store);
+    let stmnts = interval::statements(program);
+    let checker = Checker {
+        analysis: &analysis,
+        ids: stmnts
+            .iter()
+            .enumerate()
+            .map(|(id, stmnt)| (*stmnt as *const _, id))
+            .collect(),
+    };
+    stmnts
+        .iter()
+        .enumerate()
+        .filter_map(|(point, stmnt)| match stmnt {
+            Statement::While(cond, body) => Some(LoopReport {
+                point,
+                cond: cond.clone(),
+                verdict: checker.verdict(point, cond, body),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+struct Checker<'a> {
+    analysis: &'a Analysis,
+    ids: HashMap<*const Statement, usize>,
+}
+
+impl Checker<'_> {
+    fn verdict(&self, point: usize, cond: &Expr, body: &Statement) -> Verdict {
+        let Some(head) = self.analysis.heads.get(&point) else {
+            return Verdict::Terminating(None);
+        };
+        if eval(head, cond) == AbsValue::Bool(Some(true)) {
+            return Verdict::NonTerminating;
+        }
+        if let Some(ranking) = self.ranking(cond, body, true) {
+            return Verdict::Terminating(Some(ranking));
+        }
+        // A loop that starts and doesn't change anything its condition reads never stops
+        let entry = self.analysis.points[point].state.as_ref().unwrap();
+        let mut terms = Vec::new();
+        collect_terms(cond, &mut terms);
+        let zero = Some(Interval::exact(0));
+        if eval(entry, cond) == AbsValue::Bool(Some(true))
+            && terms.iter().all(|term| self.delta(body, term) == zero)
+        {
+            return Verdict::NonTerminating;
+        }
+        Verdict::Unknown
+    }
+
+    // A ranking function for a loop that runs while `cond` is `value`
+    fn ranking(&self, cond: &Expr, body: &Statement, value: bool) -> Option<Expr> {
+        match cond {
+            Expr::BoolNot(a) => self.ranking(a, body, !value),
+            // The loop stops as soon as either of them is false
+            Expr::BoolAnd(a, b) if value => self
+                .ranking(a, body, true)
+                .or_else(|| self.ranking(b, body, true)),
+            // Runs while `a <= b`
+            Expr::NatLeq(a, b) if value => self.decreasing(b, a, body),
+            // Runs while `b < a`
+            Expr::NatLeq(a, b) => self.decreasing(a, b, body),
+            _ => None,
+        }
+    }
+
+    // `hi - lo`, if the body makes it smaller by at least 1
+    fn decreasing(&self, hi: &Expr, lo: &Expr, body: &Statement) -> Option<Expr> {
+        let hi_delta = self.delta_expr(body, hi)?;
+        let lo_delta = self.delta_expr(body, lo)?;
+        (lo_delta.lo - hi_delta.hi >= 1)
+            .then(|| Expr::NatSub(Box::new(hi.clone()), Box::new(lo.clone())))
+    }
+
+    fn delta_expr(&self, body: &Statement, e: &Expr) -> Option<Interval> {
+        match e {
+            Expr::Constant(_) => Some(Interval::exact(0)),
+            Expr::StoreRead(_) | Expr::HeapRead(_) => self.delta(body, e),
+            _ => None,
+        }
+    }
+
+    // How much running `stmnt` changes the value of `term`, or `None` if that isn't known
+    fn delta(&self, stmnt: &Statement, term: &Expr) -> Option<Interval> {
+        let zero = Some(Interval::exact(0));
+        match stmnt {
+            Statement::Sequence(s1, s2) => {
+                return Some(add(self.delta(s1, term)?, self.delta(s2, term)?));
+            }
+            // Interleaved updates from both branches could overwrite each other
+            Statement::Parallel(s1, s2) if writes(s1, term) && writes(s2, term) => return None,
+            Statement::Parallel(s1, s2) => {
+                return Some(add(self.delta(s1, term)?, self.delta(s2, term)?));
+            }
+            Statement::Labeled(_, s) => return self.delta(s, term),
+            Statement::Skip => return zero,
+            _ => {}
+        }
+        let Some(state) = &self.analysis.points[self.ids[&(stmnt as *const _)]].state else {
+            return zero;
+        };
+        match (stmnt, term) {
+            (Statement::StoreAssign(x, e), Expr::StoreRead(y)) if x == y => step(state, term, e),
+            (Statement::HeapUpdate(p, e), Expr::HeapRead(q)) if p == q => step(state, term, e),
+            (Statement::HeapUpdate(p, _), Expr::HeapRead(q)) => {
+                let sites = |id| match state.store.get(id) {
+                    Some(AbsValue::Location(sites)) => Some(sites),
+                    _ => None,
+                };
+                sites(p)?
+                    .is_disjoint(sites(q)?)
+                    .then_some(Interval::exact(0))
+            }
+            (Statement::Conditional(_, s1, s2) | Statement::Choose(s1, s2), _) => {
+                self.join([&**s1, &**s2], term)
+            }
+            (Statement::Match(_, cases), _) if !rebinds(stmnt, term) => {
+                self.join(cases.iter().map(|(_, body)| body), term)
+            }
+            (Statement::Scope(body), _) => self.delta(body, term),
+            // An inner loop can run any number of times
+            (Statement::While(_, body), _) => {
+                (self.delta(body, term)? == Interval::exact(0)).then_some(Interval::exact(0))
+            }
+            _ if rebinds(stmnt, term) => None,
+            _ => zero,
+        }
+    }
+
+    // The deltas of the branches that can run
+    fn join<'a>(
+        &self,
+        branches: impl IntoIterator<Item = &'a Statement>,
+        term: &Expr,
+    ) -> Option<Interval> {
+        let mut joined: Option<Interval> = None;
+        for branch in branches {
+            if !self.reached(branch) {
+                continue;
+            }
+            let delta = self.delta(branch, term)?;
+            joined = Some(joined.map_or(delta, |joined| Interval {
+                lo: joined.lo.min(delta.lo),
+                hi: joined.hi.max(delta.hi),
+            }));
+        }
+        Some(joined.unwrap_or(Interval::exact(0)))
+    }
+
+    fn reached(&self, stmnt: &Statement) -> bool {
+        interval::statements(stmnt).first().is_none_or(|first| {
+            self.analysis.points[self.ids[&(*first as *const _)]]
+                .state
+                .is_some()
+        })
+    }
+}
+
+// The change from `term = e`, if `e` is `term` plus or minus something and it doesn't overflow
+// into 0
+fn step(state: &State, term: &Expr, e: &Expr) -> Option<Interval> {
+    let (value, range) = match eval(state, term) {
+        AbsValue::Nat(value) => (value, NAT),
+        AbsValue::Int(value) => (value, INT),
+        _ => return None,
+    };
+    let fits = |lo: i128, hi: i128| range.lo <= lo && hi <= range.hi;
+    match e {
+        e if e == term => Some(Interval::exact(0)),
+        Expr::NatAdd(a, b) if **a == *term || **b == *term => {
+            let other = if **a == *term { b } else { a };
+            let c = eval(state, other).interval()?;
+            fits(value.lo + c.lo, value.hi + c.hi).then_some(c)
+        }
+        // Nats stop at 0, so they go down by at most their value
+        Expr::NatSub(a, b) if **a == *term && range == NAT => {
+            let c = eval(state, b).interval()?;
+            Some(Interval {
+                lo: -c.hi.min(value.hi),
+                hi: -c.lo.min(value.lo),
+            })
+        }
+        Expr::NatSub(a, b) if **a == *term => {
+            let c = eval(state, b).interval()?;
+            fits(value.lo - c.hi, value.hi - c.lo).then_some(Interval {
+                lo: -c.hi,
+                hi: -c.lo,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn add(a: Interval, b: Interval) -> Interval {
+    Interval {
+        lo: a.lo + b.lo,
+        hi: a.hi + b.hi,
+    }
+}
+
+// Whether `stmnt` binds the variable `term` reads to something else
+fn rebinds(stmnt: &Statement, term: &Expr) -> bool {
+    let (Expr::StoreRead(x) | Expr::HeapRead(x)) = term else {
+        return true;
+    };
+    match stmnt {
+        Statement::StoreAssign(y, _)
+        | Statement::HeapNew(y, _)
+        | Statement::HeapAlias(y, _)
+        | Statement::Borrow(y, _, _)
+        | Statement::StoreInput(y)
+        | Statement::HeapInput(y)
+        | Statement::Havoc(y) => x == y,
+        Statement::AddressOf(p, y) => x == p || x == y,
+        Statement::Destructure(ids, _) => ids.contains(x),
+        // A case binds the fields of its pattern, and a module binds what it exports
+        Statement::Match(_, cases) => cases.iter().any(|(pattern, _)| match pattern {
+            Pattern::Variant(_, _, binders) => binders.iter().flatten().any(|y| x == y),
+            Pattern::Wildcard => false,
+        }),
+        Statement::Module(name, _, _) => x.starts_with(&qualify(name, "")),
+        _ => false,
+    }
+}
+
+// Whether anything in `stmnt` might change the value of `term`
+fn writes(stmnt: &Statement, term: &Expr) -> bool {
+    interval::statements(stmnt).into_iter().any(|s| {
+        rebinds(s, term) || matches!((s, term), (Statement::HeapUpdate(_, _), Expr::HeapRead(_)))
+    })
+}
+
+fn collect_terms<'a>(e: &'a Expr, terms: &mut Vec<&'a Expr>) {
+    match e {
+        Expr::StoreRead(_) | Expr::HeapRead(_) => terms.push(e),
+        Expr::Constant(_) => {}
+        Expr::NatAdd(a, b)
+        | Expr::NatSub(a, b)
+        | Expr::NatLeq(a, b)
+        | Expr::BoolAnd(a, b)
+        | Expr::StrConcat(a, b)
+        | Expr::Equal(a, b) => {
+            collect_terms(a, terms);
+            collect_terms(b, terms);
+        }
+        Expr::IntNeg(a) | Expr::ToInt(a) | Expr::ToNat(a) | Expr::BoolNot(a) | Expr::StrLen(a) => {
+            collect_terms(a, terms)
+        }
+        Expr::Tuple(es) | Expr::Construct(_, _, es) => {
+            es.iter().for_each(|e| collect_terms(e, terms))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{check, rebinds, Verdict};
+    use crate::parser::parse;
+    use crate::syntax::{Expr, Statement};
+
+    fn verdicts(source: &str) -> Vec<Verdict> {
+        let program = parse(source).unwrap();
+        check(&program, &HashMap::new())
+            .into_iter()
+            .map(|report| report.verdict)
+            .collect()
+    }
+
+    #[test]
+    fn test_termination() {
+        let [Verdict::Terminating(Some(ranking))] =
+            &verdicts("let x <- 0\nwhile *x < 100 do\nx <- *x + 1\nend")[..]
+        else {
+            panic!()
+        };
+        assert_eq!(ranking.to_string(), "100 - *x");
+        // Counting down, with a step that depends on a branch
+        let [Verdict::Terminating(Some(_))] = &verdicts(
+            "read n\nlet i = 10\nwhile 1 < i do\nif n < 5 then\nlet i = i - 1\nelse\n\
+            let i = i - 2\nfi\nend",
+        )[..] else {
+            panic!()
+        };
+        // An update through another name might not be the same cell
+        let [Verdict::Unknown] =
+            &verdicts("let x <- 0\nq = x\nread n\nwhile *x < n do\nq <- *q + 1\nend")[..]
+        else {
+            panic!()
+        };
+        // Both addresses of `i` are the same cell, so the update through `q` can change `*p`
+        let source = "pragma address_of\nlet i = 0\nlet p = &i\nlet q = &i\nwhile *p < 3 do\n\
+            q <- *q + 1\nend";
+        assert_eq!(verdicts(source), [Verdict::Unknown]);
+    }
+
+    #[test]
+    fn test_non_termination() {
+        assert_eq!(
+            verdicts("let x = 0\nwhile x < 10 do\nprint x\nend\nwhile 5 < x do\nprint x\nend"),
+            [Verdict::NonTerminating, Verdict::Terminating(None)]
+        );
+        assert_eq!(
+            verdicts("read x\nlet y = 0\nwhile y < x do\nlet y = y + x\nend"),
+            [Verdict::Unknown]
+        );
+    }
+
+    #[test]
+    fn test_rebinding() {
+        // The case binds `i` to the field of the variant, whatever the body did to it
+        let source =
+            "enum V = A(nat)\nlet v = V::A(5)\nlet i = 10\nwhile 1 < i do\nlet i = i - 1\n\
+            match v\ncase V::A(i) then\nskip\nend\nend";
+        assert_eq!(verdicts(source), [Verdict::Unknown]);
+        let module = Statement::Module("m".into(), "m.imp".into(), Box::new(Statement::Skip));
+        assert!(rebinds(&module, &Expr::StoreRead("m.x".into())));
+        assert!(!rebinds(&module, &Expr::StoreRead("x".into())));
+
+        // Both branches read `i` before either of them writes it back
+        let source = "let i = 10\nwhile 1 < i do\npar\nlet i = i - 1\n||\nlet i = i - 1\nend\nend";
+        assert_eq!(verdicts(source), [Verdict::Unknown]);
+    }
+}
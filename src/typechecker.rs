@@ -1,17 +1,141 @@
+use crate::derivation::{self, Derivation};
 use crate::error::TypeError;
-use crate::syntax::{Constant, Expr, Statement};
-use std::collections::HashMap;
+use crate::ownership;
+use crate::security;
+use crate::syntax::{Constant, Expr, Ident, Level, Pattern, Statement};
+use crate::typed::{
+    self, binding, Context, ExprKind, StmntKind, TypedExpr, TypedPattern, TypedStatement,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Type {
+    /// A natural number, written `nat`
     Number,
-    Location,
+    /// A signed integer
+    Int,
+    /// A location on the heap, holding a value of the given type
+    Location(Box<Type>),
     Boolean,
+    String,
+    Tuple(Vec<Type>),
+    /// A value of the enum with the given name
+    Enum(String),
 }
 
-pub fn typecheck(program: &Statement) -> Result<(), TypeError> {
+impl Type {
+    pub fn location(pointee: Type) -> Type {
+        Type::Location(Box::new(pointee))
+    }
+
+    /// Whether values of this type can be bound in the store and saved on the heap
+    pub fn is_storable(&self) -> bool {
+        match self {
+            Type::Number | Type::Int | Type::String | Type::Enum(_) => true,
+            Type::Tuple(components) => components.iter().all(Type::is_storable),
+            Type::Location(_) | Type::Boolean => false,
+        }
+    }
+}
+
+// Written like in the source, as in `enum` declarations
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "nat"),
+            Type::Int => write!(f, "int"),
+            Type::Location(pointee) => write!(f, "ref {}", pointee),
+            Type::Boolean => write!(f, "bool"),
+            Type::String => write!(f, "str"),
+            Type::Tuple(components) => {
+                let components: Vec<_> = components.iter().map(Type::to_string).collect();
+                write!(f, "({})", components.join(", "))
+            }
+            Type::Enum(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// State shared by the whole program while typechecking, unlike `sigma` which follows scoping
+#[derive(Default, Clone)]
+pub(crate) struct Checker {
+    /// The context exported by every module that has been checked, and its typed body, by module id
+    modules: HashMap<String, (HashMap<String, Type>, TypedStatement)>,
+    /// The constants declared so far in the module being checked
+    pub(crate) consts: HashSet<String>,
+    /// The variants of every enum declared so far, with the types of their fields
+    enums: HashMap<Ident, Vec<(Ident, Vec<Type>)>>,
+    /// How each branch bound the variables that were dropped after them and haven't been bound
+    /// again since, see `TypeError::Dropped`
+    dropped: HashMap<Ident, Vec<Option<(Type, String)>>>,
+}
+
+impl Checker {
+    pub(crate) fn variant(&self, name: &str, variant: &str) -> Result<&[Type], TypeError> {
+        let variants = self
+            .enums
+            .get(name)
+            .ok_or_else(|| TypeError::UnknownEnum(name.to_owned()))?;
+        variants
+            .iter()
+            .find(|(v, _)| v == variant)
+            .map(|(_, fields)| fields.as_slice())
+            .ok_or_else(|| TypeError::UnknownVariant(format!("{}::{}", name, variant)))
+    }
+}
+
+/// Typechecks `program`, returning it annotated with the type of every expression and binding
+pub fn typecheck(program: &Statement) -> Result<TypedStatement, TypeError> {
     let mut sigma = HashMap::new();
-    typecheck_stmt_aux(&mut sigma, program)
+    typecheck_typed(&mut sigma, program)
+}
+
+/// Like `typecheck_with`, but returns the typed program, see `typed::TypedStatement`
+pub fn typecheck_typed(
+    sigma: &mut HashMap<String, Type>,
+    program: &Statement,
+) -> Result<TypedStatement, TypeError> {
+    typecheck_stmt_aux(&mut Checker::default(), sigma, program)
+}
+
+/// Typechecks `program` starting from the context `sigma`, leaving the final context in `sigma`
+pub fn typecheck_with(
+    sigma: &mut HashMap<String, Type>,
+    program: &Statement,
+) -> Result<(), TypeError> {
+    typecheck_typed(sigma, program).map(|_| ())
+}
+
+/// Like `typecheck_with`, but also returns the derivation of the program's typing judgment, see
+/// `derivation::derive`
+pub fn typecheck_derived(
+    sigma: &mut HashMap<String, Type>,
+    program: &Statement,
+) -> Result<Derivation, TypeError> {
+    typecheck_typed(sigma, program).map(|typed| derivation::derive(&typed))
+}
+
+/// Like `typecheck_with`, but also checks that heap locations are moved and borrowed following
+/// Rust's ownership rules, see `ownership::check`
+pub fn typecheck_owned(
+    sigma: &mut HashMap<String, Type>,
+    program: &Statement,
+) -> Result<(), TypeError> {
+    let initial = sigma.clone();
+    typecheck_with(sigma, program)?;
+    ownership::check(program, &initial)
+}
+
+/// Like `typecheck_with`, but also checks that no `high` data flows into anything `low`, starting
+/// from the labels in `labels`, see `security::check_with`
+pub fn typecheck_secure(
+    sigma: &mut HashMap<String, Type>,
+    labels: &mut HashMap<Ident, Level>,
+    program: &Statement,
+) -> Result<(), TypeError> {
+    typecheck_with(sigma, program)?;
+    security::check_with(labels, program)
 }
 
 fn expect_ty(expected: Type, got: Type) -> Result<Type, TypeError> {
@@ -25,10 +149,12 @@ fn expect_ty(expected: Type, got: Type) -> Result<Type, TypeError> {
 fn expect_expr_ty(
     expected: Type,
     ast: &Expr,
+    checker: &Checker,
     sigma: &HashMap<String, Type>,
-) -> Result<Type, TypeError> {
-    let expr_ty = typecheck_expr_aux(sigma, ast)?;
-    expect_ty(expected, expr_ty)
+) -> Result<TypedExpr, TypeError> {
+    let typed = typecheck_expr_aux(checker, sigma, ast)?;
+    expect_ty(expected, typed.ty.clone())?;
+    Ok(typed)
 }
 
 fn expect_name_ty(
@@ -36,110 +162,697 @@ fn expect_name_ty(
     name: &str,
     sigma: &HashMap<String, Type>,
 ) -> Result<Type, TypeError> {
-    let name_ty = sigma.get(name).unwrap_or(&expected);
-    expect_ty(expected, *name_ty)
+    let name_ty = sigma.get(name).unwrap_or(&expected).clone();
+    expect_ty(expected, name_ty)
+}
+
+fn expect_storable(got: Type) -> Result<Type, TypeError> {
+    if got.is_storable() {
+        Ok(got)
+    } else {
+        Err(TypeError::NotStorable(got))
+    }
 }
 
-fn typecheck_expr_aux(sigma: &HashMap<String, Type>, ast: &Expr) -> Result<Type, TypeError> {
-    match ast {
-        Expr::StoreRead(x) => {
-            let x_ty = sigma.get(x).ok_or(TypeError::UnboundVariable)?;
-            expect_ty(Type::Number, *x_ty)
+fn expect_numeric(got: Type) -> Result<Type, TypeError> {
+    match got {
+        Type::Number | Type::Int => Ok(got),
+        got => Err(TypeError::Mismatch {
+            expected: Type::Number,
+            got,
+        }),
+    }
+}
+
+// Returns the type of the value the location points to
+fn expect_location(got: Type) -> Result<Type, TypeError> {
+    match got {
+        Type::Location(pointee) => Ok(*pointee),
+        got => Err(TypeError::Mismatch {
+            expected: Type::location(Type::Number),
+            got,
+        }),
+    }
+}
+
+/// Whether the name refers to a global from another module
+pub fn is_qualified(name: &str) -> bool {
+    name.contains('.')
+}
+
+/// The name of the global `name` exported by `module`
+pub fn qualify(module: &str, name: &str) -> String {
+    format!("{}.{}", module, name)
+}
+
+// Constants can't be bound again, since every read of them has already been replaced by their value
+fn expect_not_const(checker: &Checker, name: &str) -> Result<(), TypeError> {
+    if checker.consts.contains(name) {
+        Err(TypeError::ConstReassignment(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+// The variables bound with the same type in both contexts
+pub(crate) fn intersect(
+    a: HashMap<String, Type>,
+    b: &HashMap<String, Type>,
+) -> HashMap<String, Type> {
+    a.into_iter()
+        .filter(|(k, ty)| b.get(k) == Some(ty))
+        .collect()
+}
+
+// Taking the address of a variable changes its type, which a block can only do to the variables
+// bound inside of it, since the code after the block doesn't know whether it ran. Loops drop such
+// variables from their head instead, see `typecheck_loop`.
+fn expect_unpromoted(
+    outer: &HashMap<String, Type>,
+    inner: &HashMap<String, Type>,
+) -> Result<(), TypeError> {
+    match outer
+        .iter()
+        .find(|(k, ty)| inner.get(*k).is_some_and(|t| t != *ty))
+    {
+        Some((k, _)) => Err(TypeError::AddressTaken(k.clone())),
+        None => Ok(()),
+    }
+}
+
+/// How many times the body of a `while` is checked before giving up on its context converging
+pub const MAX_LOOP_CHECKS: usize = 16;
+
+// The context at the head of a loop has to hold when it's first reached and after every iteration,
+// so the body is checked again from the variables bound with the same type in both, until that
+// stops changing. `check_body` checks the body from a head, returning the context after it along
+// with whatever it typed. Returns the head the loop converged to and what the last check typed.
+fn loop_head<T, F>(
+    sigma: &HashMap<String, Type>,
+    max_checks: usize,
+    mut check_body: F,
+) -> Result<(HashMap<String, Type>, T), TypeError>
+where
+    F: FnMut(&HashMap<String, Type>) -> Result<(HashMap<String, Type>, T), TypeError>,
+{
+    let mut head = sigma.clone();
+    for _ in 0..max_checks {
+        let (after, typed) = check_body(&head)?;
+        let next = intersect(head.clone(), &after);
+        if next == head {
+            return Ok((head, typed));
         }
-        Expr::HeapRead(x) => {
-            let ix_ty = sigma.get(x).ok_or(TypeError::UnboundVariable)?;
-            expect_ty(Type::Location, *ix_ty)?;
-            Ok(Type::Number)
+        head = next;
+    }
+    Err(TypeError::LoopDiverges(max_checks))
+}
+
+// Nothing bound in the body is visible after the loop, since it might not run. A body that takes
+// the address of a variable bound before the loop moves it to the heap, so it isn't bound at the
+// head of the next iteration, and the body is checked again without it.
+fn typecheck_loop(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    cond: &Expr,
+    luup: &Statement,
+) -> Result<(TypedExpr, TypedStatement), TypeError> {
+    let (head, (checked, cond, body)) = loop_head(sigma, MAX_LOOP_CHECKS, |head| {
+        // What the body records in the checker would be recorded again by the next check
+        let mut checked = checker.clone();
+        let mut luup_sigma = head.clone();
+        let typed = expect_expr_ty(Type::Boolean, cond, &checked, head).and_then(|cond| {
+            let body = typecheck_stmt_aux(&mut checked, &mut luup_sigma, luup)?;
+            Ok((cond, body))
+        });
+        // The variables missing from the head were moved by the body, which the earlier checks
+        // with them bound accepted, so an unbound one is one of those read after its move
+        let (cond, body) = typed.map_err(|e| {
+            let moved = sigma.keys().filter(|k| !head.contains_key(*k)).min();
+            match (e, moved) {
+                (TypeError::UnboundVariable, Some(x)) => TypeError::AddressTaken(x.clone()),
+                (e, _) => e,
+            }
+        })?;
+        Ok((luup_sigma, (checked, cond, body)))
+    })?;
+    *checker = checked;
+    *sigma = head;
+    Ok((cond, body))
+}
+
+// A variable dropped after some branches is explained by how they bound it
+fn lookup(checker: &Checker, sigma: &HashMap<String, Type>, x: &str) -> Result<Type, TypeError> {
+    sigma
+        .get(x)
+        .cloned()
+        .ok_or_else(|| match checker.dropped.get(x) {
+            Some(branches) => TypeError::Dropped {
+                name: x.to_owned(),
+                branches: branches.clone(),
+            },
+            None => TypeError::UnboundVariable,
+        })
+}
+
+// Remembers how the branches bound every variable that isn't bound after them, given the pattern
+// and statement of each branch along with the context after it
+fn record_dropped(
+    checker: &mut Checker,
+    joined: &HashMap<String, Type>,
+    branches: &[(Option<&Pattern>, &Statement, &HashMap<String, Type>)],
+) {
+    for (_, _, branch_sigma) in branches {
+        for id in branch_sigma.keys().filter(|id| !joined.contains_key(*id)) {
+            let bindings = branches
+                .iter()
+                .map(|(pattern, stmnt, sigma)| {
+                    sigma
+                        .get(id)
+                        .map(|ty| (ty.clone(), binding_site(*pattern, stmnt, id)))
+                })
+                .collect();
+            checker.dropped.insert(id.clone(), bindings);
+        }
+    }
+}
+
+// The statement of a branch that last bound `id`, or its pattern if only that did
+fn binding_site(pattern: Option<&Pattern>, stmnt: &Statement, id: &str) -> String {
+    match (last_binding(stmnt, id), pattern) {
+        (Some(binding), _) => binding.to_string(),
+        (None, Some(pattern)) => format!("case {}", pattern),
+        (None, None) => stmnt.to_string(),
+    }
+}
+
+// Bindings inside of loops and scopes don't count, since they never leak out
+fn last_binding<'a>(stmnt: &'a Statement, id: &str) -> Option<&'a Statement> {
+    match stmnt {
+        Statement::StoreAssign(x, _)
+        | Statement::HeapNew(x, _)
+        | Statement::HeapAlias(x, _)
+        | Statement::Borrow(x, _, _)
+        | Statement::StoreInput(x)
+        | Statement::HeapInput(x)
+            if x == id =>
+        {
+            Some(stmnt)
         }
-        Expr::Constant(Constant::Nat(_)) => Ok(Type::Number),
-        Expr::Constant(Constant::Bool(_)) => Ok(Type::Boolean),
+        Statement::AddressOf(p, x) if p == id || x == id => Some(stmnt),
+        Statement::Destructure(ids, _) if ids.iter().any(|x| x == id) => Some(stmnt),
+        Statement::Sequence(s1, s2)
+        | Statement::Conditional(_, s1, s2)
+        | Statement::Choose(s1, s2)
+        | Statement::Parallel(s1, s2) => last_binding(s2, id).or_else(|| last_binding(s1, id)),
+        Statement::Match(_, cases) => cases.iter().rev().find_map(|(_, s)| last_binding(s, id)),
+        Statement::Labeled(_, s) => last_binding(s, id).map(|_| stmnt),
+        _ => None,
+    }
+}
+
+pub(crate) fn typecheck_expr_aux(
+    checker: &Checker,
+    sigma: &HashMap<String, Type>,
+    ast: &Expr,
+) -> Result<TypedExpr, TypeError> {
+    let sub = |e| typecheck_expr_aux(checker, sigma, e).map(Box::new);
+    let expect = |expected, e| expect_expr_ty(expected, e, checker, sigma).map(Box::new);
+    let (ty, kind) = match ast {
+        Expr::StoreRead(x) => (
+            expect_storable(lookup(checker, sigma, x)?)?,
+            ExprKind::StoreRead(x.clone()),
+        ),
+        Expr::HeapRead(x) => (
+            expect_location(lookup(checker, sigma, x)?)?,
+            ExprKind::HeapRead(x.clone()),
+        ),
+        Expr::Constant(c) => {
+            let ty = match c {
+                Constant::Nat(_) => Type::Number,
+                Constant::Int(_) => Type::Int,
+                Constant::Bool(_) => Type::Boolean,
+                Constant::Str(_) => Type::String,
+            };
+            (ty, ExprKind::Constant(c.clone()))
+        }
+        // Both sides need to be nats, or both ints
         Expr::NatAdd(a, b) => {
-            expect_expr_ty(Type::Number, a, sigma)?;
-            expect_expr_ty(Type::Number, b, sigma)
+            let a = sub(a)?;
+            let b = expect(expect_numeric(a.ty.clone())?, b)?;
+            (b.ty.clone(), ExprKind::NatAdd(a, b))
+        }
+        Expr::NatSub(a, b) => {
+            let a = sub(a)?;
+            let b = expect(expect_numeric(a.ty.clone())?, b)?;
+            (b.ty.clone(), ExprKind::NatSub(a, b))
         }
         Expr::NatLeq(a, b) => {
-            expect_expr_ty(Type::Number, a, sigma)?;
-            expect_expr_ty(Type::Number, b, sigma)?;
-            Ok(Type::Boolean)
+            let a = sub(a)?;
+            let b = expect(expect_numeric(a.ty.clone())?, b)?;
+            (Type::Boolean, ExprKind::NatLeq(a, b))
         }
+        Expr::IntNeg(a) => (Type::Int, ExprKind::IntNeg(expect(Type::Int, a)?)),
+        Expr::ToInt(a) => (Type::Int, ExprKind::ToInt(expect(Type::Number, a)?)),
+        Expr::ToNat(a) => (Type::Number, ExprKind::ToNat(expect(Type::Int, a)?)),
         Expr::BoolAnd(a, b) => {
-            expect_expr_ty(Type::Boolean, a, sigma)?;
-            expect_expr_ty(Type::Boolean, b, sigma)?;
-            Ok(Type::Boolean)
+            let a = expect(Type::Boolean, a)?;
+            (
+                Type::Boolean,
+                ExprKind::BoolAnd(a, expect(Type::Boolean, b)?),
+            )
         }
-        Expr::BoolNot(a) => {
-            expect_expr_ty(Type::Boolean, a, sigma)?;
-            Ok(Type::Boolean)
+        Expr::BoolNot(a) => (Type::Boolean, ExprKind::BoolNot(expect(Type::Boolean, a)?)),
+        Expr::StrConcat(a, b) => {
+            let a = expect(Type::String, a)?;
+            (
+                Type::String,
+                ExprKind::StrConcat(a, expect(Type::String, b)?),
+            )
+        }
+        Expr::StrLen(a) => (Type::Number, ExprKind::StrLen(expect(Type::String, a)?)),
+        // Both sides need to have the same type
+        Expr::Equal(a, b) => {
+            let a = sub(a)?;
+            let b = expect(a.ty.clone(), b)?;
+            (Type::Boolean, ExprKind::Equal(a, b))
+        }
+        Expr::Tuple(components) => {
+            let components: Vec<_> = components
+                .iter()
+                .map(|e| typecheck_expr_aux(checker, sigma, e))
+                .collect::<Result<_, _>>()?;
+            let ty = Type::Tuple(components.iter().map(|c| c.ty.clone()).collect());
+            (ty, ExprKind::Tuple(components))
+        }
+        Expr::Construct(name, variant, args) => {
+            let fields = checker.variant(name, variant)?;
+            expect_arity(name, variant, fields.len(), args.len())?;
+            let args = fields
+                .iter()
+                .zip(args)
+                .map(|(field, arg)| match (field, arg) {
+                    // A field holding a location is given a variable bound to one
+                    (Type::Location(_), Expr::StoreRead(x)) => Ok(TypedExpr {
+                        ty: expect_ty(field.clone(), lookup(checker, sigma, x)?)?,
+                        kind: ExprKind::StoreRead(x.clone()),
+                    }),
+                    _ => expect_expr_ty(field.clone(), arg, checker, sigma),
+                })
+                .collect::<Result<_, _>>()?;
+            let kind = ExprKind::Construct(name.clone(), variant.clone(), args);
+            (Type::Enum(name.clone()), kind)
+        }
+    };
+    Ok(TypedExpr { ty, kind })
+}
+
+fn expect_arity(name: &str, variant: &str, expected: usize, got: usize) -> Result<(), TypeError> {
+    if expected == got {
+        Ok(())
+    } else {
+        Err(TypeError::Arity {
+            variant: format!("{}::{}", name, variant),
+            expected,
+            got,
+        })
+    }
+}
+
+// Fields can refer to enums declared before, or to the enum being declared through a location
+fn check_field(checker: &Checker, name: &str, field: &Type) -> Result<(), TypeError> {
+    match field {
+        Type::Enum(e) if e != name && !checker.enums.contains_key(e) => {
+            Err(TypeError::UnknownEnum(e.clone()))
+        }
+        Type::Enum(e) if e == name => Err(TypeError::Mismatch {
+            expected: Type::location(field.clone()),
+            got: field.clone(),
+        }),
+        Type::Location(pointee) => match pointee.as_ref() {
+            Type::Enum(e) if e == name => Ok(()),
+            pointee => check_field(checker, name, pointee),
+        },
+        Type::Tuple(components) => components
+            .iter()
+            .try_for_each(|c| check_field(checker, name, c)),
+        _ => Ok(()),
+    }
+}
+
+// The variables bound in either branch don't leak out, unless both bind them with the same type
+fn typecheck_branches(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    s1: &Statement,
+    s2: &Statement,
+) -> Result<(TypedStatement, TypedStatement), TypeError> {
+    let mut s1_sigma = sigma.clone();
+    let mut s2_sigma = sigma.clone();
+    let typed_s1 = typecheck_stmt_aux(checker, &mut s1_sigma, s1)?;
+    let typed_s2 = typecheck_stmt_aux(checker, &mut s2_sigma, s2)?;
+    expect_unpromoted(sigma, &s1_sigma)?;
+    expect_unpromoted(sigma, &s2_sigma)?;
+    let joined = intersect(s1_sigma.clone(), &s2_sigma);
+    record_dropped(
+        checker,
+        &joined,
+        &[(None, s1, &s1_sigma), (None, s2, &s2_sigma)],
+    );
+    *sigma = joined;
+    Ok((typed_s1, typed_s2))
+}
+
+fn typecheck_parallel(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    s1: &Statement,
+    s2: &Statement,
+) -> Result<(TypedStatement, TypedStatement), TypeError> {
+    let mut s1_sigma = sigma.clone();
+    let mut s2_sigma = sigma.clone();
+    let typed_s1 = typecheck_stmt_aux(checker, &mut s1_sigma, s1)?;
+    let typed_s2 = typecheck_stmt_aux(checker, &mut s2_sigma, s2)?;
+    expect_unpromoted(sigma, &s1_sigma)?;
+    expect_unpromoted(sigma, &s2_sigma)?;
+    for (k, ty) in s2_sigma {
+        match s1_sigma.get(&k) {
+            Some(expected) if expected != &ty => {
+                return Err(TypeError::Mismatch {
+                    expected: expected.clone(),
+                    got: ty,
+                })
+            }
+            _ => s1_sigma.insert(k, ty),
+        };
+    }
+    *sigma = s1_sigma;
+    Ok((typed_s1, typed_s2))
+}
+
+// A module is checked once, from nothing but what it imports, and its exports are bound with its
+// name in front
+fn typecheck_module(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    name: &str,
+    id: &Ident,
+    body: &Statement,
+) -> Result<TypedStatement, TypeError> {
+    let prefix = qualify(name, "");
+    if sigma.keys().any(|k| k.starts_with(&prefix)) {
+        return Err(TypeError::DuplicateModule(name.to_string()));
+    }
+    let (exports, body) = match checker.modules.get(id) {
+        Some(module) => module.clone(),
+        None => {
+            // Modules only see what they import themselves
+            let mut module_sigma = HashMap::new();
+            let consts = std::mem::take(&mut checker.consts);
+            let dropped = std::mem::take(&mut checker.dropped);
+            let checked = typecheck_stmt_aux(checker, &mut module_sigma, body);
+            checker.consts = consts;
+            checker.dropped = dropped;
+            let body = checked?;
+            module_sigma.retain(|k, _| !is_qualified(k));
+            checker
+                .modules
+                .insert(id.clone(), (module_sigma.clone(), body.clone()));
+            (module_sigma, body)
         }
+    };
+    sigma.extend(exports.into_iter().map(|(k, ty)| (qualify(name, &k), ty)));
+    Ok(body)
+}
+
+// Every variant of the enum needs a case, which binds the fields of the variant it names
+fn typecheck_match(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    before: &Context,
+    expr: &Expr,
+    cases: &[(Pattern, Statement)],
+) -> Result<StmntKind, TypeError> {
+    let expr = typecheck_expr_aux(checker, sigma, expr)?;
+    let name = match &expr.ty {
+        Type::Enum(name) => name.clone(),
+        got => return Err(TypeError::NotAnEnum(got.clone())),
+    };
+    // Like a conditional, only the variables bound with the same type in every case leak
+    let mut case_sigmas = Vec::new();
+    let mut typed_cases = Vec::new();
+    let mut covered = HashSet::new();
+    for (pattern, body) in cases {
+        let mut case_sigma = sigma.clone();
+        let pattern = match pattern {
+            Pattern::Wildcard => {
+                covered.extend(checker.enums[&name].iter().map(|(v, _)| v.clone()));
+                TypedPattern::Wildcard
+            }
+            Pattern::Variant(enum_name, variant, binders) => {
+                expect_ty(Type::Enum(name.clone()), Type::Enum(enum_name.clone()))?;
+                let fields = checker.variant(&name, variant)?.to_vec();
+                expect_arity(&name, variant, fields.len(), binders.len())?;
+                let mut bindings = Vec::new();
+                for (binder, ty) in binders.iter().zip(fields) {
+                    bindings.push(match binder {
+                        Some(id) => {
+                            expect_not_const(checker, id)?;
+                            let ty = expect_name_ty(ty, id, &case_sigma)?;
+                            case_sigma.insert(id.clone(), ty.clone());
+                            Some(binding(before, id, ty))
+                        }
+                        None => None,
+                    });
+                }
+                covered.insert(variant.clone());
+                TypedPattern::Variant(enum_name.clone(), variant.clone(), bindings)
+            }
+        };
+        let body = typecheck_stmt_aux(checker, &mut case_sigma, body)?;
+        expect_unpromoted(sigma, &case_sigma)?;
+        case_sigmas.push(case_sigma);
+        typed_cases.push((pattern, body));
+    }
+    let missing: Vec<String> = checker.enums[&name]
+        .iter()
+        .filter(|(v, _)| !covered.contains(v))
+        .map(|(v, _)| format!("{}::{}", name, v))
+        .collect();
+    if !missing.is_empty() {
+        return Err(TypeError::NonExhaustiveMatch(missing));
     }
+    let leaked = case_sigmas
+        .iter()
+        .cloned()
+        .reduce(|leaked, case_sigma| intersect(leaked, &case_sigma))
+        .unwrap_or_default();
+    let branches: Vec<_> = cases
+        .iter()
+        .zip(&case_sigmas)
+        .map(|((pattern, body), case_sigma)| (Some(pattern), body, case_sigma))
+        .collect();
+    record_dropped(checker, &leaked, &branches);
+    *sigma = leaked;
+    Ok(StmntKind::Match(expr, typed_cases))
 }
 
-fn typecheck_stmt_aux(sigma: &mut HashMap<String, Type>, ast: &Statement) -> Result<(), TypeError> {
-    match ast {
+// Statements that don't contain others
+fn typecheck_simple(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    before: &Context,
+    ast: &Statement,
+) -> Result<StmntKind, TypeError> {
+    Ok(match ast {
         Statement::StoreAssign(id, expr) => {
-            let expr_ty = typecheck_expr_aux(sigma, expr)?;
-            expect_name_ty(Type::Number, id, sigma)?;
-            expect_ty(Type::Number, expr_ty).and_then(|_| {
-                sigma.insert(id.clone(), Type::Number);
-                Ok(())
-            })
+            expect_not_const(checker, id)?;
+            let expr = typecheck_expr_aux(checker, sigma, expr)?;
+            let ty = expect_name_ty(expect_storable(expr.ty.clone())?, id, sigma)?;
+            sigma.insert(id.clone(), ty.clone());
+            StmntKind::StoreAssign(binding(before, id, ty), expr)
+        }
+        // `x` moves to the heap, so from now on it's a location just like `p`
+        Statement::AddressOf(p, x) => {
+            expect_not_const(checker, p)?;
+            let ty = match lookup(checker, sigma, x)? {
+                ty @ Type::Location(_) => ty,
+                ty => Type::location(expect_storable(ty)?),
+            };
+            sigma.insert(x.clone(), ty.clone());
+            let p_ty = expect_name_ty(ty.clone(), p, sigma)?;
+            sigma.insert(p.clone(), p_ty.clone());
+            StmntKind::AddressOf(binding(before, p, p_ty), binding(before, x, ty))
+        }
+        // The right side needs to be a tuple with one component for each name
+        Statement::Destructure(ids, expr) => {
+            let expr = typecheck_expr_aux(checker, sigma, expr)?;
+            let components = match expect_storable(expr.ty.clone())? {
+                Type::Tuple(components) if components.len() == ids.len() => components,
+                got => {
+                    return Err(TypeError::TupleArity {
+                        expected: ids.len(),
+                        got,
+                    })
+                }
+            };
+            let mut bindings = Vec::new();
+            for (id, ty) in ids.iter().zip(components) {
+                expect_not_const(checker, id)?;
+                let ty = expect_name_ty(ty, id, sigma)?;
+                sigma.insert(id.clone(), ty.clone());
+                bindings.push(binding(before, id, ty));
+            }
+            StmntKind::Destructure(bindings, expr)
         }
         Statement::HeapNew(id, expr) => {
-            let expr_ty = typecheck_expr_aux(sigma, expr)?;
-            expect_name_ty(Type::Location, id, sigma)?;
-            expect_ty(Type::Number, expr_ty).and_then(|_| {
-                sigma.insert(id.clone(), Type::Location);
-                Ok(())
-            })
+            expect_not_const(checker, id)?;
+            let expr = typecheck_expr_aux(checker, sigma, expr)?;
+            let pointee = expect_storable(expr.ty.clone())?;
+            let ty = expect_name_ty(Type::location(pointee), id, sigma)?;
+            sigma.insert(id.clone(), ty.clone());
+            StmntKind::HeapNew(binding(before, id, ty), expr)
         }
         Statement::HeapUpdate(id, expr) => {
-            let expr_ty = typecheck_expr_aux(sigma, expr)?;
-            let id_ty = sigma.get(id).ok_or(TypeError::UnboundVariable)?;
-            expect_ty(Type::Location, *id_ty).map(|_| ())?;
-            expect_ty(Type::Number, expr_ty).map(|_| ())
-        }
-        Statement::HeapAlias(alias, id) => {
-            let stored_ty = sigma.get(id).ok_or(TypeError::UnboundVariable)?;
-            expect_name_ty(Type::Location, alias, sigma)?;
-            expect_ty(Type::Location, *stored_ty).and_then(|_| {
-                sigma.insert(alias.clone(), Type::Location);
-                Ok(())
-            })
+            expect_not_const(checker, id)?;
+            let expr = typecheck_expr_aux(checker, sigma, expr)?;
+            let pointee = expect_location(lookup(checker, sigma, id)?)?;
+            expect_ty(pointee, expr.ty.clone())?;
+            StmntKind::HeapUpdate(id.clone(), expr)
+        }
+        Statement::HeapAlias(alias, id) | Statement::Borrow(alias, id, _) => {
+            expect_not_const(checker, alias)?;
+            let pointee = expect_location(lookup(checker, sigma, id)?)?;
+            let ty = expect_name_ty(Type::location(pointee), alias, sigma)?;
+            sigma.insert(alias.clone(), ty.clone());
+            let alias = binding(before, alias, ty);
+            match ast {
+                Statement::Borrow(_, _, mutable) => StmntKind::Borrow(alias, id.clone(), *mutable),
+                _ => StmntKind::HeapAlias(alias, id.clone()),
+            }
+        }
+        Statement::Havoc(id) => {
+            expect_not_const(checker, id)?;
+            expect_numeric(lookup(checker, sigma, id)?)?;
+            StmntKind::Havoc(id.clone())
+        }
+        // Input is always read as a number, and bound like `let id = e` or `let id <- e`
+        Statement::StoreInput(id) => {
+            expect_not_const(checker, id)?;
+            expect_name_ty(Type::Number, id, sigma)?;
+            sigma.insert(id.clone(), Type::Number);
+            StmntKind::StoreInput(binding(before, id, Type::Number))
+        }
+        Statement::HeapInput(id) => {
+            expect_not_const(checker, id)?;
+            expect_name_ty(Type::location(Type::Number), id, sigma)?;
+            sigma.insert(id.clone(), Type::location(Type::Number));
+            StmntKind::HeapInput(binding(before, id, Type::location(Type::Number)))
+        }
+        Statement::Assert(cond, message) => {
+            let cond = expect_expr_ty(Type::Boolean, cond, checker, sigma)?;
+            let message = message
+                .as_ref()
+                .map(|message| expect_expr_ty(Type::String, message, checker, sigma))
+                .transpose()?;
+            StmntKind::Assert(cond, message)
+        }
+        Statement::Assume(cond) => {
+            StmntKind::Assume(expect_expr_ty(Type::Boolean, cond, checker, sigma)?)
+        }
+        Statement::Contract(clause, cond) => StmntKind::Contract(
+            *clause,
+            expect_expr_ty(Type::Boolean, cond, checker, sigma)?,
+        ),
+        Statement::Const(id, expr) => {
+            if sigma.contains_key(id) || !checker.consts.insert(id.clone()) {
+                return Err(TypeError::ConstReassignment(id.clone()));
+            }
+            StmntKind::Const(id.clone(), typecheck_expr_aux(checker, sigma, expr)?)
+        }
+        Statement::Enum(name, variants) => {
+            if checker.enums.contains_key(name) {
+                return Err(TypeError::DuplicateEnum(name.clone()));
+            }
+            let mut seen = HashSet::new();
+            for (variant, fields) in variants {
+                if !seen.insert(variant) {
+                    return Err(TypeError::DuplicateEnum(format!("{}::{}", name, variant)));
+                }
+                for field in fields {
+                    check_field(checker, name, field)?;
+                }
+            }
+            checker.enums.insert(name.clone(), variants.clone());
+            StmntKind::Enum(name.clone(), variants.clone())
         }
+        // Any value can be printed
+        Statement::Print(expr) => StmntKind::Print(typecheck_expr_aux(checker, sigma, expr)?),
+        Statement::Skip => StmntKind::Skip,
+        _ => unreachable!("{} contains other statements", ast),
+    })
+}
+
+pub(crate) fn typecheck_stmt_aux(
+    checker: &mut Checker,
+    sigma: &mut HashMap<String, Type>,
+    ast: &Statement,
+) -> Result<TypedStatement, TypeError> {
+    let before = typed::context(sigma);
+    let kind = match ast {
         Statement::Sequence(s1, s2) => {
-            typecheck_stmt_aux(sigma, s1)?;
-            typecheck_stmt_aux(sigma, s2)
+            let s1 = typecheck_stmt_aux(checker, sigma, s1)?;
+            StmntKind::Sequence(
+                Box::new(s1),
+                Box::new(typecheck_stmt_aux(checker, sigma, s2)?),
+            )
         }
         Statement::Conditional(cond, then, els) => {
-            expect_expr_ty(Type::Boolean, cond, sigma)?;
-            // The variables bound in the then and else branches are disjoint and don't leak out.
-            let mut then_sigma = sigma.clone();
-            let mut els_sigma = sigma.clone();
-            typecheck_stmt_aux(&mut then_sigma, then)?;
-            typecheck_stmt_aux(&mut els_sigma, els)?;
-            *sigma = then_sigma
-                .into_iter()
-                .filter_map(|(k, v1)| {
-                    if let Some(v2) = els_sigma.get(&k) {
-                        if &v1 == v2 {
-                            Some((k, *v2))
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            Ok(())
+            let cond = expect_expr_ty(Type::Boolean, cond, checker, sigma)?;
+            let (then, els) = typecheck_branches(checker, sigma, then, els)?;
+            StmntKind::Conditional(cond, Box::new(then), Box::new(els))
+        }
+        // Either statement could run, so this works just like a conditional
+        Statement::Choose(s1, s2) => {
+            let (s1, s2) = typecheck_branches(checker, sigma, s1, s2)?;
+            StmntKind::Choose(Box::new(s1), Box::new(s2))
+        }
+        // Both statements run, so everything either of them binds is still bound afterwards. They
+        // can't bind the same name with different types, since either one could do it first.
+        Statement::Parallel(s1, s2) => {
+            let (s1, s2) = typecheck_parallel(checker, sigma, s1, s2)?;
+            StmntKind::Parallel(Box::new(s1), Box::new(s2))
+        }
+        // Labels only matter to the information flow checker
+        Statement::Labeled(level, stmnt) => {
+            StmntKind::Labeled(*level, Box::new(typecheck_stmt_aux(checker, sigma, stmnt)?))
         }
         Statement::While(cond, luup) => {
-            expect_expr_ty(Type::Boolean, cond, sigma)?;
-            let mut luup_sigma = sigma.clone();
-            typecheck_stmt_aux(&mut luup_sigma, luup)
+            let (cond, body) = typecheck_loop(checker, sigma, cond, luup)?;
+            StmntKind::While(cond, Box::new(body))
         }
-        Statement::Skip => Ok(()),
-    }
+        Statement::Scope(body) => {
+            // Like a loop body, nothing bound inside the scope is visible after `end`
+            let mut scope_sigma = sigma.clone();
+            let body = typecheck_stmt_aux(checker, &mut scope_sigma, body)?;
+            expect_unpromoted(sigma, &scope_sigma)?;
+            StmntKind::Scope(Box::new(body))
+        }
+        Statement::Match(expr, cases) => typecheck_match(checker, sigma, &before, expr, cases)?,
+        Statement::Import(path, _) => return Err(TypeError::UnresolvedImport(path.clone())),
+        Statement::Module(name, id, body) => {
+            let body = typecheck_module(checker, sigma, name, id, body)?;
+            StmntKind::Module(name.clone(), id.clone(), Box::new(body))
+        }
+        _ => typecheck_simple(checker, sigma, &before, ast)?,
+    };
+    // A variable that's bound again isn't explained by the branches that dropped it anymore, even
+    // once it goes out of scope
+    checker.dropped.retain(|id, _| !sigma.contains_key(id));
+    Ok(TypedStatement {
+        before,
+        after: typed::context(sigma),
+        kind,
+    })
 }
 
 #[allow(unused)]
@@ -172,7 +885,7 @@ mod test {
                 )),
             )),
         );
-        typecheck(&program)
+        typecheck(&program).map(|_| ())
     }
 
     #[test]
@@ -223,4 +936,402 @@ mod test {
         );
         typecheck(&program).unwrap_err();
     }
+
+    #[test]
+    fn storable_test() {
+        // Booleans can't be bound or saved on the heap, on their own or inside of a tuple
+        let pair = Expr::Tuple(vec![Expr::Constant(Nat(1)), Expr::Constant(Bool(true))]);
+        for program in [
+            Statement::StoreAssign("x".into(), Expr::Constant(Bool(true))),
+            Statement::HeapNew("x".into(), Expr::Constant(Bool(false))),
+            Statement::StoreAssign("x".into(), pair),
+        ] {
+            assert!(matches!(
+                typecheck(&program),
+                Err(TypeError::NotStorable(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn scope_test() {
+        // x is bound inside the scope, so it is unbound once the scope ends
+        let program = Statement::Sequence(
+            Box::new(Statement::Scope(Box::new(Statement::StoreAssign(
+                "x".into(),
+                Expr::Constant(Nat(1)),
+            )))),
+            Box::new(Statement::StoreAssign(
+                "y".into(),
+                Expr::StoreRead("x".into()),
+            )),
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::UnboundVariable)
+        ));
+    }
+
+    #[test]
+    fn string_test() {
+        // Strings can be stored on the heap, and reading them back gives a string
+        let program = Statement::Sequence(
+            Box::new(Statement::HeapNew(
+                "s".into(),
+                Expr::Constant(Str("hello".into())),
+            )),
+            Box::new(Statement::StoreAssign(
+                "n".into(),
+                Expr::StrLen(Box::new(Expr::StrConcat(
+                    Box::new(Expr::HeapRead("s".into())),
+                    Box::new(Expr::Constant(Str(" world".into()))),
+                ))),
+            )),
+        );
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert_eq!(sigma.get("s"), Some(&Type::location(Type::String)));
+        assert_eq!(sigma.get("n"), Some(&Type::Number));
+
+        // A number can't be written to a location holding a string
+        let program = Statement::Sequence(
+            Box::new(program),
+            Box::new(Statement::HeapUpdate(
+                "s".into(),
+                Expr::StoreRead("n".into()),
+            )),
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::Mismatch {
+                expected: Type::String,
+                got: Type::Number
+            })
+        ));
+    }
+
+    #[test]
+    fn module_test() {
+        let module = |id: &str, x: &str| {
+            Box::new(Statement::Module(
+                "m".into(),
+                id.into(),
+                Box::new(Statement::StoreAssign(x.into(), Expr::Constant(Nat(1)))),
+            ))
+        };
+        let program = Statement::Sequence(module("a.imp", "x"), module("b.imp", "y"));
+        let result = typecheck(&program);
+        assert!(matches!(result, Err(TypeError::DuplicateModule(name)) if name == "m"));
+    }
+
+    #[test]
+    fn const_test() {
+        // Constants can't be bound again, whether with `let`, `read` or another `const`
+        let decl = Statement::Const("N".into(), Expr::Constant(Nat(3)));
+        for stmnt in [
+            Statement::StoreAssign("N".into(), Expr::Constant(Nat(4))),
+            Statement::StoreInput("N".into()),
+            Statement::Const("N".into(), Expr::Constant(Nat(4))),
+        ] {
+            let program = Statement::Sequence(Box::new(decl.clone()), Box::new(stmnt));
+            assert!(matches!(
+                typecheck(&program),
+                Err(TypeError::ConstReassignment(id)) if id == "N"
+            ));
+        }
+    }
+
+    #[test]
+    fn tuple_test() {
+        let pair = Expr::Tuple(vec![
+            Expr::Constant(Nat(1)),
+            Expr::Constant(Str("a".into())),
+        ]);
+        let program = Statement::Destructure(vec!["q".into(), "r".into()], pair.clone());
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert_eq!(sigma.get("q"), Some(&Type::Number));
+        assert_eq!(sigma.get("r"), Some(&Type::String));
+
+        // The number of names has to match the number of components
+        let program = Statement::Destructure(vec!["a".into(), "b".into(), "c".into()], pair);
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::TupleArity { expected: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn enum_test() {
+        let decl = Statement::Enum(
+            "Option".into(),
+            vec![("None".into(), vec![]), ("Some".into(), vec![Type::Number])],
+        );
+        let some = Expr::Construct("Option".into(), "Some".into(), vec![Expr::Constant(Nat(1))]);
+        let case = |variant: &str, binders: Vec<Option<String>>| {
+            (
+                Pattern::Variant("Option".into(), variant.into(), binders),
+                Statement::Skip,
+            )
+        };
+        let program = |cases| {
+            Statement::Sequence(
+                Box::new(decl.clone()),
+                Box::new(Statement::Match(some.clone(), cases)),
+            )
+        };
+
+        let mut sigma = HashMap::new();
+        let cases = vec![case("Some", vec![Some("x".into())]), case("None", vec![])];
+        typecheck_with(&mut sigma, &program(cases)).unwrap();
+        // `x` is only bound in one of the cases, so it doesn't leak
+        assert_eq!(sigma.get("x"), None);
+
+        // Every variant needs a case
+        let result = typecheck(&program(vec![case("Some", vec![None])]));
+        assert!(
+            matches!(result, Err(TypeError::NonExhaustiveMatch(missing)) if missing == vec!["Option::None"])
+        );
+
+        // Patterns need one name for each field
+        let result = typecheck(&program(vec![
+            case("Some", vec![]),
+            (Pattern::Wildcard, Statement::Skip),
+        ]));
+        assert!(matches!(
+            result,
+            Err(TypeError::Arity {
+                expected: 1,
+                got: 0,
+                ..
+            })
+        ));
+
+        // Only enums can be matched on
+        let program = Statement::Match(
+            Expr::Constant(Nat(1)),
+            vec![(Pattern::Wildcard, Statement::Skip)],
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::NotAnEnum(Type::Number))
+        ));
+    }
+
+    #[test]
+    fn address_of_test() {
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::AddressOf("p".into(), "x".into())),
+        );
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert_eq!(sigma.get("x"), Some(&Type::location(Type::Number)));
+        assert_eq!(sigma.get("p"), Some(&Type::location(Type::Number)));
+
+        // After the loop `x` could be a number or a location, depending on whether it ran
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::While(
+                Expr::Constant(Bool(false)),
+                Box::new(Statement::AddressOf("p".into(), "x".into())),
+            )),
+        );
+        assert!(matches!(typecheck(&program), Err(TypeError::AddressTaken(x)) if x == "x"));
+
+        // A body that binds `x` again before taking its address is checked a second time without
+        // `x`, which then isn't bound after the loop
+        let program = crate::parser::parse(
+            "pragma address_of\nlet x = 1\nwhile true do\nlet x = 2\nlet p = &x\nend",
+        )
+        .unwrap();
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert!(sigma.is_empty());
+    }
+
+    #[test]
+    fn choose_test() {
+        // Only `y` is bound with the same type by both statements
+        let program = Statement::Choose(
+            Box::new(Statement::Sequence(
+                Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+                Box::new(Statement::StoreAssign("y".into(), Expr::Constant(Nat(1)))),
+            )),
+            Box::new(Statement::StoreAssign("y".into(), Expr::Constant(Nat(2)))),
+        );
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert_eq!(sigma, HashMap::from([("y".to_string(), Type::Number)]));
+
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign(
+                "s".into(),
+                Expr::Constant(Str("a".into())),
+            )),
+            Box::new(Statement::Havoc("s".into())),
+        );
+        assert!(typecheck(&program).is_err());
+        assert!(typecheck(&Statement::Havoc("z".into())).is_err());
+    }
+
+    #[test]
+    fn parallel_test() {
+        // Everything either branch binds is still bound afterwards
+        let program = Statement::Parallel(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::StoreAssign(
+                "y".into(),
+                Expr::Constant(Str("a".into())),
+            )),
+        );
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert_eq!(sigma.len(), 2);
+
+        let program = Statement::Parallel(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::StoreAssign(
+                "x".into(),
+                Expr::Constant(Str("a".into())),
+            )),
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn dropped_test() {
+        let branches = |then_s: Statement, else_s: Statement| {
+            Statement::Sequence(
+                Box::new(Statement::Conditional(
+                    Expr::Constant(Bool(true)),
+                    Box::new(then_s),
+                    Box::new(else_s),
+                )),
+                Box::new(Statement::Print(Expr::StoreRead("b".into()))),
+            )
+        };
+        let nat = Statement::StoreAssign("b".into(), Expr::Constant(Nat(1)));
+        let string = Statement::StoreAssign("b".into(), Expr::Constant(Str("a".into())));
+
+        // Both bindings are reported, along with their types
+        let result = typecheck(&branches(nat.clone(), string));
+        let expected = vec![
+            Some((Type::Number, "let b = 1".to_string())),
+            Some((Type::String, "let b = \"a\"".to_string())),
+        ];
+        assert!(
+            matches!(result, Err(TypeError::Dropped { name, branches }) if name == "b" && branches == expected)
+        );
+
+        let result = typecheck(&branches(Statement::Skip, nat.clone()));
+        let expected = vec![None, Some((Type::Number, "let b = 1".to_string()))];
+        assert!(matches!(result, Err(TypeError::Dropped { branches, .. }) if branches == expected));
+
+        // Once `b` is bound again, it's unbound after the scope because of the scope
+        let rebound = Statement::Sequence(
+            Box::new(Statement::Conditional(
+                Expr::Constant(Bool(true)),
+                Box::new(nat.clone()),
+                Box::new(Statement::Skip),
+            )),
+            Box::new(Statement::Scope(Box::new(nat.clone()))),
+        );
+        let program = Statement::Sequence(
+            Box::new(rebound),
+            Box::new(Statement::Print(Expr::StoreRead("b".into()))),
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::UnboundVariable)
+        ));
+
+        // A module's own variables aren't visible to its importer, so neither is why they were
+        // dropped
+        let module = Statement::Conditional(
+            Expr::Constant(Bool(true)),
+            Box::new(nat),
+            Box::new(Statement::Skip),
+        );
+        let program = Statement::Sequence(
+            Box::new(Statement::Module(
+                "m".into(),
+                "m.imp".into(),
+                Box::new(module),
+            )),
+            Box::new(Statement::Print(Expr::StoreRead("b".into()))),
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::UnboundVariable)
+        ));
+    }
+
+    #[test]
+    fn loop_test() {
+        // The context after the loop still has `x` with the same type
+        let body = Statement::Sequence(
+            Box::new(Statement::StoreAssign(
+                "y".into(),
+                Expr::StoreRead("x".into()),
+            )),
+            Box::new(Statement::StoreAssign(
+                "x".into(),
+                Expr::StoreRead("y".into()),
+            )),
+        );
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::While(Expr::Constant(Bool(true)), Box::new(body))),
+        );
+        let mut sigma = HashMap::new();
+        typecheck_with(&mut sigma, &program).unwrap();
+        assert_eq!(sigma, HashMap::from([("x".to_string(), Type::Number)]));
+
+        // Rebinding `x` with another type in the body is an error, like anywhere else
+        let program = Statement::Sequence(
+            Box::new(Statement::StoreAssign("x".into(), Expr::Constant(Nat(1)))),
+            Box::new(Statement::While(
+                Expr::Constant(Bool(true)),
+                Box::new(Statement::StoreAssign(
+                    "x".into(),
+                    Expr::Constant(Str("a".into())),
+                )),
+            )),
+        );
+        assert!(matches!(
+            typecheck(&program),
+            Err(TypeError::Mismatch { .. })
+        ));
+
+        // A body that unbinds the first of the variables it's checked with takes a check for each
+        // of them, and one more to see that nothing changes
+        let sigma = HashMap::from([
+            ("a".to_string(), Type::Number),
+            ("b".to_string(), Type::String),
+            ("c".to_string(), Type::Boolean),
+        ]);
+        let mut checks = 0;
+        let unbind_first = |head: &HashMap<String, Type>| {
+            let mut after = head.clone();
+            if let Some(first) = head.keys().min() {
+                after.remove(first);
+            }
+            Ok((after, ()))
+        };
+        let (head, _) = loop_head(&sigma, 4, |head| {
+            checks += 1;
+            unbind_first(head)
+        })
+        .unwrap();
+        assert!(head.is_empty());
+        assert_eq!(checks, 4);
+
+        // With one check less, the head never settles
+        let result = loop_head(&sigma, 3, unbind_first);
+        assert!(matches!(result, Err(TypeError::LoopDiverges(3))));
+    }
 }
@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::syntax::{Clause, Constant, Expr, Ident, Level, Pattern, Statement};
+use crate::typechecker::Type;
+
+/// An expression annotated with its type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedExpr {
+    pub ty: Type,
+    pub kind: ExprKind,
+}
+
+/// The same expressions as `Expr`, with typed subexpressions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    StoreRead(Ident),
+    HeapRead(Ident),
+    Constant(Constant),
+    NatAdd(Box<TypedExpr>, Box<TypedExpr>),
+    NatSub(Box<TypedExpr>, Box<TypedExpr>),
+    NatLeq(Box<TypedExpr>, Box<TypedExpr>),
+    IntNeg(Box<TypedExpr>),
+    ToInt(Box<TypedExpr>),
+    ToNat(Box<TypedExpr>),
+    BoolAnd(Box<TypedExpr>, Box<TypedExpr>),
+    BoolNot(Box<TypedExpr>),
+    StrConcat(Box<TypedExpr>, Box<TypedExpr>),
+    StrLen(Box<TypedExpr>),
+    Equal(Box<TypedExpr>, Box<TypedExpr>),
+    Tuple(Vec<TypedExpr>),
+    /// A `ref` field is given a `StoreRead` of a variable bound to a location
+    Construct(Ident, Ident, Vec<TypedExpr>),
+}
+
+/// Whether a binding introduces a new variable or updates one that's already bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Fresh,
+    /// The variable was already bound with the same type, except for `let p = &x` which rebinds
+    /// `x` to a location
+    Rebound,
+}
+
+/// A variable bound by a statement, with the type it has afterwards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub name: Ident,
+    pub ty: Type,
+    pub kind: BindingKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypedPattern {
+    Variant(Ident, Ident, Vec<Option<Binding>>),
+    Wildcard,
+}
+
+/// A typing context, sorted by name so that it's always written the same way
+pub type Context = BTreeMap<Ident, Type>;
+
+pub(crate) fn context(sigma: &HashMap<Ident, Type>) -> Context {
+    sigma
+        .iter()
+        .map(|(k, ty)| (k.clone(), ty.clone()))
+        .collect()
+}
+
+/// A statement that typechecks, with the contexts before and after it and the type of every
+/// expression and binding in it. Imports have all been resolved into modules by then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedStatement {
+    pub before: Context,
+    pub after: Context,
+    pub kind: StmntKind,
+}
+
+/// The same statements as `Statement`, with typed subexpressions and substatements
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StmntKind {
+    StoreAssign(Binding, TypedExpr),
+    HeapNew(Binding, TypedExpr),
+    HeapUpdate(Ident, TypedExpr),
+    HeapAlias(Binding, Ident),
+    Borrow(Binding, Ident, bool),
+    /// `let p = &x`, binding `p` and rebinding `x` to the same location
+    AddressOf(Binding, Binding),
+    Destructure(Vec<Binding>, TypedExpr),
+    Sequence(Box<TypedStatement>, Box<TypedStatement>),
+    Conditional(TypedExpr, Box<TypedStatement>, Box<TypedStatement>),
+    /// The body starts from the context at the head of the loop
+    While(TypedExpr, Box<TypedStatement>),
+    Scope(Box<TypedStatement>),
+    Choose(Box<TypedStatement>, Box<TypedStatement>),
+    Havoc(Ident),
+    Parallel(Box<TypedStatement>, Box<TypedStatement>),
+    Print(TypedExpr),
+    StoreInput(Binding),
+    HeapInput(Binding),
+    Assert(TypedExpr, Option<TypedExpr>),
+    Assume(TypedExpr),
+    Contract(Clause, TypedExpr),
+    Labeled(Level, Box<TypedStatement>),
+    Const(Ident, TypedExpr),
+    Enum(Ident, Vec<(Ident, Vec<Type>)>),
+    Match(TypedExpr, Vec<(TypedPattern, TypedStatement)>),
+    /// The body starts from an empty context
+    Module(Ident, String, Box<TypedStatement>),
+    Skip,
+}
+
+/// How `id` is bound to a value of type `ty` by a statement starting from `before`
+pub(crate) fn binding(before: &Context, id: &Ident, ty: Type) -> Binding {
+    Binding {
+        name: id.clone(),
+        ty,
+        kind: if before.contains_key(id) {
+            BindingKind::Rebound
+        } else {
+            BindingKind::Fresh
+        },
+    }
+}
+
+// Erases the types, giving back the expression that was typechecked
+impl From<&TypedExpr> for Expr {
+    fn from(typed: &TypedExpr) -> Self {
+        let sub = |e: &TypedExpr| Box::new(Expr::from(e));
+        match &typed.kind {
+            ExprKind::StoreRead(x) => Expr::StoreRead(x.clone()),
+            ExprKind::HeapRead(x) => Expr::HeapRead(x.clone()),
+            ExprKind::Constant(c) => Expr::Constant(c.clone()),
+            ExprKind::NatAdd(a, b) => Expr::NatAdd(sub(a), sub(b)),
+            ExprKind::NatSub(a, b) => Expr::NatSub(sub(a), sub(b)),
+            ExprKind::NatLeq(a, b) => Expr::NatLeq(sub(a), sub(b)),
+            ExprKind::IntNeg(a) => Expr::IntNeg(sub(a)),
+            ExprKind::ToInt(a) => Expr::ToInt(sub(a)),
+            ExprKind::ToNat(a) => Expr::ToNat(sub(a)),
+            ExprKind::BoolAnd(a, b) => Expr::BoolAnd(sub(a), sub(b)),
+            ExprKind::BoolNot(a) => Expr::BoolNot(sub(a)),
+            ExprKind::StrConcat(a, b) => Expr::StrConcat(sub(a), sub(b)),
+            ExprKind::StrLen(a) => Expr::StrLen(sub(a)),
+            ExprKind::Equal(a, b) => Expr::Equal(sub(a), sub(b)),
+            ExprKind::Tuple(components) => Expr::Tuple(components.iter().map(Expr::from).collect()),
+            ExprKind::Construct(name, variant, args) => Expr::Construct(
+                name.clone(),
+                variant.clone(),
+                args.iter().map(Expr::from).collect(),
+            ),
+        }
+    }
+}
+
+// Erases the types of the binders
+impl From<&TypedPattern> for Pattern {
+    fn from(typed: &TypedPattern) -> Self {
+        match typed {
+            TypedPattern::Variant(name, variant, binders) => Pattern::Variant(
+                name.clone(),
+                variant.clone(),
+                binders
+                    .iter()
+                    .map(|b| b.as_ref().map(|b| b.name.clone()))
+                    .collect(),
+            ),
+            TypedPattern::Wildcard => Pattern::Wildcard,
+        }
+    }
+}
+
+// Erases the types and contexts, giving back the statement that was typechecked
+impl From<&TypedStatement> for Statement {
+    fn from(typed: &TypedStatement) -> Self {
+        let sub = |s: &TypedStatement| Box::new(Statement::from(s));
+        let bound = |b: &Binding| b.name.clone();
+        match &typed.kind {
+            StmntKind::StoreAssign(b, e) => Statement::StoreAssign(bound(b), e.into()),
+            StmntKind::HeapNew(b, e) => Statement::HeapNew(bound(b), e.into()),
+            StmntKind::HeapUpdate(id, e) => Statement::HeapUpdate(id.clone(), e.into()),
+            StmntKind::HeapAlias(b, id) => Statement::HeapAlias(bound(b), id.clone()),
+            StmntKind::Borrow(b, id, mutable) => Statement::Borrow(bound(b), id.clone(), *mutable),
+            StmntKind::AddressOf(p, x) => Statement::AddressOf(bound(p), bound(x)),
+            StmntKind::Destructure(bs, e) => {
+                Statement::Destructure(bs.iter().map(bound).collect(), e.into())
+            }
+            StmntKind::Sequence(s1, s2) => Statement::Sequence(sub(s1), sub(s2)),
+            StmntKind::Conditional(c, s1, s2) => Statement::Conditional(c.into(), sub(s1), sub(s2)),
+            StmntKind::While(c, body) => Statement::While(c.into(), sub(body)),
+            StmntKind::Scope(body) => Statement::Scope(sub(body)),
+            StmntKind::Choose(s1, s2) => Statement::Choose(sub(s1), sub(s2)),
+            StmntKind::Havoc(id) => Statement::Havoc(id.clone()),
+            StmntKind::Parallel(s1, s2) => Statement::Parallel(sub(s1), sub(s2)),
+            StmntKind::Print(e) => Statement::Print(e.into()),
+            StmntKind::StoreInput(b) => Statement::StoreInput(bound(b)),
+            StmntKind::HeapInput(b) => Statement::HeapInput(bound(b)),
+            StmntKind::Assert(c, m) => Statement::Assert(c.into(), m.as_ref().map(Expr::from)),
+            StmntKind::Assume(c) => Statement::Assume(c.into()),
+            StmntKind::Contract(clause, c) => Statement::Contract(*clause, c.into()),
+            StmntKind::Labeled(level, s) => Statement::Labeled(*level, sub(s)),
+            StmntKind::Const(id, e) => Statement::Const(id.clone(), e.into()),
+            StmntKind::Enum(name, variants) => Statement::Enum(name.clone(), variants.clone()),
+            StmntKind::Match(e, cases) => {
+                let cases = cases
+                    .iter()
+                    .map(|(pattern, body)| (pattern.into(), Statement::from(body)))
+                    .collect();
+                Statement::Match(e.into(), cases)
+            }
+            StmntKind::Module(name, id, body) => {
+                Statement::Module(name.clone(), id.clone(), sub(body))
+            }
+            StmntKind::Skip => Statement::Skip,
+        }
+    }
+}
+
+// The typed AST is serialised as an S-expression, with a node for every expression and statement.
+// Tools can rely on this format, so it should only ever be extended.
+
+// Types as S-expressions, like `(ref (tuple nat str))`
+struct SType<'a>(&'a Type);
+
+impl fmt::Display for SType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Type::Location(pointee) => write!(f, "(ref {})", SType(pointee)),
+            Type::Tuple(components) => {
+                write!(f, "(tuple")?;
+                components
+                    .iter()
+                    .try_for_each(|c| write!(f, " {}", SType(c)))?;
+                write!(f, ")")
+            }
+            Type::Enum(name) => write!(f, "(enum {})", name),
+            ty => write!(f, "{}", ty),
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            BindingKind::Fresh => "fresh",
+            BindingKind::Rebound => "rebound",
+        };
+        write!(f, "(binding {} {} {})", self.name, SType(&self.ty), kind)
+    }
+}
+
+fn node<T: fmt::Display>(f: &mut fmt::Formatter<'_>, head: &str, children: &[T]) -> fmt::Result {
+    write!(f, "({}", head)?;
+    children.iter().try_for_each(|c| write!(f, " {}", c))?;
+    write!(f, ")")
+}
+
+impl fmt::Display for TypedExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ty = SType(&self.ty);
+        let (op, children): (&str, Vec<&TypedExpr>) = match &self.kind {
+            ExprKind::StoreRead(x) => return write!(f, "(store-read {} {})", ty, x),
+            ExprKind::HeapRead(x) => return write!(f, "(heap-read {} {})", ty, x),
+            ExprKind::Constant(Constant::Int(i)) => return write!(f, "(const {} {})", ty, i),
+            ExprKind::Constant(c) => return write!(f, "(const {} {})", ty, c),
+            ExprKind::NatAdd(a, b) => ("add", vec![a, b]),
+            ExprKind::NatSub(a, b) => ("sub", vec![a, b]),
+            ExprKind::NatLeq(a, b) => ("leq", vec![a, b]),
+            ExprKind::IntNeg(a) => ("neg", vec![a]),
+            ExprKind::ToInt(a) => ("to-int", vec![a]),
+            ExprKind::ToNat(a) => ("to-nat", vec![a]),
+            ExprKind::BoolAnd(a, b) => ("and", vec![a, b]),
+            ExprKind::BoolNot(a) => ("not", vec![a]),
+            ExprKind::StrConcat(a, b) => ("concat", vec![a, b]),
+            ExprKind::StrLen(a) => ("len", vec![a]),
+            ExprKind::Equal(a, b) => ("eq", vec![a, b]),
+            ExprKind::Tuple(components) => ("tuple", components.iter().collect()),
+            ExprKind::Construct(name, variant, args) => {
+                let head = format!("construct {} {}::{}", ty, name, variant);
+                return node(f, &head, args);
+            }
+        };
+        node(f, &format!("{} {}", op, ty), &children)
+    }
+}
+
+impl fmt::Display for TypedPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypedPattern::Variant(name, variant, binders) => {
+                let binders: Vec<_> = binders
+                    .iter()
+                    .map(|b| b.as_ref().map_or("_".to_owned(), Binding::to_string))
+                    .collect();
+                node(f, &format!("variant {}::{}", name, variant), &binders)
+            }
+            TypedPattern::Wildcard => write!(f, "_"),
+        }
+    }
+}
+
+impl fmt::Display for TypedStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            StmntKind::StoreAssign(b, e) => write!(f, "(let {} {})", b, e),
+            StmntKind::HeapNew(b, e) => write!(f, "(new {} {})", b, e),
+            StmntKind::HeapUpdate(id, e) => write!(f, "(update {} {})", id, e),
+            StmntKind::HeapAlias(b, id) => write!(f, "(alias {} {})", b, id),
+            StmntKind::Borrow(b, id, true) => write!(f, "(borrow-mut {} {})", b, id),
+            StmntKind::Borrow(b, id, false) => write!(f, "(borrow {} {})", b, id),
+            StmntKind::AddressOf(p, x) => write!(f, "(address-of {} {})", p, x),
+            StmntKind::Destructure(bindings, e) => {
+                let bindings: Vec<_> = bindings.iter().map(Binding::to_string).collect();
+                write!(f, "(destructure ({}) {})", bindings.join(" "), e)
+            }
+            StmntKind::Sequence(s1, s2) => write!(f, "(seq {} {})", s1, s2),
+            StmntKind::Conditional(c, s1, s2) => write!(f, "(if {} {} {})", c, s1, s2),
+            StmntKind::While(c, body) => write!(f, "(while {} {})", c, body),
+            StmntKind::Scope(body) => write!(f, "(scope {})", body),
+            StmntKind::Choose(s1, s2) => write!(f, "(choose {} {})", s1, s2),
+            StmntKind::Havoc(id) => write!(f, "(havoc {})", id),
+            StmntKind::Parallel(s1, s2) => write!(f, "(par {} {})", s1, s2),
+            StmntKind::Print(e) => write!(f, "(print {})", e),
+            StmntKind::StoreInput(b) => write!(f, "(read {})", b),
+            StmntKind::HeapInput(b) => write!(f, "(read-new {})", b),
+            StmntKind::Assert(c, None) => write!(f, "(assert {})", c),
+            StmntKind::Assert(c, Some(m)) => write!(f, "(assert {} {})", c, m),
+            StmntKind::Assume(c) => write!(f, "(assume {})", c),
+            StmntKind::Contract(clause, c) => write!(f, "({} {})", clause, c),
+            StmntKind::Labeled(level, s) => write!(f, "(labeled {} {})", level, s),
+            StmntKind::Const(id, e) => write!(f, "(const-decl {} {})", id, e),
+            StmntKind::Enum(name, variants) => {
+                let variants: Vec<_> = variants
+                    .iter()
+                    .map(|(variant, fields)| {
+                        let fields: Vec<_> = fields.iter().map(|t| SType(t).to_string()).collect();
+                        format!("({})", [vec![variant.clone()], fields].concat().join(" "))
+                    })
+                    .collect();
+                node(f, &format!("enum {}", name), &variants)
+            }
+            StmntKind::Match(e, cases) => {
+                let cases: Vec<_> = cases
+                    .iter()
+                    .map(|(pattern, body)| format!("(case {} {})", pattern, body))
+                    .collect();
+                node(f, &format!("match {}", e), &cases)
+            }
+            StmntKind::Module(name, id, body) => {
+                write!(f, "(module {} {:?} {})", name, id, body)
+            }
+            StmntKind::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Context, ExprKind, StmntKind};
+    use crate::parser::parse;
+    use crate::typechecker::{typecheck, Type};
+
+    #[test]
+    fn test_typed() {
+        let program = parse("let x = 1\nlet p <- (x, \"a\")\nlet x = x + 2").unwrap();
+        let typed = typecheck(&program).unwrap();
+        let expected = "(seq (seq (seq skip \
+            (let (binding x nat fresh) (const nat 1))) \
+            (new (binding p (ref (tuple nat str)) fresh) (tuple (tuple nat str) (store-read nat x) (const str \"a\")))) \
+            (let (binding x nat rebound) (add nat (store-read nat x) (const nat 2))))";
+        assert_eq!(typed.to_string(), expected);
+    }
+
+    #[test]
+    fn test_contexts() {
+        let program =
+            parse("let x = 1\nwhile x < 3 do\nlet y = \"a\"\nlet x = x + 1\nend").unwrap();
+        let typed = typecheck(&program).unwrap();
+        let StmntKind::Sequence(_, luup) = typed.kind else {
+            panic!()
+        };
+        let StmntKind::While(_, body) = &luup.kind else {
+            panic!()
+        };
+        let nat = Context::from([("x".to_owned(), Type::Number)]);
+        // The body starts from the head of the loop, and nothing it binds is bound after the loop
+        assert_eq!(luup.before, nat);
+        assert_eq!(luup.after, nat);
+        assert_eq!(body.before, nat);
+        assert_eq!(body.after.get("y"), Some(&Type::String));
+    }
+
+    #[test]
+    fn test_typed_match() {
+        let source = "enum List = Nil | Cons(int, ref List)\nlet l <- List::Nil\n\
+            let c = List::Cons(-1, l)\nmatch c\ncase List::Cons(h, t) then\nprint h\n\
+            case _ then\nskip\nend";
+        let typed = typecheck(&parse(source).unwrap()).unwrap();
+        let (construct, matched) = match typed.kind {
+            StmntKind::Sequence(s1, s2) => match (s1.kind, s2.kind) {
+                (StmntKind::Sequence(_, s), StmntKind::Match(_, cases)) => (*s, cases),
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        // The location passed to the `ref` field is typed as one
+        let StmntKind::StoreAssign(binding, expr) = construct.kind else {
+            panic!()
+        };
+        assert_eq!(binding.ty, Type::Enum("List".into()));
+        let ExprKind::Construct(_, _, args) = expr.kind else {
+            panic!()
+        };
+        assert_eq!(args[1].ty, Type::location(Type::Enum("List".into())));
+
+        assert_eq!(
+            matched[0].0.to_string(),
+            "(variant List::Cons (binding h int fresh) (binding t (ref (enum List)) fresh))"
+        );
+        assert!(matches!(&matched[1].1.kind, StmntKind::Sequence(_, _)));
+    }
+}
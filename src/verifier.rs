@@ -0,0 +1,748 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Not;
+
+use crate::arithmetic::{satisfiable, Formula, Linear, Sat};
+use crate::error::VerifyError;
+use crate::evaluator::MAX_ITERATIONS;
+use crate::interval::{self, INT, NAT};
+use crate::points_to::{self, PointsTo};
+use crate::syntax::{Clause, Constant, Expr, Ident, Statement};
+use crate::termination::{self, Verdict};
+use crate::typechecker::{typecheck_typed, Type};
+use crate::typed::{Binding, StmntKind, TypedPattern, TypedStatement};
+
+/// What a verification condition shows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Goal {
+    /// Every `assert` and `ensures` holds, given the `requires` and loop invariants
+    Program,
+    /// The invariant of the loop still holds after every iteration. Loops are numbered like
+    /// `interval::Analysis::points`.
+    Preserved { point: usize, cond: Expr },
+    /// The rest of the program can be verified from the invariant once the loop stops, and from its
+    /// condition being false if it always stops before `evaluator::MAX_ITERATIONS` iterations
+    Exit { point: usize, cond: Expr },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Proved,
+    /// Some values of the variables make the condition false. For a loop, these might not be
+    /// values it can actually reach, which a stronger invariant would rule out.
+    Refuted,
+    /// The condition was too large to decide
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Obligation {
+    pub goal: Goal,
+    /// A condition that must hold for any values of its variables
+    pub condition: Expr,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Obligation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.goal {
+            Goal::Program => write!(f, "The program's assertions and postconditions")?,
+            Goal::Preserved { point, cond } => write!(
+                f,
+                "#{} while {} do: the invariant after every iteration",
+                point, cond
+            )?,
+            Goal::Exit { point, cond } => write!(
+                f,
+                "#{} while {} do: the rest of the program after it",
+                point, cond
+            )?,
+        }
+        match self.outcome {
+            Outcome::Proved => write!(f, ": proved"),
+            Outcome::Refuted => write!(f, ": not valid"),
+            Outcome::Unknown => write!(f, ": unknown"),
+        }
+    }
+}
+
+/// Proves that every `assert` and `ensures` in `program` holds, assuming its `requires`, with
+/// the variables in `sigma` bound to any value. Every loop needs an `invariant` that holds when
+/// it's reached and after every iteration, which is assumed after it. Without one, the invariant
+/// is `true`. Since the evaluator leaves a loop after `evaluator::MAX_ITERATIONS` iterations, its
+/// condition is only assumed to be false after it if `termination::check` shows that it stops
+/// before that.
+///
+/// The weakest precondition of the program is computed backwards from its end, and the
+/// resulting conditions are decided by `arithmetic::satisfiable`, which follows the evaluator's
+/// arithmetic, so additions that overflow give 0 and nats stop at 0. A heap update is also
+/// applied to every other variable `points_to::analyze` says might point to the same cell.
+pub fn verify(
+    program: &Statement,
+    sigma: &HashMap<Ident, Type>,
+) -> Result<Vec<Obligation>, VerifyError> {
+    let typed = typecheck_typed(&mut sigma.clone(), program).map_err(VerifyError::Type)?;
+    let mut types = sigma.clone();
+    let mut conflicts = HashSet::new();
+    bindings(&typed, &mut types, &mut conflicts);
+    let mut verifier = Verifier {
+        ids: interval::statements(program)
+            .into_iter()
+            .enumerate()
+            .map(|(id, stmnt)| (stmnt as *const _, id))
+            .collect(),
+        points_to: points_to::analyze(program, &HashMap::new()),
+        bounded: bounded(program),
+        types,
+        conflicts,
+        fresh: 0,
+        loops: Vec::new(),
+    };
+    let program_vc = verifier.wp(program, tru())?;
+
+    let mut goals = vec![(Goal::Program, program_vc)];
+    verifier.loops.sort_by_key(|(goal, _)| match goal {
+        Goal::Preserved { point, .. } => (*point, 0),
+        Goal::Exit { point, .. } => (*point, 1),
+        Goal::Program => (0, 0),
+    });
+    goals.append(&mut verifier.loops);
+    goals
+        .into_iter()
+        .map(|(goal, condition)| {
+            let mut translator = Translator {
+                types: &verifier.types,
+                conflicts: &verifier.conflicts,
+                ints: HashMap::new(),
+                bools: HashMap::new(),
+            };
+            let formula = translator.formula(&condition)?;
+            // A counterexample has to respect the range of every variable
+            let bounds = translator.ints.values().filter_map(|(x, ty)| {
+                let range = match ty {
+                    Type::Number => NAT,
+                    Type::Int => INT,
+                    _ => return None,
+                };
+                let x = Linear::var(*x);
+                Some(
+                    Formula::leq(&Linear::constant(range.lo), &x)
+                        .and(Formula::leq(&x, &Linear::constant(range.hi))),
+                )
+            });
+            let counterexample = bounds.fold(formula.not(), Formula::and);
+            let outcome = match satisfiable(&counterexample) {
+                Sat::Unsat => Outcome::Proved,
+                Sat::Sat => Outcome::Refuted,
+                Sat::Unknown => Outcome::Unknown,
+            };
+            Ok(Obligation {
+                goal,
+                condition,
+                outcome,
+            })
+        })
+        .collect()
+}
+
+// The type of every variable bound in the program, and the names bound with different types
+fn bindings(
+    stmnt: &TypedStatement,
+    types: &mut HashMap<Ident, Type>,
+    conflicts: &mut HashSet<Ident>,
+) {
+    match &stmnt.kind {
+        StmntKind::StoreAssign(b, _)
+        | StmntKind::HeapNew(b, _)
+        | StmntKind::HeapAlias(b, _)
+        | StmntKind::Borrow(b, _, _)
+        | StmntKind::StoreInput(b)
+        | StmntKind::HeapInput(b) => bind(b, types, conflicts),
+        StmntKind::AddressOf(p, x) => {
+            bind(p, types, conflicts);
+            bind(x, types, conflicts);
+        }
+        StmntKind::Destructure(bs, _) => bs.iter().for_each(|b| bind(b, types, conflicts)),
+        StmntKind::Sequence(s1, s2)
+        | StmntKind::Conditional(_, s1, s2)
+        | StmntKind::Choose(s1, s2)
+        | StmntKind::Parallel(s1, s2) => {
+            bindings(s1, types, conflicts);
+            bindings(s2, types, conflicts);
+        }
+        StmntKind::While(_, body)
+        | StmntKind::Scope(body)
+        | StmntKind::Labeled(_, body)
+        | StmntKind::Module(_, _, body) => bindings(body, types, conflicts),
+        StmntKind::Match(_, cases) => {
+            for (pattern, body) in cases {
+                if let TypedPattern::Variant(_, _, binders) = pattern {
+                    binders
+                        .iter()
+                        .flatten()
+                        .for_each(|b| bind(b, types, conflicts));
+                }
+                bindings(body, types, conflicts);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A loop stops by itself if it never runs, or if its ranking function is less than
+// `MAX_ITERATIONS` when it's reached, since it's at least 0 in every iteration and goes down by 1
+fn bounded(program: &Statement) -> HashSet<usize> {
+    let analysis = interval::analyze(program, &HashMap::new());
+    termination::check(program, &HashMap::new())
+        .into_iter()
+        .filter(|report| match &report.verdict {
+            Verdict::Terminating(None) => true,
+            Verdict::Terminating(Some(ranking)) => analysis.points[report.point]
+                .state
+                .as_ref()
+                .and_then(|state| interval::eval(state, ranking).interval())
+                .is_some_and(|range| range.hi < MAX_ITERATIONS as i128),
+            _ => false,
+        })
+        .map(|report| report.point)
+        .collect()
+}
+
+fn bind(binding: &Binding, types: &mut HashMap<Ident, Type>, conflicts: &mut HashSet<Ident>) {
+    if let Some(old) = types.insert(binding.name.clone(), binding.ty.clone()) {
+        if old != binding.ty {
+            conflicts.insert(binding.name.clone());
+        }
+    }
+}
+
+struct Verifier {
+    ids: HashMap<*const Statement, usize>,
+    points_to: PointsTo,
+    // The loops that stop before the evaluator would cut them off
+    bounded: HashSet<usize>,
+    types: HashMap<Ident, Type>,
+    conflicts: HashSet<Ident>,
+    // How many variables were made up for `read` and `havoc`
+    fresh: usize,
+    // The conditions of every loop, which don't depend on what comes before it
+    loops: Vec<(Goal, Expr)>,
+}
+
+impl Verifier {
+    // The weakest condition before `stmnt` that makes `post` true after it
+    fn wp(&mut self, stmnt: &Statement, post: Expr) -> Result<Expr, VerifyError> {
+        Ok(match stmnt {
+            Statement::Sequence(s1, s2) => {
+                let post = self.wp(s2, post)?;
+                self.wp(s1, post)?
+            }
+            Statement::Labeled(_, s) => self.wp(s, post)?,
+            Statement::Skip
+            | Statement::Print(_)
+            | Statement::Const(_, _)
+            | Statement::Enum(_, _)
+            | Statement::Import(_, _) => post,
+            Statement::StoreAssign(x, e) => subst(&post, &[(store(x), e.clone())]),
+            Statement::HeapNew(p, e) => self.alloc(p, e.clone(), post),
+            Statement::HeapAlias(q, p) | Statement::Borrow(q, p, _) => {
+                subst(&post, &[(store(q), store(p)), (heap(q), heap(p))])
+            }
+            // The cell of every variable that might point to the same cell is updated if it's
+            // the same location
+            Statement::HeapUpdate(p, e) => {
+                let point = self.ids[&(stmnt as *const _)];
+                let mut aliases = Vec::new();
+                for atom in atoms(&post) {
+                    if let Expr::HeapRead(q) = atom {
+                        if q != *p
+                            && self.points_to.may_alias(point, p, &q)
+                            && !aliases.contains(&q)
+                        {
+                            aliases.push(q);
+                        }
+                    }
+                }
+                let mut pre = tru();
+                for subset in 0..1usize << aliases.len() {
+                    let mut same = tru();
+                    let mut substitution = vec![(heap(p), e.clone())];
+                    for (i, q) in aliases.iter().enumerate() {
+                        let equal = Expr::Equal(Box::new(store(p)), Box::new(store(q)));
+                        if subset & (1 << i) != 0 {
+                            same = and(same, equal);
+                            substitution.push((heap(q), e.clone()));
+                        } else {
+                            same = and(same, not(equal));
+                        }
+                    }
+                    pre = and(pre, implies(same, subst(&post, &substitution)));
+                }
+                pre
+            }
+            Statement::Destructure(ids, Expr::Tuple(es)) if ids.len() == es.len() => {
+                let mut substitution = Vec::new();
+                for (x, e) in ids.iter().zip(es) {
+                    substitution.push((store(x), e.clone()));
+                    if let Expr::StoreRead(y) = e {
+                        substitution.push((heap(x), heap(y)));
+                    }
+                }
+                subst(&post, &substitution)
+            }
+            Statement::StoreInput(x) | Statement::Havoc(x) => {
+                let fresh = self.fresh(x);
+                subst(&post, &[(store(x), store(&fresh)), (heap(x), heap(&fresh))])
+            }
+            Statement::HeapInput(p) => {
+                let value = self.fresh(p);
+                self.types.insert(value.clone(), Type::Number);
+                self.alloc(p, store(&value), post)
+            }
+            Statement::Assert(cond, _)
+            | Statement::Contract(Clause::Ensures | Clause::Invariant, cond) => {
+                and(cond.clone(), post)
+            }
+            Statement::Assume(cond) | Statement::Contract(Clause::Requires, cond) => {
+                implies(cond.clone(), post)
+            }
+            Statement::Conditional(cond, then_s, else_s) => {
+                let then_pre = self.wp(then_s, post.clone())?;
+                let else_pre = self.wp(else_s, post)?;
+                and(
+                    implies(cond.clone(), then_pre),
+                    implies(not(cond.clone()), else_pre),
+                )
+            }
+            Statement::Choose(s1, s2) => {
+                let s1_pre = self.wp(s1, post.clone())?;
+                and(s1_pre, self.wp(s2, post)?)
+            }
+            Statement::Scope(body) => self.wp(body, post)?,
+            Statement::While(cond, body) => {
+                let point = self.ids[&(stmnt as *const _)];
+                let invariant = invariants(body).into_iter().cloned().fold(tru(), and);
+                let body_pre = self.wp(body, invariant.clone())?;
+                self.loops.push((
+                    Goal::Preserved {
+                        point,
+                        cond: cond.clone(),
+                    },
+                    implies(and(invariant.clone(), cond.clone()), body_pre),
+                ));
+                // The evaluator might cut the loop off while its condition still holds
+                let exit = if self.bounded.contains(&point) {
+                    and(invariant.clone(), not(cond.clone()))
+                } else {
+                    invariant.clone()
+                };
+                self.loops.push((
+                    Goal::Exit {
+                        point,
+                        cond: cond.clone(),
+                    },
+                    implies(exit, post),
+                ));
+                invariant
+            }
+            Statement::Destructure(_, _)
+            | Statement::AddressOf(_, _)
+            | Statement::Parallel(_, _)
+            | Statement::Match(_, _)
+            | Statement::Module(_, _, _) => {
+                return Err(VerifyError::Unsupported(interval::header(stmnt)))
+            }
+        })
+    }
+
+    // A new variable of the same type as `x`, standing for any value it could be given
+    // `p` is bound to a new cell holding `e`, which isn't any location already in `post`
+    fn alloc(&mut self, p: &Ident, e: Expr, post: Expr) -> Expr {
+        let fresh = self.fresh(p);
+        let mut distinct = tru();
+        for atom in atoms(&post) {
+            if let Expr::StoreRead(q) = &atom {
+                if q != p && matches!(self.types.get(q), Some(Type::Location(_))) {
+                    let equal = Expr::Equal(Box::new(store(&fresh)), Box::new(atom.clone()));
+                    distinct = and(distinct, not(equal));
+                }
+            }
+        }
+        implies(
+            distinct,
+            subst(&post, &[(store(p), store(&fresh)), (heap(p), e)]),
+        )
+    }
+
+    fn fresh(&mut self, x: &Ident) -> Ident {
+        let fresh = format!("{}#{}", x, self.fresh);
+        self.fresh += 1;
+        let ty = self.types.get(x).cloned().unwrap_or(Type::Number);
+        self.types.insert(fresh.clone(), ty);
+        fresh
+    }
+}
+
+// The `invariant` clauses the parser put at the start of a loop's body
+fn invariants(body: &Statement) -> Vec<&Expr> {
+    let mut invariants = Vec::new();
+    for stmnt in interval::statements(body) {
+        match stmnt {
+            Statement::Contract(Clause::Invariant, inv) => invariants.push(inv),
+            _ => break,
+        }
+    }
+    invariants
+}
+
+fn tru() -> Expr {
+    Expr::Constant(Constant::Bool(true))
+}
+
+fn store(x: &str) -> Expr {
+    Expr::StoreRead(x.to_owned())
+}
+
+fn heap(p: &str) -> Expr {
+    Expr::HeapRead(p.to_owned())
+}
+
+fn and(a: Expr, b: Expr) -> Expr {
+    match (a, b) {
+        (a, b) if a == tru() => b,
+        (a, b) if b == tru() => a,
+        (a, b) => Expr::BoolAnd(Box::new(a), Box::new(b)),
+    }
+}
+
+fn not(a: Expr) -> Expr {
+    match a {
+        Expr::BoolNot(a) => *a,
+        a => Expr::BoolNot(Box::new(a)),
+    }
+}
+
+fn implies(a: Expr, b: Expr) -> Expr {
+    if a == tru() || b == tru() {
+        b
+    } else {
+        not(and(a, not(b)))
+    }
+}
+
+// Replaces every variable and heap read in `substitution` at the same time
+fn subst(e: &Expr, substitution: &[(Expr, Expr)]) -> Expr {
+    if let Some((_, by)) = substitution.iter().find(|(atom, _)| atom == e) {
+        return by.clone();
+    }
+    let sub = |a: &Expr| Box::new(subst(a, substitution));
+    match e {
+        Expr::StoreRead(_) | Expr::HeapRead(_) | Expr::Constant(_) => e.clone(),
+        Expr::NatAdd(a, b) => Expr::NatAdd(sub(a), sub(b)),
+        Expr::NatSub(a, b) => Expr::NatSub(sub(a), sub(b)),
+        Expr::NatLeq(a, b) => Expr::NatLeq(sub(a), sub(b)),
+        Expr::IntNeg(a) => Expr::IntNeg(sub(a)),
+        Expr::ToInt(a) => Expr::ToInt(sub(a)),
+        Expr::ToNat(a) => Expr::ToNat(sub(a)),
+        Expr::BoolAnd(a, b) => Expr::BoolAnd(sub(a), sub(b)),
+        Expr::BoolNot(a) => Expr::BoolNot(sub(a)),
+        Expr::StrConcat(a, b) => Expr::StrConcat(sub(a), sub(b)),
+        Expr::StrLen(a) => Expr::StrLen(sub(a)),
+        Expr::Equal(a, b) => Expr::Equal(sub(a), sub(b)),
+        Expr::Tuple(es) => Expr::Tuple(es.iter().map(|e| subst(e, substitution)).collect()),
+        Expr::Construct(name, variant, es) => Expr::Construct(
+            name.clone(),
+            variant.clone(),
+            es.iter().map(|e| subst(e, substitution)).collect(),
+        ),
+    }
+}
+
+// The variables and heap reads in `e`, each once
+fn atoms(e: &Expr) -> Vec<Expr> {
+    let mut atoms = Vec::new();
+    for atom in all_atoms(e) {
+        if !atoms.contains(&atom) {
+            atoms.push(atom);
+        }
+    }
+    atoms
+}
+
+fn all_atoms(e: &Expr) -> Vec<Expr> {
+    match e {
+        Expr::StoreRead(_) | Expr::HeapRead(_) => vec![e.clone()],
+        Expr::Constant(_) => vec![],
+        Expr::NatAdd(a, b)
+        | Expr::NatSub(a, b)
+        | Expr::NatLeq(a, b)
+        | Expr::BoolAnd(a, b)
+        | Expr::StrConcat(a, b)
+        | Expr::Equal(a, b) => [all_atoms(a), all_atoms(b)].concat(),
+        Expr::IntNeg(a) | Expr::ToInt(a) | Expr::ToNat(a) | Expr::BoolNot(a) | Expr::StrLen(a) => {
+            all_atoms(a)
+        }
+        Expr::Tuple(es) | Expr::Construct(_, _, es) => es.iter().flat_map(all_atoms).collect(),
+    }
+}
+
+// Turns expressions into formulas, numbering their variables
+struct Translator<'a> {
+    types: &'a HashMap<Ident, Type>,
+    conflicts: &'a HashSet<Ident>,
+    // The number and type of every nat and int variable or heap read, by how it's written
+    ints: HashMap<String, (usize, Type)>,
+    bools: HashMap<String, usize>,
+}
+
+impl Translator<'_> {
+    fn ty(&self, e: &Expr) -> Result<Type, VerifyError> {
+        let unsupported = || VerifyError::Unsupported(e.to_string());
+        match e {
+            Expr::StoreRead(x) if !self.conflicts.contains(x) => {
+                self.types.get(x).cloned().ok_or_else(unsupported)
+            }
+            Expr::HeapRead(p) if !self.conflicts.contains(p) => match self.types.get(p) {
+                Some(Type::Location(ty)) => Ok(*ty.clone()),
+                _ => Err(unsupported()),
+            },
+            Expr::Constant(Constant::Nat(_)) => Ok(Type::Number),
+            Expr::Constant(Constant::Int(_)) => Ok(Type::Int),
+            Expr::Constant(Constant::Bool(_)) | Expr::NatLeq(_, _) | Expr::Equal(_, _) => {
+                Ok(Type::Boolean)
+            }
+            Expr::BoolAnd(_, _) | Expr::BoolNot(_) => Ok(Type::Boolean),
+            Expr::NatAdd(a, _) | Expr::NatSub(a, _) => self.ty(a),
+            Expr::IntNeg(_) => Ok(Type::Int),
+            _ => Err(unsupported()),
+        }
+    }
+
+    fn formula(&mut self, e: &Expr) -> Result<Formula, VerifyError> {
+        Ok(match e {
+            Expr::Constant(Constant::Bool(b)) => Formula::Const(*b),
+            Expr::StoreRead(_) | Expr::HeapRead(_) if self.ty(e)? == Type::Boolean => {
+                let next = self.bools.len();
+                Formula::Bool(*self.bools.entry(e.to_string()).or_insert(next))
+            }
+            Expr::BoolAnd(a, b) => self.formula(a)?.and(self.formula(b)?),
+            Expr::BoolNot(a) => self.formula(a)?.not(),
+            Expr::NatLeq(a, b) => self.compare(a, b, Formula::leq)?,
+            Expr::Equal(a, b) if self.ty(a)? == Type::Boolean => {
+                let (a, b) = (self.formula(a)?, self.formula(b)?);
+                a.clone().and(b.clone()).or(a.not().and(b.not()))
+            }
+            Expr::Equal(a, b) => self.compare(a, b, |a, b| Formula::Eq(a.sub(b)))?,
+            _ => return Err(VerifyError::Unsupported(e.to_string())),
+        })
+    }
+
+    fn compare(
+        &mut self,
+        a: &Expr,
+        b: &Expr,
+        compare: impl Fn(&Linear, &Linear) -> Formula,
+    ) -> Result<Formula, VerifyError> {
+        let (a, b) = (self.term(a)?, self.term(b)?);
+        let mut cases = Formula::Const(false);
+        for (a_guard, a) in &a {
+            for (b_guard, b) in &b {
+                let case = a_guard.clone().and(b_guard.clone()).and(compare(a, b));
+                cases = cases.or(case);
+            }
+        }
+        Ok(cases)
+    }
+
+    // The values of a nat or int expression, each with the condition under which it's that value
+    fn term(&mut self, e: &Expr) -> Result<Vec<(Formula, Linear)>, VerifyError> {
+        let ty = self.ty(e)?;
+        let range = match ty {
+            Type::Number => NAT,
+            Type::Int => INT,
+            // Locations are only compared, so any numbers tell them apart
+            Type::Location(_) if matches!(e, Expr::StoreRead(_)) => INT,
+            _ => return Err(VerifyError::Unsupported(e.to_string())),
+        };
+        let (lo, hi) = (Linear::constant(range.lo), Linear::constant(range.hi));
+        let fits = |l: &Linear| Formula::leq(&lo, l).and(Formula::leq(l, &hi));
+        let zero = Linear::constant(0);
+        Ok(match e {
+            Expr::Constant(Constant::Nat(n)) => {
+                vec![(Formula::Const(true), Linear::constant(*n as i128))]
+            }
+            Expr::Constant(Constant::Int(i)) => {
+                vec![(Formula::Const(true), Linear::constant(*i as i128))]
+            }
+            Expr::StoreRead(_) | Expr::HeapRead(_) => {
+                let next = self.ints.len();
+                let (x, _) = self.ints.entry(e.to_string()).or_insert((next, ty));
+                vec![(Formula::Const(true), Linear::var(*x))]
+            }
+            // Results that don't fit are 0, and subtracting a larger nat gives 0
+            Expr::NatAdd(a, b) | Expr::NatSub(a, b) => {
+                let (a_cases, b_cases) = (self.term(a)?, self.term(b)?);
+                let mut cases = Vec::new();
+                for (a_guard, a) in &a_cases {
+                    for (b_guard, b) in &b_cases {
+                        let guard = a_guard.clone().and(b_guard.clone());
+                        let result = match e {
+                            Expr::NatAdd(_, _) => a.add(b),
+                            _ => a.sub(b),
+                        };
+                        cases.push((guard.clone().and(fits(&result)), result.clone()));
+                        cases.push((guard.and(fits(&result).not()), zero.clone()));
+                    }
+                }
+                cases
+            }
+            Expr::IntNeg(a) => {
+                let mut cases = Vec::new();
+                for (guard, a) in self.term(a)? {
+                    let overflows = Formula::Eq(a.sub(&lo));
+                    cases.push((guard.clone().and(overflows.clone().not()), a.scale(-1)));
+                    cases.push((guard.and(overflows), zero.clone()));
+                }
+                cases
+            }
+            _ => return Err(VerifyError::Unsupported(e.to_string())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{verify, Goal, Outcome};
+    use crate::error::VerifyError;
+    use crate::parser::parse;
+
+    fn outcomes(source: &str) -> Result<Vec<(Goal, Outcome)>, VerifyError> {
+        let program = parse(source).unwrap();
+        let obligations = verify(&program, &HashMap::new())?;
+        Ok(obligations
+            .into_iter()
+            .map(|o| (o.goal, o.outcome))
+            .collect())
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let proved =
+            outcomes("read x\nrequires x < 10\nlet y = x + 1\nensures y < 11\nensures 1 < y")
+                .unwrap();
+        assert_eq!(proved, [(Goal::Program, Outcome::Proved)]);
+        // `x + 1` is 0 when `x` is the largest nat
+        let refuted = outcomes("read x\nlet y = x + 1\nensures 1 < y").unwrap();
+        assert_eq!(refuted, [(Goal::Program, Outcome::Refuted)]);
+        let branches = outcomes(
+            "read x\nif x < 5 then\nlet y = 5 - x\nelse\nlet y = x - 5\nfi\nensures y < x",
+        )
+        .unwrap();
+        assert_eq!(branches, [(Goal::Program, Outcome::Refuted)]);
+        let saturating = outcomes(
+            "read x\nrequires x < 5\nlet y = 5 - x\nlet z = x - 5\n\
+            ensures y + x == 5\nensures z == 0",
+        )
+        .unwrap();
+        assert_eq!(saturating, [(Goal::Program, Outcome::Proved)]);
+        let conjunction =
+            outcomes("read x\nrequires 1 < x & x < 10\nlet y = x - 1\nensures y < 9 & 0 < y")
+                .unwrap();
+        assert_eq!(conjunction, [(Goal::Program, Outcome::Proved)]);
+    }
+
+    #[test]
+    fn test_loops() {
+        let program = "let i = 0\nlet s = 0\nwhile i < 4 invariant i < 5 invariant s == i do\n\
+            let i = i + 1\nlet s = s + 1\nend\nensures s == 5";
+        let proved = outcomes(program).unwrap();
+        assert!(
+            proved
+                .iter()
+                .all(|(_, outcome)| *outcome == Outcome::Proved),
+            "{:?}",
+            proved
+        );
+        assert_eq!(proved.len(), 3);
+        // The same invariants, written as one
+        let program = "let i = 0\nlet s = 0\nwhile i < 4 invariant i < 5 & s == i do\n\
+            let i = i + 1\nlet s = s + 1\nend\nensures s == 5";
+        let proved = outcomes(program).unwrap();
+        assert!(
+            proved
+                .iter()
+                .all(|(_, outcome)| *outcome == Outcome::Proved),
+            "{:?}",
+            proved
+        );
+        // The invariant holds when the loop starts and is enough after it, but not after the
+        // last iteration
+        let program =
+            "let i = 0\nwhile i < 4 invariant i < 4 do\nlet i = i + 1\nend\nensures 4 < i";
+        let preserved: Vec<_> = outcomes(program)
+            .unwrap()
+            .into_iter()
+            .map(|(_, outcome)| outcome)
+            .collect();
+        assert_eq!(
+            preserved,
+            [Outcome::Proved, Outcome::Refuted, Outcome::Proved]
+        );
+    }
+
+    #[test]
+    fn test_cut_off() {
+        let exit = |program| {
+            let outcomes = outcomes(program).unwrap();
+            match outcomes.last().unwrap() {
+                (Goal::Exit { .. }, outcome) => *outcome,
+                other => panic!("{:?}", other),
+            }
+        };
+        // The evaluator leaves the loop with `i == 6`
+        assert_eq!(
+            exit("let i = 0\nwhile true do\nlet i = i + 1\nend\nensures i == 100"),
+            Outcome::Refuted
+        );
+        // Stops after 6 iterations, by itself
+        assert_eq!(
+            exit("let i = 0\nwhile i < 5 invariant i < 6 do\nlet i = i + 1\nend\nensures i == 6"),
+            Outcome::Proved
+        );
+        // Would stop after 10 iterations, but is cut off after 6
+        assert_eq!(
+            exit("let i = 0\nwhile i < 9 invariant i < 10 do\nlet i = i + 1\nend\nensures i == 10"),
+            Outcome::Refuted
+        );
+        // Nothing is known about `n`
+        assert_eq!(
+            exit("read n\nlet i = 0\nwhile i < n do\nlet i = i + 1\nend\nensures n < i"),
+            Outcome::Refuted
+        );
+    }
+
+    #[test]
+    fn test_heap() {
+        let proved = outcomes("let p <- 1\nq = p\nq <- 5\nensures *p == 5").unwrap();
+        assert_eq!(proved, [(Goal::Program, Outcome::Proved)]);
+        // `r` might be `p` or `q`, so `*q` might still be 2
+        let refuted = outcomes(
+            "let p <- 1\nlet q <- 2\nread x\nif x < 1 then\nr = p\nelse\n\
+            r = q\nfi\nr <- 3\nensures *q == 3",
+        )
+        .unwrap();
+        assert_eq!(refuted, [(Goal::Program, Outcome::Refuted)]);
+        let proved = outcomes(
+            "let p <- 1\nlet q <- 2\nread x\nif x < 1 then\nr = p\nelse\n\
+            r = q\nfi\nr <- 3\nlet s = *p + *q\nensures s < 5",
+        )
+        .unwrap();
+        assert_eq!(proved, [(Goal::Program, Outcome::Proved)]);
+        assert!(matches!(
+            outcomes("let x = \"a\"\nensures len x == 1"),
+            Err(VerifyError::Unsupported(_))
+        ));
+    }
+}
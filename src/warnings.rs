@@ -0,0 +1,465 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::evaluator::{self, Value};
+use crate::syntax::{Expr, Ident, Pattern, Statement};
+use crate::typechecker::Type;
+use crate::typed::{
+    Binding, BindingKind, ExprKind, StmntKind, TypedExpr, TypedPattern, TypedStatement,
+};
+
+/// Something suspicious in a program that typechecks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A variable that's bound but never read, with the statement or pattern that binds it
+    Unused(Ident, String),
+    /// A `let` of a variable that's already bound, which updates it rather than binding a new one
+    Rebound(Ident),
+    /// A variable bound inside one of the branches of an `if`, `choose` or `match`, which isn't
+    /// bound after it since the other branches don't bind it with the same type. The variables a
+    /// `match` pattern binds aren't reported, since they're meant to be read in their case.
+    BranchLocal(Ident),
+    /// The condition of an `if` or `while` that's always true or always false
+    ConstantCondition(&'static str, bool),
+    /// Statements after a `while true` loop, which are only reached because the evaluator stops
+    /// every loop after `evaluator::MAX_ITERATIONS` iterations
+    Unreachable,
+}
+
+/// The kinds of warnings, which can be allowed one by one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    Unused,
+    Rebound,
+    BranchLocal,
+    ConstantCondition,
+    Unreachable,
+}
+
+impl Warning {
+    pub fn kind(&self) -> WarningKind {
+        match self {
+            Warning::Unused(_, _) => WarningKind::Unused,
+            Warning::Rebound(_) => WarningKind::Rebound,
+            Warning::BranchLocal(_) => WarningKind::BranchLocal,
+            Warning::ConstantCondition(_, _) => WarningKind::ConstantCondition,
+            Warning::Unreachable => WarningKind::Unreachable,
+        }
+    }
+}
+
+// Kinds are named like in `--allow unused`
+impl FromStr for WarningKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unused" => Ok(WarningKind::Unused),
+            "rebound" => Ok(WarningKind::Rebound),
+            "branch-local" => Ok(WarningKind::BranchLocal),
+            "constant-condition" => Ok(WarningKind::ConstantCondition),
+            "unreachable" => Ok(WarningKind::Unreachable),
+            _ => Err(format!("Unknown warning '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Unused(id, site) => write!(f, "'{}' is bound by `{}` but never read", id, site),
+            Warning::Rebound(id) => write!(f, "'{}' is already bound, so `let` updates it", id),
+            Warning::BranchLocal(id) => write!(
+                f,
+                "'{}' is only bound in some branches, or with different types, so it's unbound after them",
+                id
+            ),
+            Warning::ConstantCondition(stmnt, value) => {
+                write!(f, "The condition of this `{}` is always {}", stmnt, value)
+            }
+            Warning::Unreachable => write!(
+                f,
+                "Code after a `while true` loop only runs because the evaluator stops the loop after {} iterations",
+                evaluator::MAX_ITERATIONS
+            ),
+        }
+    }
+}
+
+/// The warnings for `program`, which typechecked starting from `sigma`, except for the `allowed`
+/// kinds. The globals a module exports count as read, since that's what it's imported for.
+pub fn check(
+    program: &TypedStatement,
+    sigma: &HashMap<Ident, Type>,
+    allowed: &HashSet<WarningKind>,
+) -> Vec<Warning> {
+    let mut warnings = Warnings {
+        env: sigma
+            .iter()
+            .map(|(id, ty)| (id.clone(), (ty.clone(), Vec::new())))
+            .collect(),
+        ..Warnings::default()
+    };
+    warnings.stmnt(program);
+    // The same statement in two branches is reported once
+    let mut reported = HashSet::new();
+    let unused = warnings
+        .bindings
+        .iter()
+        .filter(|(id, site, read)| !read && reported.insert((id, site)))
+        .map(|(id, site, _)| Warning::Unused(id.clone(), site.clone()));
+    let mut all = std::mem::take(&mut warnings.warnings);
+    all.extend(unused);
+    all.retain(|w| !allowed.contains(&w.kind()));
+    all
+}
+
+// Every bound variable, with its type and the bindings its value could come from, as indexes
+// into `Warnings::bindings`
+type Env = HashMap<Ident, (Type, Vec<usize>)>;
+
+#[derive(Default)]
+struct Warnings {
+    env: Env,
+    // Every variable bound so far, with the statement or pattern that binds it, and whether it
+    // has been read
+    bindings: Vec<(Ident, String, bool)>,
+    warnings: Vec<Warning>,
+    // The ids of the modules already checked, since a module only runs once
+    modules: HashSet<String>,
+}
+
+impl Warnings {
+    fn stmnt(&mut self, stmnt: &TypedStatement) {
+        let site = || Statement::from(stmnt).to_string();
+        match &stmnt.kind {
+            StmntKind::StoreAssign(b, e) | StmntKind::HeapNew(b, e) => {
+                self.expr(e);
+                self.bind_let(b, site());
+            }
+            StmntKind::Destructure(bindings, e) => {
+                self.expr(e);
+                let site = site();
+                bindings.iter().for_each(|b| self.bind_let(b, site.clone()));
+            }
+            StmntKind::HeapUpdate(id, e) => {
+                self.expr(e);
+                self.read(id);
+            }
+            StmntKind::HeapAlias(b, id) | StmntKind::Borrow(b, id, _) => {
+                self.read(id);
+                self.bind(b, site());
+            }
+            StmntKind::AddressOf(p, x) => {
+                self.read(&x.name);
+                self.bind(x, site());
+                self.bind(p, site());
+            }
+            StmntKind::StoreInput(b) | StmntKind::HeapInput(b) => self.bind(b, site()),
+            StmntKind::Print(e)
+            | StmntKind::Assume(e)
+            | StmntKind::Contract(_, e)
+            | StmntKind::Const(_, e) => self.expr(e),
+            StmntKind::Assert(cond, message) => {
+                self.expr(cond);
+                message.iter().for_each(|m| self.expr(m));
+            }
+            // The statements after one that only ends when the evaluator cuts it off are reported
+            // once
+            StmntKind::Sequence(_, _) => {
+                let mut stmnts = Vec::new();
+                flatten(stmnt, &mut stmnts);
+                let mut reported = false;
+                for (i, s) in stmnts.iter().enumerate() {
+                    self.stmnt(s);
+                    let rest = &stmnts[i + 1..];
+                    if !reported && diverges(s) && rest.iter().any(|s| s.kind != StmntKind::Skip) {
+                        self.warnings.push(Warning::Unreachable);
+                        reported = true;
+                    }
+                }
+            }
+            StmntKind::Conditional(cond, then_s, else_s) => {
+                self.condition("if", cond);
+                self.expr(cond);
+                let branches = vec![self.block(then_s), self.block(else_s)];
+                self.join(branches, &HashSet::new());
+            }
+            StmntKind::Choose(s1, s2) => {
+                let branches = vec![self.block(s1), self.block(s2)];
+                self.join(branches, &HashSet::new());
+            }
+            StmntKind::Match(e, cases) => {
+                self.expr(e);
+                let mut binders = HashSet::new();
+                let branches = cases
+                    .iter()
+                    .map(|(pattern, body)| {
+                        let outer = self.env.clone();
+                        if let TypedPattern::Variant(_, _, bindings) = pattern {
+                            let site = format!("case {}", Pattern::from(pattern));
+                            for b in bindings.iter().flatten() {
+                                self.bind(b, site.clone());
+                                binders.insert(b.name.clone());
+                            }
+                        }
+                        self.stmnt(body);
+                        std::mem::replace(&mut self.env, outer)
+                    })
+                    .collect();
+                self.join(branches, &binders);
+            }
+            // Everything either branch binds is still bound afterwards
+            StmntKind::Parallel(s1, s2) => {
+                let mut env = self.block(s1);
+                for (id, (ty, sources)) in self.block(s2) {
+                    env.entry(id).or_insert((ty, Vec::new())).1.extend(sources);
+                }
+                self.env = env;
+            }
+            StmntKind::While(cond, body) => {
+                self.condition("while", cond);
+                self.expr(cond);
+                self.block(body);
+            }
+            StmntKind::Scope(body) => {
+                self.block(body);
+            }
+            StmntKind::Labeled(_, stmnt) => self.stmnt(stmnt),
+            StmntKind::Module(_, id, body) => {
+                if self.modules.insert(id.clone()) {
+                    let outer = std::mem::take(&mut self.env);
+                    self.stmnt(body);
+                    let exports = std::mem::replace(&mut self.env, outer);
+                    exports
+                        .values()
+                        .for_each(|(_, sources)| self.mark_read(sources.clone()));
+                }
+            }
+            StmntKind::Havoc(_) | StmntKind::Enum(_, _) | StmntKind::Skip => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &TypedExpr) {
+        match &expr.kind {
+            ExprKind::StoreRead(x) | ExprKind::HeapRead(x) => self.read(x),
+            ExprKind::Constant(_) => {}
+            ExprKind::NatAdd(a, b)
+            | ExprKind::NatSub(a, b)
+            | ExprKind::NatLeq(a, b)
+            | ExprKind::BoolAnd(a, b)
+            | ExprKind::StrConcat(a, b)
+            | ExprKind::Equal(a, b) => {
+                self.expr(a);
+                self.expr(b);
+            }
+            ExprKind::IntNeg(a)
+            | ExprKind::ToInt(a)
+            | ExprKind::ToNat(a)
+            | ExprKind::BoolNot(a)
+            | ExprKind::StrLen(a) => self.expr(a),
+            ExprKind::Tuple(es) | ExprKind::Construct(_, _, es) => {
+                es.iter().for_each(|e| self.expr(e))
+            }
+        }
+    }
+
+    fn condition(&mut self, stmnt: &'static str, cond: &TypedExpr) {
+        if let Some(value) = constant(cond) {
+            self.warnings.push(Warning::ConstantCondition(stmnt, value));
+        }
+    }
+
+    fn read(&mut self, id: &Ident) {
+        if let Some((_, sources)) = self.env.get(id) {
+            self.mark_read(sources.clone());
+        }
+    }
+
+    fn mark_read(&mut self, sources: Vec<usize>) {
+        sources.into_iter().for_each(|i| self.bindings[i].2 = true);
+    }
+
+    fn bind_let(&mut self, binding: &Binding, site: String) {
+        if binding.kind == BindingKind::Rebound {
+            self.warnings.push(Warning::Rebound(binding.name.clone()));
+        }
+        self.bind(binding, site);
+    }
+
+    // Updating a variable keeps the bindings it came from, since the old value may still be read
+    fn bind(&mut self, binding: &Binding, site: String) {
+        let sources = match binding.kind {
+            BindingKind::Fresh => {
+                self.bindings.push((binding.name.clone(), site, false));
+                vec![self.bindings.len() - 1]
+            }
+            BindingKind::Rebound => self
+                .env
+                .get(&binding.name)
+                .map_or(Vec::new(), |(_, s)| s.clone()),
+        };
+        self.env
+            .insert(binding.name.clone(), (binding.ty.clone(), sources));
+    }
+
+    // Checks a block in its own copy of the environment, returning the environment after it
+    fn block(&mut self, stmnt: &TypedStatement) -> Env {
+        let outer = self.env.clone();
+        self.stmnt(stmnt);
+        std::mem::replace(&mut self.env, outer)
+    }
+
+    // Like the typechecker, only the variables bound with the same type by every branch are kept.
+    // The `binders` of `match` patterns are dropped without a warning.
+    fn join(&mut self, branches: Vec<Env>, binders: &HashSet<Ident>) {
+        let mut joined: Option<Env> = None;
+        for branch in &branches {
+            joined = Some(match joined {
+                None => branch.clone(),
+                Some(joined) => joined
+                    .into_iter()
+                    .filter_map(|(id, (ty, mut sources))| match branch.get(&id) {
+                        Some((t, s)) if *t == ty => {
+                            sources.extend(s);
+                            Some((id, (ty, sources)))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+            });
+        }
+        let joined = joined.unwrap_or_default();
+        let dropped: BTreeSet<&Ident> = branches
+            .iter()
+            .flat_map(|branch| branch.keys())
+            .filter(|id| !joined.contains_key(*id) && !binders.contains(*id))
+            .collect();
+        self.warnings.extend(
+            dropped
+                .into_iter()
+                .map(|id| Warning::BranchLocal(id.clone())),
+        );
+        self.env = joined;
+    }
+}
+
+fn flatten<'a>(stmnt: &'a TypedStatement, stmnts: &mut Vec<&'a TypedStatement>) {
+    match &stmnt.kind {
+        StmntKind::Sequence(s1, s2) => {
+            flatten(s1, stmnts);
+            flatten(s2, stmnts);
+        }
+        _ => stmnts.push(stmnt),
+    }
+}
+
+// The value of a condition that doesn't read any variables
+fn constant(cond: &TypedExpr) -> Option<bool> {
+    match evaluator::eval_expr(&Expr::from(cond), &HashMap::new(), &Vec::new()) {
+        Ok(Value::Bool(value)) => Some(value),
+        _ => None,
+    }
+}
+
+// Whether the statement certainly runs a `while true` loop, which only ends when the evaluator
+// stops it
+fn diverges(stmnt: &TypedStatement) -> bool {
+    match &stmnt.kind {
+        StmntKind::While(cond, _) => constant(cond) == Some(true),
+        StmntKind::Sequence(s1, s2) | StmntKind::Parallel(s1, s2) => diverges(s1) || diverges(s2),
+        StmntKind::Conditional(_, s1, s2) | StmntKind::Choose(s1, s2) => {
+            diverges(s1) && diverges(s2)
+        }
+        StmntKind::Match(_, cases) => !cases.is_empty() && cases.iter().all(|(_, s)| diverges(s)),
+        StmntKind::Scope(s) | StmntKind::Labeled(_, s) => diverges(s),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{Warning, WarningKind};
+    use crate::parser::parse;
+    use crate::typechecker::typecheck;
+
+    fn check(source: &str, allowed: &[WarningKind]) -> Vec<Warning> {
+        let typed = typecheck(&parse(source).unwrap()).unwrap();
+        super::check(
+            &typed,
+            &HashMap::new(),
+            &allowed.iter().copied().collect::<HashSet<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_bindings() {
+        let warnings = check("let x = 1\nlet y = x\nlet x = 2\nprint x", &[]);
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::Rebound("x".into()),
+                Warning::Unused("y".into(), "let y = x".into())
+            ]
+        );
+        // Only the kinds that aren't allowed are reported
+        assert_eq!(
+            check("let x = 1\nlet x = 2", &[WarningKind::Rebound]),
+            vec![Warning::Unused("x".into(), "let x = 1".into())]
+        );
+        // The same binding in both branches is reported once
+        assert_eq!(
+            check(
+                "choose
+let x = 1
+or
+let x = 1
+end",
+                &[]
+            ),
+            vec![Warning::Unused("x".into(), "let x = 1".into())]
+        );
+
+        // `a` has a different type in each branch, and `b` is only bound in one of them
+        let source =
+            "let c = 1\nif c < 2 then\nlet a = 1\nlet b = a\nelse\nlet a = \"a\"\nprint a\nfi";
+        let warnings = check(source, &[WarningKind::Unused]);
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::BranchLocal("a".into()),
+                Warning::BranchLocal("b".into())
+            ]
+        );
+
+        // The variables a pattern binds are only meant to be read in their case
+        let source = "enum O = N | S(nat, nat)\nlet o = O::S(1, 2)\n\
+            match o\ncase O::S(n, m) then\nprint n\ncase O::N then\nskip\nend";
+        assert_eq!(
+            check(source, &[]),
+            vec![Warning::Unused("m".into(), "case O::S(n, m)".into())]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let warnings = check("let x = 1\nif true then\nprint x\nelse\nskip\nfi", &[]);
+        assert_eq!(warnings, vec![Warning::ConstantCondition("if", true)]);
+        let warnings = check("while true do\nskip\nend\nprint 1\nprint 2", &[]);
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::ConstantCondition("while", true),
+                Warning::Unreachable
+            ]
+        );
+        assert!(check(
+            "let x = 1\nwhile x < 3 do\nlet x = x + 1\nend\nprint x",
+            &[WarningKind::Rebound]
+        )
+        .is_empty());
+    }
+}